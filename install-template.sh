@@ -171,15 +171,18 @@ valopt() {
     then
         local uop=$(echo $op | tr 'a-z-' 'A-Z_')
         local v="CFG_${uop}"
-        eval $v="$default"
-        for arg in $CFG_ARGS
+        eval "$v=\$default"
+        while IFS= read -r arg
         do
-            if echo "$arg" | grep -q -- "--$op="
-            then
-                local val=$(echo "$arg" | cut -f2 -d=)
-                eval $v=$val
-            fi
-        done
+            case "$arg" in
+                "--$op="*)
+                    local val="${arg#*=}"
+                    eval "$v=\$val"
+                    ;;
+            esac
+        done <<EOF
+$CFG_ARGS
+EOF
         putvar $v
     else
         if [ -z "$default" ]
@@ -211,7 +214,7 @@ opt() {
 
     if [ $HELP -eq 0 ]
     then
-        for arg in $CFG_ARGS
+        while IFS= read -r arg
         do
             if [ "$arg" = "--${flag}-${op}" ]
             then
@@ -221,7 +224,9 @@ opt() {
                 eval $v=1
                 putvar $v
             fi
-        done
+        done <<EOF
+$CFG_ARGS
+EOF
     else
         if [ ! -z "${META-}" ]
         then
@@ -240,7 +245,7 @@ flag() {
 
     if [ $HELP -eq 0 ]
     then
-        for arg in $CFG_ARGS
+        while IFS= read -r arg
         do
             if [ "$arg" = "--${op}" ]
             then
@@ -249,7 +254,9 @@ flag() {
                 eval $v=1
                 putvar $v
             fi
-        done
+        done <<EOF
+$CFG_ARGS
+EOF
     else
         if [ ! -z "${META-}" ]
         then
@@ -260,30 +267,35 @@ flag() {
 }
 
 validate_opt () {
-    for arg in $CFG_ARGS
+    while IFS= read -r arg
     do
+        if [ -z "$arg" ]
+        then
+            continue
+        fi
         local is_arg_valid=0
         for option in $BOOL_OPTIONS
         do
-            if test --disable-$option = $arg
+            if test "--disable-$option" = "$arg"
             then
                 is_arg_valid=1
             fi
-            if test --enable-$option = $arg
+            if test "--enable-$option" = "$arg"
             then
                 is_arg_valid=1
             fi
-            if test --$option = $arg
+            if test "--$option" = "$arg"
             then
                 is_arg_valid=1
             fi
         done
         for option in $VAL_OPTIONS
         do
-            if echo "$arg" | grep -q -- "--$option="
-            then
-                is_arg_valid=1
-            fi
+            case "$arg" in
+                "--$option="*)
+                    is_arg_valid=1
+                    ;;
+            esac
         done
         if [ "$arg" = "--help" ]
         then
@@ -297,19 +309,59 @@ validate_opt () {
                 err "Option '$arg' is not recognized"
             fi
         fi
-    done
+    done <<EOF
+$CFG_ARGS
+EOF
 }
 
 absolutify() {
     local file_path="$1"
-    local file_path_dirname="$(dirname "$file_path")"
-    local file_path_basename="$(basename "$file_path")"
-    local file_abs_path="$(abs_path "$file_path_dirname")"
+    local file_path_dirname=.
+    local file_path_basename="$file_path"
+    case "$file_path" in
+	*/*)
+	    file_path_dirname="${file_path%/*}"
+	    file_path_basename="${file_path##*/}"
+	    ;;
+    esac
+    # The trailing 'x' keeps newlines at the end of the directory name
+    local file_abs_path="$(abs_path "${file_path_dirname:-/}" && printf x)"
+    file_abs_path="${file_abs_path%?x}"
     local file_path="$file_abs_path/$file_path_basename"
     # This is the return value
     RETVAL="$file_path"
 }
 
+# Decodes a path from a manifest, where the generator wrote backslashes,
+# newlines and other unusual bytes as '\\', '\n' and '\0ooo' escapes
+decode_path() {
+    case "$1" in
+	*\\*)
+	    # The trailing 'x' keeps newlines at the end of the name
+	    RETVAL="$(printf '%bx' "$1")"
+	    RETVAL="${RETVAL%x}"
+	    ;;
+	*)
+	    RETVAL="$1"
+	    ;;
+    esac
+}
+
+# Encodes a path for an installed manifest, reversed by decode_path
+encode_path() {
+    local _newline='
+'
+    case "$1" in
+	*\\*|*"$_newline"*)
+	    RETVAL="$(printf '%sx' "$1" | sed 's/\\/\\\\/g' | awk 'NR > 1 { printf "\\n" } { printf "%s", $0 }')"
+	    RETVAL="${RETVAL%x}"
+	    ;;
+	*)
+	    RETVAL="$1"
+	    ;;
+    esac
+}
+
 # Prints the absolute path of a directory to stdout
 abs_path() {
     local path="$1"
@@ -337,7 +389,7 @@ uninstall_legacy() {
 
 	    # iterate through installed manifest and remove files
 	    local _p;
-	    while read _p; do
+	    while IFS= read -r _p; do
 		# the installed manifest contains absolute paths
 		msg "removing legacy file $_p"
 		if [ -f "$_p" ]
@@ -432,6 +484,12 @@ uninstall_components() {
 		if [ "$_available_component" = "$_installed_component" ]; then
 		    msg "uninstalling component '$_available_component'"
 		    local _component_manifest="$_md/manifest-$_installed_component"
+		    # Files an upgrade keeps, see plan_upgrades. An uninstall removes
+		    # them all, whatever an interrupted upgrade left in the list.
+		    local _keep="$_md/keep-$_installed_component"
+		    if [ -n "${CFG_UNINSTALL-}" ]; then
+			run rm -f "$_keep"
+		    fi
 
 		    # Sanity check: there should be a component manifest
 		    if [ ! -f "$_component_manifest" ]; then
@@ -440,10 +498,11 @@ uninstall_components() {
 
 		    # Iterate through installed component manifest and remove files
 		    local _directive
-		    while read _directive; do
+		    while IFS= read -r _directive; do
 
-			local _command=`echo $_directive | cut -f1 -d:`
-			local _file=`echo $_directive | cut -f2 -d:`
+			local _command="${_directive%%:*}"
+			decode_path "${_directive#*:}"
+			local _file="$RETVAL"
 
 			# Sanity checks
 			if [ ! -n "$_command" ]; then critical_err "malformed installation directive"; fi
@@ -451,6 +510,9 @@ uninstall_components() {
 
 			case "$_command" in
 			    file)
+				if is_kept "$_keep" "$_file"; then
+				    continue
+				fi
 				verbose_msg "removing file $_file"
 				if [ -f "$_file" ]; then
 				    run rm -f "$_file"
@@ -461,9 +523,14 @@ uninstall_components() {
 				;;
 
 			    dir)
-				verbose_msg "removing directory $_file"
-				run rm -r "$_file"
-				want_ok "unable to remove directory $_file"
+				if has_kept_below "$_keep" "$_file" && ! has_multiline_names "$_file"; then
+				    verbose_msg "removing changed files from directory $_file"
+				    remove_changed_files "$_file" "$_keep"
+				else
+				    verbose_msg "removing directory $_file"
+				    run rm -r "$_file"
+				    want_ok "unable to remove directory $_file"
+				fi
 				;;
 
 			    *)
@@ -478,6 +545,8 @@ uninstall_components() {
 		    run rm "$_component_manifest"
 		    # This is a hard error because the installation is unrecoverable
 		    critical_need_ok "failed to remove installed manifest for component '$_installed_component'"
+		    run rm -f "$_md/hashes-$_installed_component"
+		    want_ok "failed to remove installed hashes for component '$_installed_component'"
 
 		    # Update the installed component list
 		    local _modified_components="$(sed "/^$_installed_component\$/d" "$_md/components")"
@@ -512,6 +581,357 @@ uninstall_components() {
     fi
 }
 
+# Finds the files of the components being upgraded that can stay as they are,
+# so that only what changed gets removed and copied again
+plan_upgrades() {
+    local _src_dir="$1"
+    local _abs_libdir="$2"
+    local _dest_prefix="$3"
+    local _components="$4"
+
+    local _md="$_abs_libdir/$TEMPLATE_REL_MANIFEST_DIR"
+    local _component
+    for _component in $_components; do
+	local _keep="$_md/keep-$_component"
+	rm -f "$_keep"
+	if [ -f "$_md/manifest-$_component" -a -f "$_md/hashes-$_component" -a -f "$_src_dir/$_component/hashes.in" ]; then
+	    plan_upgrade "$_src_dir" "$_dest_prefix" "$_component" "$_md/hashes-$_component" "$_keep"
+	fi
+    done
+}
+
+# Writes the absolute paths of the installed files of a component that keep
+# their destination and contents in the new version, one per line, judging by
+# the hashes recorded by the old installation, the new hashes.in, and the
+# files on disk, which may have changed since. Unusual names are left out,
+# those files are just copied again.
+plan_upgrade() {
+    local _src_dir="$1"
+    local _dest_prefix="$2"
+    local _component="$3"
+    local _old_hashes="$4"
+    local _keep="$5"
+
+    local _sha256
+    if command -v sha256sum > /dev/null 2>&1; then
+	_sha256="sha256sum"
+    elif command -v shasum > /dev/null 2>&1; then
+	_sha256="shasum -a 256"
+    else
+	verbose_msg "sha256sum not found, copying all files of '$_component' again"
+	return 0
+    fi
+
+    # The destination of each directive of manifest.in, empty if its
+    # directory doesn't exist, so that there's nothing to keep
+    local _destinations="$_keep.destinations"
+    local _directive
+    while IFS= read -r _directive; do
+	decode_path "${_directive#*:}"
+	install_path_of "$_dest_prefix" "$RETVAL"
+	if [ -d "${RETVAL%/*}/" ]; then
+	    absolutify "$RETVAL"
+	    encode_path "$RETVAL"
+	else
+	    RETVAL=""
+	fi
+	printf '%s\t%s\n' "${_directive#*:}" "$RETVAL"
+    done < "$_src_dir/$_component/manifest.in" > "$_destinations"
+
+    # Files with the same hash in both versions, as '<sha256>  <path>' lines
+    # like sha256sum prints
+    local _candidates="$_keep.candidates"
+    awk '
+	FILENAME == ARGV[1] {
+	    i = index($0, "\t")
+	    if (substr($0, i + 1) != "") {
+		destination[substr($0, 1, i - 1)] = substr($0, i + 1)
+	    }
+	    next
+	}
+	FILENAME == ARGV[2] {
+	    i = index($0, "\t")
+	    rest = substr($0, i + 1)
+	    old[substr(rest, index(rest, "\t") + 1)] = substr($0, 1, i - 1)
+	    next
+	}
+	{
+	    hash = substr($0, 1, index($0, " ") - 1)
+	    dir = substr($0, index($0, " ") + 1)
+	    rest = ""
+	    while (!(dir in destination) && match(dir, /\/[^\/]*$/)) {
+		rest = substr(dir, RSTART) rest
+		dir = substr(dir, 1, RSTART - 1)
+	    }
+	    if (dir in destination) {
+		path = destination[dir] rest
+		if (old[path] == hash && index(path, "\\") == 0) {
+		    print hash "  " path
+		}
+	    }
+	}
+    ' "$_destinations" "$_old_hashes" "$_src_dir/$_component/hashes.in" > "$_candidates"
+
+    # Keep the candidates that still have the contents they were installed with
+    sed 's/^[^ ]*  //' "$_candidates" | tr '\n' '\0' | xargs -0 $_sha256 2> /dev/null | awk '
+	FILENAME == ARGV[1] { current[$0]; next }
+	$0 in current { print substr($0, index($0, "  ") + 2) }
+    ' - "$_candidates" > "$_keep"
+    rm -f "$_destinations" "$_candidates"
+
+    local _kept="$(wc -l < "$_keep" | tr -d ' ')"
+    verbose_msg "keeping $_kept unchanged files of component '$_component'"
+}
+
+# Checks that the components a delta installer upgrades were installed from
+# the version it was made against, by comparing the hashes recorded in their
+# installed manifests with the base-hashes.in the delta carries
+verify_delta_base() {
+    local _src_dir="$1"
+    local _abs_libdir="$2"
+    local _components="$3"
+
+    local _md="$_abs_libdir/$TEMPLATE_REL_MANIFEST_DIR"
+    local _base="$(cat "$_src_dir/delta-base")"
+    local _component
+    for _component in $_components; do
+	local _delta="$_src_dir/$_component/delta.in"
+	if [ ! -f "$_delta" ]; then
+	    continue
+	fi
+	if [ ! -f "$_md/manifest-$_component" ]; then
+	    err "component '$_component' is not installed, and this installer only upgrades it from $_base. use the full installer"
+	fi
+	if [ ! -f "$_md/hashes-$_component" ]; then
+	    err "the installation of component '$_component' has no recorded hashes to check against $_base. use the full installer"
+	fi
+
+	local _tab="$(printf '\t')"
+	local _installed="$(awk -F "$_tab" '{ print $1 " " $2 }' "$_md/hashes-$_component" | LC_ALL=C sort)"
+	local _expected="$(LC_ALL=C sort "$_src_dir/$_component/base-hashes.in")"
+	if [ "$_installed" != "$_expected" ]; then
+	    err "the installed component '$_component' is not $_base, which this installer upgrades from. use the full installer"
+	fi
+
+	local _kept="$(grep -c '^keep:' "$_delta")"
+	msg "upgrading component '$_component' from $_base: $_kept files unchanged"
+    done
+}
+
+# Checks that the upgrade keeps every file a delta installer leaves out, as
+# those can only come from the installation. They may have been modified
+# since they were installed, or be installed elsewhere with other options.
+check_delta_kept() {
+    local _src_dir="$1"
+    local _abs_libdir="$2"
+    local _components="$3"
+
+    local _md="$_abs_libdir/$TEMPLATE_REL_MANIFEST_DIR"
+    local _tab="$(printf '\t')"
+    local _component
+    for _component in $_components; do
+	local _delta="$_src_dir/$_component/delta.in"
+	local _keep="$_md/keep-$_component"
+	if [ ! -f "$_delta" ]; then
+	    continue
+	fi
+
+	local _missing=""
+	if [ -f "$_keep" ]; then
+	    # Each 'keep:' directive names the image path, which the installed
+	    # hashes map to the installed path
+	    _missing="$(awk -F "$_tab" '
+		FILENAME == ARGV[1] { kept[$0]; next }
+		FILENAME == ARGV[2] { installed[$2] = $3; next }
+		/^keep:/ {
+		    path = substr($0, 6)
+		    if (!(path in installed) || !(installed[path] in kept)) {
+			print (path in installed) ? installed[path] : path
+			exit
+		    }
+		}
+	    ' "$_keep" "$_md/hashes-$_component" "$_delta")"
+	elif grep -q '^keep:' "$_delta"; then
+	    _missing="the files of '$_component' (sha256sum or shasum is needed to check them)"
+	fi
+
+	if [ -n "$_missing" ]; then
+	    local _c
+	    for _c in $_components; do
+		rm -f "$_md/keep-$_c"
+	    done
+	    err "unable to take $_missing from the installation, which this installer doesn't carry. repair the installation or use the full installer"
+	fi
+    done
+}
+
+# Returns whether a path is in the list of files an upgrade keeps
+is_kept() {
+    local _keep="$1"
+    local _path="$2"
+    local _newline='
+'
+    case "$_path" in
+	*\\*|*"$_newline"*)
+	    return 1
+	    ;;
+    esac
+    [ -f "$_keep" ] && grep -qxF -- "$_path" "$_keep"
+}
+
+# Returns whether an upgrade keeps any files below a directory
+has_kept_below() {
+    local _keep="$1"
+    local _dir="$2"
+    [ -f "$_keep" ] && DIR="$_dir/" awk '
+	index($0, ENVIRON["DIR"]) == 1 { found = 1; exit }
+	END { exit !found }
+    ' "$_keep"
+}
+
+# Returns whether a directory contains names with newlines, which the
+# line-based file lists of an upgrade can't carry
+has_multiline_names() {
+    local _newline='
+'
+    [ -n "$(find "$1" -name "*$_newline*" | head -n 1)" ]
+}
+
+# Removes the files below a bulk directory that an upgrade doesn't keep, and
+# the directories left empty
+remove_changed_files() {
+    local _dir="$1"
+    local _keep="$2"
+
+    find "$_dir" ! -type d | awk '
+	FILENAME == ARGV[1] { kept[$0]; next }
+	!($0 in kept)
+    ' "$_keep" - | while IFS= read -r _path; do
+	run rm -f "$_path"
+	want_ok "failed to remove $_path"
+    done
+    find "$_dir" -depth -type d -empty -exec rmdir {} \;
+}
+
+# Copies the files of a bulk directory of the image that an upgrade doesn't
+# keep into the installed directory
+copy_changed_files() {
+    local _src="$1"
+    local _dst="$2"
+    local _keep="$3"
+
+    find "$_src" ! -type d | SRC="$_src" DST="$_dst" awk '
+	FILENAME == ARGV[1] { kept[$0]; next }
+	!((ENVIRON["DST"] substr($0, length(ENVIRON["SRC"]) + 1)) in kept)
+    ' "$_keep" - | while IFS= read -r _path; do
+	local _target="$_dst${_path#"$_src"}"
+	make_dir_recursive "${_target%/*}"
+	critical_need_ok "directory creation failed"
+	run cp -P "$_path" "$_target"
+	critical_need_ok "failed to copy $_path"
+    done
+}
+
+# Makes the kept files below an installed bulk directory executable if they
+# are in the image, and not otherwise, as the permissions set after copying
+# only keep that part of their mode. Files the image doesn't have, which a
+# delta installer leaves out, keep their mode.
+fix_kept_modes() {
+    local _src="$1"
+    local _dst="$2"
+    local _keep="$3"
+
+    {
+	find "$_src" -type f \( -perm -u+x -o -perm -g+x -o -perm -o+x \) | sed 's/^/x /'
+	find "$_src" -type f ! \( -perm -u+x -o -perm -g+x -o -perm -o+x \) | sed 's/^/- /'
+	find "$_dst" -type f \( -perm -u+x -o -perm -g+x -o -perm -o+x \) | sed 's/^/i /'
+    } | SRC="$_src" DST="$_dst" awk '
+	FILENAME == ARGV[1] { kept[$0]; next }
+	{
+	    kind = substr($0, 1, 1)
+	    path = substr($0, 3)
+	    if (kind != "i") {
+		path = ENVIRON["DST"] substr(path, length(ENVIRON["SRC"]) + 1)
+	    }
+	    if (!(path in kept)) {
+		next
+	    }
+	    if (kind == "i") {
+		executable[path]
+	    } else {
+		image[path] = kind
+	    }
+	}
+	END {
+	    for (path in image) {
+		if (image[path] == "x" && !(path in executable)) {
+		    print "+x " path
+		} else if (image[path] == "-" && (path in executable)) {
+		    print "-x " path
+		}
+	    }
+	}
+    ' "$_keep" - | while IFS= read -r _change; do
+	run chmod "a${_change%% *}" "${_change#* }"
+	critical_need_ok "failed to set permissions on ${_change#* }"
+    done
+}
+
+# Returns the mode a file of the image at $1, with the image path $2, is
+# installed with
+file_mode_of() {
+    if [ "${2#bin/}" != "$2" ] || test -x "$1"; then
+	RETVAL=755
+    else
+	RETVAL=644
+    fi
+}
+
+# Decides where a path of a component image is installed
+install_path_of() {
+    local _dest_prefix="$1"
+    local _file="$2"
+
+    RETVAL="$_dest_prefix/$_file"
+
+    case "$_file" in
+	etc/*)
+	    RETVAL="$CFG_SYSCONFDIR/${_file#etc/}"
+	    ;;
+	bin/*)
+	    RETVAL="$CFG_BINDIR/${_file#bin/}"
+	    ;;
+	lib/*)
+	    RETVAL="$CFG_LIBDIR/${_file#lib/}"
+	    ;;
+	share/man/*)
+	    RETVAL="$CFG_MANDIR/${_file#share/man/}"
+	    ;;
+	share*)
+	    RETVAL="$CFG_DATADIR/${_file#share/}"
+	    ;;
+    esac
+
+    # HACK: Try to support overriding --docdir.  Paths with the form
+    # "share/doc/$product/" can be redirected to a single --docdir
+    # path. If the following detects that --docdir has been specified
+    # then it will replace everything preceeding the "$product" path
+    # component. The problem here is that the combined rust installer
+    # contains two "products": rust and cargo; so the contents of those
+    # directories will both be dumped into the same directory; and the
+    # contents of those directories are _not_ disjoint. Since this feature
+    # is almost entirely to support 'make install' anyway I don't expect
+    # this problem to be a big deal in practice.
+    if [ "$CFG_DOCDIR" != "<default>" ]
+    then
+	case "$_file" in
+	    share/doc/*/*)
+		RETVAL="$CFG_DOCDIR/${_file#share/doc/*/}"
+		;;
+	esac
+    fi
+}
+
 install_components() {
     local _src_dir="$1"
     local _abs_libdir="$2"
@@ -531,12 +951,22 @@ install_components() {
 	    critical_err "manifest for $_component does not exist at $_input_manifest"
 	fi
 
+	# Extended attributes recorded by the generator, if any
+	local _xattrs="$_src_dir/$_component/xattrs.in"
+	if [ -f "$_xattrs" ] && ! command -v setfattr > /dev/null 2>&1; then
+	    warn "setfattr not found, extended attributes of '$_component' will not be restored"
+	    _xattrs=""
+	fi
+
 	# The installed manifest directory
 	local _md="$_abs_libdir/$TEMPLATE_REL_MANIFEST_DIR"
 
 	# The file name of the manifest we're going to create during install
 	local _installed_manifest="$_md/manifest-$_component"
 
+	# Files an upgrade keeps, see plan_upgrades
+	local _keep="$_md/keep-$_component"
+
 	# Create the installed manifest, which we will fill in with absolute file paths
 	touch "$_installed_manifest"
 	critical_need_ok "failed to create installed manifest"
@@ -547,69 +977,24 @@ install_components() {
 
 	# Now install, iterate through the new manifest and copy files
 	local _directive
-	while read _directive; do
+	while IFS= read -r _directive; do
 
-	    local _command=`echo $_directive | cut -f1 -d:`
-	    local _file=`echo $_directive | cut -f2 -d:`
+	    # The path stays encoded for matching against xattrs.in
+	    local _command="${_directive%%:*}"
+	    local _encoded_file="${_directive#*:}"
+	    decode_path "$_encoded_file"
+	    local _file="$RETVAL"
 
 	    # Sanity checks
 	    if [ ! -n "$_command" ]; then critical_err "malformed installation directive"; fi
 	    if [ ! -n "$_file" ]; then critical_err "malformed installation directive"; fi
 
 	    # Decide the destination of the file
-	    local _file_install_path="$_dest_prefix/$_file"
-
-	    if echo "$_file" | grep "^etc/" > /dev/null
-	    then
-		local _f="$(echo "$_file" | sed 's/^etc\///')"
-		_file_install_path="$CFG_SYSCONFDIR/$_f"
-	    fi
-
-	    if echo "$_file" | grep "^bin/" > /dev/null
-	    then
-		local _f="$(echo "$_file" | sed 's/^bin\///')"
-		_file_install_path="$CFG_BINDIR/$_f"
-	    fi
-
-	    if echo "$_file" | grep "^lib/" > /dev/null
-	    then
-		local _f="$(echo "$_file" | sed 's/^lib\///')"
-		_file_install_path="$CFG_LIBDIR/$_f"
-	    fi
-
-	    if echo "$_file" | grep "^share" > /dev/null
-	    then
-		local _f="$(echo "$_file" | sed 's/^share\///')"
-		_file_install_path="$CFG_DATADIR/$_f"
-	    fi
-
-	    if echo "$_file" | grep "^share/man/" > /dev/null
-	    then
-		local _f="$(echo "$_file" | sed 's/^share\/man\///')"
-		_file_install_path="$CFG_MANDIR/$_f"
-	    fi
-
-            # HACK: Try to support overriding --docdir.  Paths with the form
-            # "share/doc/$product/" can be redirected to a single --docdir
-            # path. If the following detects that --docdir has been specified
-            # then it will replace everything preceeding the "$product" path
-            # component. The problem here is that the combined rust installer
-            # contains two "products": rust and cargo; so the contents of those
-            # directories will both be dumped into the same directory; and the
-            # contents of those directories are _not_ disjoint. Since this feature
-            # is almost entirely to support 'make install' anyway I don't expect
-            # this problem to be a big deal in practice.
-            if [ "$CFG_DOCDIR" != "<default>" ]
-            then
-	        if echo "$_file" | grep "^share/doc/" > /dev/null
-	        then
-		    local _f="$(echo "$_file" | sed 's/^share\/doc\/[^/]*\///')"
-		    _file_install_path="$CFG_DOCDIR/$_f"
-	        fi
-            fi
+	    install_path_of "$_dest_prefix" "$_file"
+	    local _file_install_path="$RETVAL"
 
 	    # Make sure there's a directory for it
-	    make_dir_recursive "$(dirname "$_file_install_path")"
+	    make_dir_recursive "${_file_install_path%/*}"
 	    critical_need_ok "directory creation failed"
 
 	    # Make the path absolute so we can uninstall it later without
@@ -621,41 +1006,64 @@ install_components() {
 	    case "$_command" in
 		file )
 
+		    if is_kept "$_keep" "$_file_install_path"; then
+			verbose_msg "keeping unchanged file $_file_install_path"
+			# The mode may still have changed. Delta installers don't
+			# carry the files they keep, which have the same mode.
+			if [ -f "$_src_dir/$_component/$_file" ]; then
+			    file_mode_of "$_src_dir/$_component/$_file" "$_file"
+			    run chmod "$RETVAL" "$_file_install_path"
+			    critical_need_ok "failed to set permissions on $_file_install_path"
+			fi
+			encode_path "$_file_install_path"
+			append_to_file "file:$RETVAL" "$_installed_manifest"
+			critical_need_ok "failed to update manifest"
+			continue
+		    fi
+
 		    verbose_msg "copying file $_file_install_path"
 
 		    maybe_backup_path "$_file_install_path"
 
-		    if echo "$_file" | grep "^bin/" > /dev/null || test -x "$_src_dir/$_component/$_file"
-		    then
-			run cp "$_src_dir/$_component/$_file" "$_file_install_path"
-			run chmod 755 "$_file_install_path"
-		    else
-			run cp "$_src_dir/$_component/$_file" "$_file_install_path"
-			run chmod 644 "$_file_install_path"
-		    fi
+		    file_mode_of "$_src_dir/$_component/$_file" "$_file"
+		    run cp "$_src_dir/$_component/$_file" "$_file_install_path"
+		    run chmod "$RETVAL" "$_file_install_path"
 		    critical_need_ok "file creation failed"
 
+		    restore_xattrs "$_xattrs" "$_encoded_file" "$_file_install_path"
+
 		    # Update the manifest
-		    append_to_file "file:$_file_install_path" "$_installed_manifest"
+		    encode_path "$_file_install_path"
+		    append_to_file "file:$RETVAL" "$_installed_manifest"
 		    critical_need_ok "failed to update manifest"
 
 		    ;;
 
 		dir )
 
-		    verbose_msg "copying directory $_file_install_path"
+		    if [ -d "$_file_install_path" ] && has_kept_below "$_keep" "$_file_install_path" \
+			&& ! has_multiline_names "$_src_dir/$_component/$_file"; then
+			verbose_msg "copying changed files of directory $_file_install_path"
+			copy_changed_files "$_src_dir/$_component/$_file" "$_file_install_path" "$_keep"
+			fix_kept_modes "$_src_dir/$_component/$_file" "$_file_install_path" "$_keep"
+		    else
+			verbose_msg "copying directory $_file_install_path"
 
-		    maybe_backup_path "$_file_install_path"
+			maybe_backup_path "$_file_install_path"
 
-		    run cp -R "$_src_dir/$_component/$_file" "$_file_install_path"
-		    critical_need_ok "failed to copy directory"
+			run cp -R "$_src_dir/$_component/$_file" "$_file_install_path"
+			critical_need_ok "failed to copy directory"
+		    fi
 
                     # Set permissions. 0755 for dirs, 644 for files
                     run chmod -R u+rwX,go+rX,go-w "$_file_install_path"
                     critical_need_ok "failed to set permissions on directory"
 
+		    restore_xattrs "$_xattrs" "$_encoded_file" "$_file_install_path"
+
 		    # Update the manifest
-		    append_to_file "dir:$_file_install_path" "$_installed_manifest"
+		    encode_path "$_file_install_path"
+		    append_to_file "dir:$RETVAL" "$_installed_manifest"
 		    critical_need_ok "failed to update manifest"
 		    ;;
 
@@ -665,6 +1073,80 @@ install_components() {
 	    esac
 	done < "$_input_manifest"
 
+	# Keep the hashes of the installed files, for checking the installation
+	local _hashes="$_src_dir/$_component/hashes.in"
+	if [ -f "$_hashes" ]; then
+	    record_hashes "$_input_manifest" "$_installed_manifest" "$_hashes" "$_md/hashes-$_component"
+	    critical_need_ok "failed to record hashes for $_component"
+	fi
+	run rm -f "$_keep"
+
+    done
+}
+
+# Writes the hashes of the installed files of a component, as lines of
+# '<sha256><tab><image path><tab><installed path>' with escaped paths. Each
+# directive of manifest.in got the installed path on the same line of the
+# installed manifest, which is also what files below bulk directories go by.
+record_hashes() {
+    local _input_manifest="$1"
+    local _installed_manifest="$2"
+    local _hashes="$3"
+    local _installed_hashes="$4"
+
+    awk '
+	FILENAME == ARGV[1] {
+	    image[FNR] = substr($0, index($0, ":") + 1)
+	    next
+	}
+	FILENAME == ARGV[2] {
+	    installed[image[FNR]] = substr($0, index($0, ":") + 1)
+	    next
+	}
+	{
+	    hash = substr($0, 1, index($0, " ") - 1)
+	    path = substr($0, index($0, " ") + 1)
+	    dir = path
+	    rest = ""
+	    while (!(dir in installed) && match(dir, /\/[^\/]*$/)) {
+		rest = substr(dir, RSTART) rest
+		dir = substr(dir, 1, RSTART - 1)
+	    }
+	    if (dir in installed) {
+		print hash "\t" path "\t" installed[dir] rest
+	    }
+	}
+    ' "$_input_manifest" "$_installed_manifest" "$_hashes" > "$_installed_hashes"
+    local _retval=$?
+    log_line "$ record hashes in \"$_installed_hashes\""
+    return $_retval
+}
+
+# Restores the extended attributes listed in xattrs.in for an installed file,
+# or for everything below an installed directory, given its path as encoded in
+# manifest.in. Attributes that can't be set, like capabilities without
+# privileges, only produce a warning.
+restore_xattrs() {
+    local _xattrs="$1"
+    local _file="$2"
+    local _install_path="$3"
+
+    if [ ! -f "$_xattrs" ]; then
+	return 0
+    fi
+
+    local _tab="$(printf '\t')"
+    XATTRS_FILE="$_file" awk -F "$_tab" '
+	BEGIN { f = ENVIRON["XATTRS_FILE"] }
+	$3 == f || index($3, f "/") == 1
+    ' "$_xattrs" |
+    while IFS="$_tab" read -r _name _value _path; do
+	decode_path "${_path#"$_file"}"
+	local _target="$_install_path$RETVAL"
+	verbose_msg "restoring extended attribute $_name on $_target"
+	if ! setfattr -h -n "$_name" -v "$_value" "$_target" 2> /dev/null; then
+	    warn "failed to restore extended attribute $_name on $_target"
+	fi
     done
 }
 
@@ -674,7 +1156,8 @@ maybe_configure_ld() {
     local _ostype="$(uname -s)"
     assert_nz "$_ostype"  "ostype"
 
-    if [ "$_ostype" = "Linux" -a ! -n "${CFG_DISABLE_LDCONFIG-}" ]; then
+    # User installs can't touch the system linker configuration
+    if [ "$_ostype" = "Linux" -a ! -n "${CFG_DISABLE_LDCONFIG-}" -a ! -n "${CFG_USER-}" ]; then
 
 	# Fedora-based systems do not configure the dynamic linker to look
 	# /usr/local/lib, which is our default installation directory. To
@@ -710,7 +1193,7 @@ maybe_unconfigure_ld() {
     local _ostype="$(uname -s)"
     assert_nz "$_ostype"  "ostype"
 
-    if [ "$_ostype" != "Linux" ]; then
+    if [ "$_ostype" != "Linux" -o -n "${CFG_USER-}" ]; then
 	return 0
     fi
 
@@ -729,6 +1212,141 @@ maybe_backup_path() {
     fi
 }
 
+# Prints the installed components whose manifests list a path, or a bulk
+# directory containing it
+print_owners() {
+    local _md="$1"
+    local _path="$2"
+
+    if [ ! -f "$_md/components" ]; then
+	err "unable to find installation manifest at $_md"
+    fi
+
+    case "$_path" in
+	/*) ;;
+	*) _path="$(pwd)/$_path" ;;
+    esac
+    if [ -d "${_path%/*}/" ]; then
+	absolutify "$_path"
+	_path="$RETVAL"
+    fi
+
+    local _found=false
+    local _component
+    while IFS= read -r _component; do
+	if [ -z "$_component" -o ! -f "$_md/manifest-$_component" ]; then
+	    continue
+	fi
+
+	local _directive
+	while IFS= read -r _directive; do
+	    decode_path "${_directive#*:}"
+	    local _owned=false
+	    case "${_directive%%:*}" in
+		file)
+		    if [ "$RETVAL" = "$_path" ]; then _owned=true; fi
+		    ;;
+		dir)
+		    case "$_path/" in
+			"$RETVAL"/*) _owned=true ;;
+		    esac
+		    ;;
+	    esac
+	    if [ "$_owned" = true ]; then
+		echo "$_component"
+		_found=true
+		break
+	    fi
+	done < "$_md/manifest-$_component"
+    done < "$_md/components"
+
+    if [ "$_found" = false ]; then
+	err "no installed component owns $_path"
+    fi
+}
+
+# Takes the advisory lock serializing installs into one prefix: the directory
+# '<manifest dir>.lock' next to the manifest directory, which survives the
+# manifest directory being deleted by an uninstall. mkdir is atomic, also on
+# NFS. The lock records its host and pid so that a lock left behind by a
+# killed installer on this host can be broken, and is released on exit.
+acquire_lock() {
+    local _lock="$1"
+    local _timeout="$2"
+    local _host="$(uname -n)"
+    local _waited=0
+
+    while ! mkdir "$_lock" 2> /dev/null; do
+	if [ ! -d "$_lock" ]; then
+	    err "failed to create lock $_lock"
+	fi
+
+	local _owner="$(cat "$_lock/owner" 2> /dev/null)"
+	if [ "${_owner% *}" = "$_host" ] && ! process_exists "${_owner##* }" \
+	    && break_stale_lock "$_lock" "$_owner"; then
+	    continue
+	fi
+
+	if [ "$_waited" -ge "$_timeout" ]; then
+	    err "another installation is in progress (lock $_lock held by ${_owner:-an unknown process})"
+	fi
+	if [ "$_waited" -eq 0 ]; then
+	    msg "waiting up to $_timeout seconds for another installation to finish"
+	fi
+	sleep 1
+	_waited=$((_waited + 1))
+    done
+
+    LOCK_DIR="$_lock"
+    trap release_lock EXIT
+    trap 'exit 1' HUP INT TERM
+    echo "$_host $$" > "$_lock/owner"
+}
+
+# Removes the lock $1 if the owner $2, found gone, still holds it, and
+# returns whether it could check. Installers break locks one at a time,
+# holding $1.break, so no other installer can take the lock between the
+# check and the removal.
+break_stale_lock() {
+    local _lock="$1"
+    local _owner="$2"
+
+    if ! mkdir "$_lock.break" 2> /dev/null; then
+	return 1
+    fi
+    if [ "$(cat "$_lock/owner" 2> /dev/null)" = "$_owner" ]; then
+	msg "removing stale lock $_lock left by process ${_owner##* }"
+	if ! rm -rf "$_lock"; then
+	    rmdir "$_lock.break"
+	    err "failed to remove stale lock $_lock"
+	fi
+    fi
+    rmdir "$_lock.break"
+}
+
+# Returns whether the process $1 is running, including processes of other
+# users, which kill can't signal
+process_exists() {
+    local _pid="$1"
+
+    if kill -0 "$_pid" 2> /dev/null || [ -d "/proc/$_pid" ]; then
+	return 0
+    fi
+    case "$(LC_ALL=C kill -0 "$_pid" 2>&1)" in
+	*"o such process"*)
+	    return 1
+	    ;;
+    esac
+    return 0
+}
+
+release_lock() {
+    if [ -n "${LOCK_DIR-}" ]; then
+	rm -rf "$LOCK_DIR"
+	LOCK_DIR=""
+    fi
+}
+
 install_uninstaller() {
     local _src_dir="$1"
     local _src_basename="$2"
@@ -748,12 +1366,12 @@ do_preflight_sanity_checks() {
     verbose_msg "verifying destination is writable"
     make_dir_recursive "$CFG_LIBDIR"
     need_ok "can't write to destination. consider \`sudo\`."
-    touch "$CFG_LIBDIR/rust-install-probe" > /dev/null
+    touch "$CFG_LIBDIR/rust-install-probe.$$" > /dev/null
     if [ $? -ne 0 ]
     then
 	err "can't write to destination. consider \`sudo\`."
     fi
-    rm "$CFG_LIBDIR/rust-install-probe"
+    rm "$CFG_LIBDIR/rust-install-probe.$$"
     need_ok "failed to remove install probe"
 
     # Sanity check: don't install to the directory containing the installer.
@@ -765,6 +1383,90 @@ do_preflight_sanity_checks() {
     fi
 }
 
+# Finds the free KiB and the mount point of the filesystem a directory, which
+# doesn't need to exist yet, will be on, as '<KiB> <mount point>'
+free_space_of() {
+    local _dir="$1"
+
+    while [ -n "$_dir" -a ! -d "$_dir" ]; do
+	case "$_dir" in
+	    */*) _dir="${_dir%/*}" ;;
+	    *) _dir="" ;;
+	esac
+    done
+
+    RETVAL="$(df -Pk "${_dir:-.}" 2> /dev/null | awk 'NR == 2 {
+	avail = $4
+	$1 = $2 = $3 = $4 = $5 = ""
+	sub(/^ +/, "")
+	print avail " " $0
+    }')"
+}
+
+# Fails if the components, according to the sizes recorded in their size.in,
+# don't fit into the free space of the filesystems they are installed to
+check_free_space() {
+    local _src_dir="$1"
+    local _dest_prefix="$2"
+    local _components="$3"
+
+    if ! command -v df > /dev/null 2>&1; then
+	warn "df not found, not checking for free disk space"
+	return 0
+    fi
+
+    # Lines of '<bytes> <free KiB> <mount point><tab><target dir>'
+    local _needed=""
+    local _component
+    for _component in $_components; do
+	local _sizes="$_src_dir/$_component/size.in"
+	# Installers from older generators don't record sizes
+	if [ ! -f "$_sizes" ]; then
+	    continue
+	fi
+
+	local _line
+	while IFS= read -r _line; do
+	    decode_path "${_line#* }"
+	    install_path_of "$_dest_prefix" "${RETVAL:+$RETVAL/}x"
+	    local _dir="${RETVAL%/x}"
+	    free_space_of "$_dir"
+	    if [ -z "$RETVAL" ]; then
+		warn "unable to find the free disk space for $_dir"
+		continue
+	    fi
+	    local _space="$RETVAL"
+	    # Keep the line in one piece, whatever the name of the directory
+	    encode_path "$_dir"
+	    _needed="$_needed${_line%% *} $_space	$RETVAL
+"
+	done < "$_sizes"
+    done
+
+    # Sum up the space needed on each filesystem
+    local _shortage="$(printf '%s' "$_needed" | awk '{
+	split($0, parts, "\t")
+	mount = parts[1]
+	sub(/^[^ ]* [^ ]* /, "", mount)
+	if (!(mount in needed)) {
+	    dirs[mount] = parts[2]
+	}
+	needed[mount] += $1
+	free[mount] = $2
+    } END {
+	for (mount in needed) {
+	    kib = int((needed[mount] + 1023) / 1024)
+	    if (kib > free[mount]) {
+		printf "%d KiB are needed in %s, but only %d KiB are free on %s\n", kib, dirs[mount], free[mount], mount
+		exit
+	    }
+	}
+    }')"
+    if [ -n "$_shortage" ]; then
+	err "not enough disk space: $_shortage. use --disable-space-check to install anyway"
+    fi
+}
+
 verbose_msg "looking for install programs"
 verbose_msg
 
@@ -779,7 +1481,8 @@ need_cmd chmod
 need_cmd env
 need_cmd pwd
 
-CFG_ARGS="${@:-}"
+# The arguments, one per line, so that values can contain spaces
+CFG_ARGS="$(printf '%s\n' "$@")"
 
 HELP=0
 if [ "${1-}" = "--help" ]
@@ -800,8 +1503,18 @@ BOOL_OPTIONS=""
 VAL_OPTIONS=""
 
 flag uninstall "only uninstall from the installation prefix"
+flag user "install for the current user into ~/.local and the XDG base directories"
 valopt destdir "" "set installation root"
-valopt prefix "/usr/local" "set installation prefix"
+
+# User installs go into ~/.local, without needing root
+default_prefix="/usr/local"
+if [ -n "${CFG_USER-}" ]; then
+    if [ -z "${HOME-}" ]; then
+	err "--user needs HOME to be set"
+    fi
+    default_prefix="$HOME/.local"
+fi
+valopt prefix "$default_prefix" "set installation prefix"
 
 # Avoid prepending an extra / to the prefix path if there's no destdir
 # NB: CFG vars here are undefined when passing --help
@@ -816,17 +1529,26 @@ fi
 valopt without "" "comma-separated list of components to not install"
 valopt components "" "comma-separated list of components to install"
 flag list-components "list available components"
-valopt sysconfdir "$CFG_DESTDIR_PREFIX/etc" "install system configuration files"
+valopt owner "" "print the installed component that owns PATH"
+default_sysconfdir="${CFG_DESTDIR_PREFIX-}/etc"
+default_datadir="${CFG_DESTDIR_PREFIX-}/share"
+if [ -n "${CFG_USER-}" ]; then
+    default_sysconfdir="${CFG_DESTDIR-}${XDG_CONFIG_HOME:-$HOME/.config}"
+    default_datadir="${CFG_DESTDIR-}${XDG_DATA_HOME:-$HOME/.local/share}"
+fi
+valopt sysconfdir "$default_sysconfdir" "install system configuration files"
 valopt bindir "$CFG_DESTDIR_PREFIX/bin" "install binaries"
 valopt libdir "$CFG_DESTDIR_PREFIX/lib" "install libraries"
-valopt datadir "$CFG_DESTDIR_PREFIX/share" "install data"
+valopt datadir "$default_datadir" "install data"
 # NB We repeat datadir default value because we don't set CFG_DATADIR in --help
 valopt mandir "${CFG_DATADIR-"$CFG_DESTDIR_PREFIX/share"}/man" "install man pages in PATH"
 # NB See the docdir handling in install_components for an explanation of this
 # weird <default> string
-valopt docdir "\<default\>" "install documentation in PATH"
+valopt docdir "<default>" "install documentation in PATH"
 opt ldconfig 1 "run ldconfig after installation (Linux only)"
+opt space-check 1 "check for free disk space before installing"
 opt verify 1 "obsolete"
+valopt lock-timeout 60 "seconds to wait for another installation into the prefix"
 flag verbose "run with verbose output"
 
 if [ $HELP -eq 1 ]
@@ -856,7 +1578,7 @@ TEMPLATE_RUST_INSTALLER_VERSION=%%TEMPLATE_RUST_INSTALLER_VERSION%%
 # OK, let's get installing ...
 
 # This is where we are installing from
-src_dir="$(abs_path $(dirname "$0"))"
+src_dir="$(abs_path "$(dirname "$0")")"
 
 # The name of the script
 src_basename="$(basename "$0")"
@@ -877,6 +1599,12 @@ fi
 # This is where we are installing to
 dest_prefix="$CFG_DESTDIR_PREFIX"
 
+# If the user asked who installed a path, answer from the installed manifests
+if [ -n "$CFG_OWNER" ]; then
+    print_owners "$CFG_LIBDIR/$TEMPLATE_REL_MANIFEST_DIR" "$CFG_OWNER"
+    exit 0
+fi
+
 # Open the components file to get the list of components to install.
 # NB: During install this components file is read from the installer's
 # source dir, during a full uninstall it's read from the manifest dir,
@@ -954,12 +1682,25 @@ fi
 
 do_preflight_sanity_checks "$src_dir" "$dest_prefix"
 
+if [ -z "${CFG_UNINSTALL-}" -a -z "${CFG_DISABLE_SPACE_CHECK-}" ]; then
+    verbose_msg "checking for free disk space"
+    check_free_space "$src_dir" "$dest_prefix" "$components"
+fi
+
 # Using an absolute path to libdir in a few places so that the status
 # messages are consistently using absolute paths.
 absolutify "$CFG_LIBDIR"
 abs_libdir="$RETVAL"
 assert_nz "$abs_libdir" "abs_libdir"
 
+# Keep concurrent installs from clobbering each other's manifests and logs
+case "$CFG_LOCK_TIMEOUT" in
+    "" | *[!0-9]*)
+	err "--lock-timeout must be a number of seconds"
+	;;
+esac
+acquire_lock "$abs_libdir/$TEMPLATE_REL_MANIFEST_DIR.lock" "$CFG_LOCK_TIMEOUT"
+
 # Create the manifest directory, where we will put our logs
 make_dir_recursive "$abs_libdir/$TEMPLATE_REL_MANIFEST_DIR"
 need_ok "failed to create $TEMPLATE_REL_MANIFEST_DIR"
@@ -967,6 +1708,18 @@ need_ok "failed to create $TEMPLATE_REL_MANIFEST_DIR"
 # Log messages and commands
 init_logging "$abs_libdir"
 
+# Upgrades leave the files that don't change alone. Delta installers only
+# carry the files that changed since the version they upgrade from.
+if [ -z "${CFG_UNINSTALL-}" ]; then
+    if [ -f "$src_dir/delta-base" ]; then
+	verify_delta_base "$src_dir" "$abs_libdir" "$components"
+    fi
+    plan_upgrades "$src_dir" "$abs_libdir" "$dest_prefix" "$components"
+    if [ -f "$src_dir/delta-base" ]; then
+	check_delta_kept "$src_dir" "$abs_libdir" "$components"
+    fi
+fi
+
 # First do any uninstallation, including from legacy manifests. This
 # will also upgrade the metadata of existing installs.
 uninstall_components "$abs_libdir" "$dest_prefix" "$components"
@@ -1002,4 +1755,19 @@ echo
 echo "    $TEMPLATE_SUCCESS_MESSAGE"
 echo
 
+# Programs installed for the user are only found if their bindir is on PATH
+if [ -n "${CFG_USER-}" ]; then
+    case ":${PATH-}:" in
+	*":$CFG_BINDIR:"* | *":$CFG_BINDIR/:"*)
+	    ;;
+	*)
+	    echo "    $CFG_BINDIR is not in your PATH. To run the installed programs,"
+	    echo "    add this line to your shell profile:"
+	    echo
+	    echo "        export PATH=\"$CFG_BINDIR:\$PATH\""
+	    echo
+	    ;;
+    esac
+fi
+
 
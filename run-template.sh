@@ -0,0 +1,158 @@
+#!/bin/bash
+# Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+# file at the top-level directory of this distribution and at
+# http://rust-lang.org/COPYRIGHT.
+#
+# Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+# http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+# <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+# option. This file may not be copied, modified, or distributed
+# except according to those terms.
+
+# A self-extracting installer: this script, followed by a compressed
+# installer tarball. It checks the tarball, unpacks it into a temporary
+# directory and runs the install.sh inside with the same arguments.
+
+# No undefined variables
+set -u
+
+TEMPLATE_PRODUCT_NAME=%%TEMPLATE_PRODUCT_NAME%%
+TEMPLATE_PACKAGE_NAME=%%TEMPLATE_PACKAGE_NAME%%
+TEMPLATE_PAYLOAD_FORMAT=%%TEMPLATE_PAYLOAD_FORMAT%%
+TEMPLATE_PAYLOAD_OFFSET=%%TEMPLATE_PAYLOAD_OFFSET%%
+TEMPLATE_PAYLOAD_SIZE=%%TEMPLATE_PAYLOAD_SIZE%%
+TEMPLATE_PAYLOAD_SHA256=%%TEMPLATE_PAYLOAD_SHA256%%
+
+msg() {
+    echo "install: ${1-}"
+}
+
+warn() {
+    echo "install: WARNING: $1" >&2
+}
+
+err() {
+    echo "install: error: $1" >&2
+    exit 1
+}
+
+need_cmd() {
+    if ! command -v "$1" > /dev/null 2>&1; then
+	err "need $1"
+    fi
+}
+
+print_help() {
+    echo "Usage: $0 [--extract-only[=DIR]] [install.sh options]"
+    echo
+    echo "Installs $TEMPLATE_PRODUCT_NAME from the installer embedded in this file."
+    echo
+    echo "    --extract-only[=DIR]    unpack the installer into DIR (default: .)"
+    echo "                            instead of running it"
+    echo
+    echo "The other options are passed to install.sh:"
+    echo
+}
+
+# Prints the payload, the tarball appended to this script
+payload() {
+    tail -c +$((TEMPLATE_PAYLOAD_OFFSET + 1)) "$0"
+}
+
+# Checks that the payload is complete and unchanged
+verify_payload() {
+    local _size
+    _size="$(wc -c < "$0")"
+    if [ "$(($_size))" -ne "$((TEMPLATE_PAYLOAD_OFFSET + TEMPLATE_PAYLOAD_SIZE))" ]; then
+	err "$0 is $(($_size)) bytes long instead of $((TEMPLATE_PAYLOAD_OFFSET + TEMPLATE_PAYLOAD_SIZE)), it may be truncated"
+    fi
+
+    local _hash
+    if command -v sha256sum > /dev/null 2>&1; then
+	_hash="$(payload | sha256sum)"
+    elif command -v shasum > /dev/null 2>&1; then
+	_hash="$(payload | shasum -a 256)"
+    else
+	warn "neither sha256sum nor shasum found, not checking the installer's checksum"
+	return
+    fi
+    if [ "${_hash%% *}" != "$TEMPLATE_PAYLOAD_SHA256" ]; then
+	err "the installer embedded in $0 is corrupt (checksum mismatch)"
+    fi
+}
+
+# Unpacks the payload into a directory
+extract_payload() {
+    local _dir="$1"
+
+    local _decompress
+    case "$TEMPLATE_PAYLOAD_FORMAT" in
+	gz) _decompress=gzip ;;
+	xz) _decompress=xz ;;
+	zst) _decompress=zstd ;;
+	*) err "unknown payload format $TEMPLATE_PAYLOAD_FORMAT" ;;
+    esac
+    need_cmd "$_decompress"
+    need_cmd tar
+
+    payload | "$_decompress" -dc | (cd "$_dir" && tar -xf -)
+    if [ "${PIPESTATUS[0]}${PIPESTATUS[1]}${PIPESTATUS[2]}" != 000 ]; then
+	err "failed to extract the installer into $_dir"
+    fi
+}
+
+TEMP_DIR=""
+
+cleanup() {
+    if [ -n "$TEMP_DIR" ]; then
+	rm -rf "$TEMP_DIR"
+    fi
+}
+
+CFG_EXTRACT_ONLY=""
+CFG_ARGS=()
+for arg in "$@"; do
+    case "$arg" in
+	--extract-only)
+	    CFG_EXTRACT_ONLY=.
+	    ;;
+	--extract-only=*)
+	    CFG_EXTRACT_ONLY="${arg#--extract-only=}"
+	    if [ -z "$CFG_EXTRACT_ONLY" ]; then
+		err "--extract-only needs a directory"
+	    fi
+	    ;;
+	--help|-h)
+	    print_help
+	    CFG_ARGS+=("$arg")
+	    ;;
+	*)
+	    CFG_ARGS+=("$arg")
+	    ;;
+    esac
+done
+
+verify_payload
+
+if [ -n "$CFG_EXTRACT_ONLY" ]; then
+    if [ -e "$CFG_EXTRACT_ONLY/$TEMPLATE_PACKAGE_NAME" ]; then
+	err "$CFG_EXTRACT_ONLY/$TEMPLATE_PACKAGE_NAME already exists"
+    fi
+    mkdir -p "$CFG_EXTRACT_ONLY" || err "failed to create $CFG_EXTRACT_ONLY"
+    extract_payload "$CFG_EXTRACT_ONLY"
+    msg "extracted to $CFG_EXTRACT_ONLY/$TEMPLATE_PACKAGE_NAME"
+    msg "run $CFG_EXTRACT_ONLY/$TEMPLATE_PACKAGE_NAME/install.sh to install"
+    exit 0
+fi
+
+need_cmd mktemp
+trap cleanup EXIT
+trap 'exit 1' HUP INT TERM
+TEMP_DIR="$(mktemp -d "${TMPDIR:-/tmp}/$TEMPLATE_PACKAGE_NAME.XXXXXX")" ||
+    err "failed to create a temporary directory"
+extract_payload "$TEMP_DIR"
+
+"$TEMP_DIR/$TEMPLATE_PACKAGE_NAME/install.sh" ${CFG_ARGS[@]+"${CFG_ARGS[@]}"}
+exit $?
+
+# The payload starts on the next byte.
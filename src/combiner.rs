@@ -1,13 +1,13 @@
 use super::Scripter;
 use super::Tarballer;
 use crate::{
-    compression::{CompressionFormat, CompressionFormats},
-    util::*,
+    compression::CompressionFormats, package::UnpackedInstaller, spec::PackageSpec,
+    tarballer::TarFormat, util::*,
 };
-use anyhow::{bail, Context, Result};
-use std::io::{Read, Write};
+use anyhow::{Context, Result};
+use std::collections::BTreeSet;
+use std::io::Write;
 use std::path::Path;
-use tar::Archive;
 
 actor! {
     #[derive(Debug)]
@@ -48,15 +48,73 @@ actor! {
         #[clap(value_name = "DIR")]
         output_dir: String = "./dist",
 
-        /// The formats used to compress the tarball
+        /// The formats used to compress the tarball, and zip to also write a zip archive
         #[clap(value_name = "FORMAT", default_value_t)]
         compression_formats: CompressionFormats,
+
+        /// How files are staged into the work directory: copy, hardlink or reflink.
+        #[clap(value_name = "MODE", default_value_t)]
+        copy_mode: CopyMode,
+
+        /// The flavor of tar headers to write: gnu, ustar or pax.
+        #[clap(value_name = "FORMAT", default_value_t)]
+        tar_format: TarFormat,
+
+        /// Store files with identical contents once, as hardlinks to the first copy.
+        dedup: bool,
+
+        /// Record extended attributes, including capabilities and ACLs (pax only).
+        xattrs: bool,
+
+        /// Store only the data regions of files with holes (pax only).
+        sparse: bool,
+
+        /// Also write <package>.run, a single executable file that unpacks and runs the installer.
+        self_extracting: bool,
+
+        /// A TOML or JSON package spec, whose settings replace the corresponding flags
+        /// unless those are given too.
+        #[clap(value_name = "FILE")]
+        spec: String = "",
+
+        // The flags given on the command line, which take precedence over the spec.
+        given_flags: BTreeSet<String>,
     }
 }
 
 impl Combiner {
     /// Combines the installer tarballs.
-    pub fn run(self) -> Result<()> {
+    pub fn run(mut self) -> Result<()> {
+        let mut input_tarballs = split_list(&self.input_tarballs);
+        if !self.spec.is_empty() {
+            let spec = PackageSpec::load(&self.spec)?;
+            apply_spec!(
+                self,
+                spec,
+                self.given_flags,
+                product_name,
+                package_name,
+                rel_manifest_dir,
+                success_message,
+                legacy_manifest_dirs,
+                non_installed_overlay,
+                work_dir,
+                output_dir,
+                compression_formats,
+                copy_mode,
+                tar_format,
+                dedup,
+                xattrs,
+                sparse,
+                self_extracting,
+            );
+            if !self.given_flags.contains("input_tarballs") {
+                if let Some(tarballs) = spec.input_tarballs {
+                    input_tarballs = tarballs;
+                }
+            }
+        }
+
         create_dir_all(&self.work_dir)?;
 
         let package_dir = Path::new(&self.work_dir).join(&self.package_name);
@@ -67,52 +125,29 @@ impl Combiner {
 
         // Merge each installer into the work directory of the new installer.
         let components = create_new_file(package_dir.join("components"))?;
-        for input_tarball in self
-            .input_tarballs
-            .split(',')
-            .map(str::trim)
+        for input_tarball in input_tarballs
+            .iter()
+            .map(|s| s.trim())
             .filter(|s| !s.is_empty())
         {
             // Extract the input tarballs
-            let compression =
-                CompressionFormat::detect_from_path(input_tarball).ok_or_else(|| {
-                    anyhow::anyhow!("couldn't figure out the format of {}", input_tarball)
-                })?;
-            Archive::new(compression.decode(input_tarball)?)
-                .unpack(&self.work_dir)
-                .with_context(|| {
-                    format!(
-                        "unable to extract '{}' into '{}'",
-                        &input_tarball, self.work_dir
-                    )
-                })?;
-
-            let pkg_name =
-                input_tarball.trim_end_matches(&format!(".tar.{}", compression.extension()));
-            let pkg_name = Path::new(pkg_name).file_name().unwrap();
-            let pkg_dir = Path::new(&self.work_dir).join(&pkg_name);
-
-            // Verify the version number.
-            let mut version = String::new();
-            open_file(pkg_dir.join("rust-installer-version"))
-                .and_then(|mut file| Ok(file.read_to_string(&mut version)?))
-                .with_context(|| format!("failed to read version in '{}'", input_tarball))?;
-            if version.trim().parse() != Ok(crate::RUST_INSTALLER_VERSION) {
-                bail!("incorrect installer version in {}", input_tarball);
-            }
+            let installer = UnpackedInstaller::unpack(input_tarball, self.work_dir.as_ref())?;
 
             // Copy components to the new combined installer.
-            let mut pkg_components = String::new();
-            open_file(pkg_dir.join("components"))
-                .and_then(|mut file| Ok(file.read_to_string(&mut pkg_components)?))
-                .with_context(|| format!("failed to read components in '{}'", input_tarball))?;
-            for component in pkg_components.split_whitespace() {
+            for component in &installer.components {
                 // All we need to do is copy the component directory. We could
                 // move it, but rustbuild wants to reuse the unpacked package
                 // dir for OS-specific installers on macOS and Windows.
-                let component_dir = package_dir.join(&component);
+                let component_dir = package_dir.join(component);
                 create_dir(&component_dir)?;
-                copy_recursive(&pkg_dir.join(&component), &component_dir)?;
+                copy_recursive(
+                    &installer.dir.join(component),
+                    &component_dir,
+                    self.copy_mode,
+                )?;
+                if self.xattrs {
+                    apply_xattrs(&component_dir)?;
+                }
 
                 // Merge the component name.
                 writeln!(&components, "{}", component).context("failed to write new components")?;
@@ -131,18 +166,17 @@ impl Combiner {
 
         // Copy the overlay.
         if !self.non_installed_overlay.is_empty() {
-            copy_recursive(self.non_installed_overlay.as_ref(), &package_dir)?;
+            copy_recursive(
+                self.non_installed_overlay.as_ref(),
+                &package_dir,
+                self.copy_mode,
+            )?;
         }
 
         // Generate the install script.
         let output_script = package_dir.join("install.sh");
-        let mut scripter = Scripter::default();
-        scripter
-            .product_name(self.product_name)
-            .rel_manifest_dir(self.rel_manifest_dir)
-            .success_message(self.success_message)
-            .legacy_manifest_dirs(self.legacy_manifest_dirs)
-            .output_script(path_to_str(&output_script)?.into());
+        let mut scripter = self.scripter();
+        scripter.output_script(path_to_str(&output_script)?.into());
         scripter.run()?;
 
         // Make the tarballs.
@@ -150,12 +184,36 @@ impl Combiner {
         let output = Path::new(&self.output_dir).join(&self.package_name);
         let mut tarballer = Tarballer::default();
         tarballer
-            .work_dir(self.work_dir)
-            .input(self.package_name)
+            .work_dir(self.work_dir.clone())
+            .input(self.package_name.clone())
             .output(path_to_str(&output)?.into())
-            .compression_formats(self.compression_formats.clone());
+            .compression_formats(self.compression_formats.clone())
+            .tar_format(self.tar_format)
+            .dedup(self.dedup)
+            .xattrs(self.xattrs)
+            .sparse(self.sparse);
         tarballer.run()?;
 
+        // Make the self-extracting installer.
+        if self.self_extracting {
+            self.scripter().write_self_extracting(
+                &self.package_name,
+                &self.compression_formats,
+                &output,
+            )?;
+        }
+
         Ok(())
     }
+
+    /// Configures a `Scripter` for the combined installer, without an output script.
+    fn scripter(&self) -> Scripter {
+        let mut scripter = Scripter::default();
+        scripter
+            .product_name(self.product_name.clone())
+            .rel_manifest_dir(self.rel_manifest_dir.clone())
+            .success_message(self.success_message.clone())
+            .legacy_manifest_dirs(self.legacy_manifest_dirs.clone());
+        scripter
+    }
 }
@@ -3,11 +3,14 @@ use flate2::{read::GzDecoder, write::GzEncoder};
 use rayon::prelude::*;
 use std::{convert::TryFrom, fmt, io::Read, io::Write, path::Path, str::FromStr};
 use xz2::{read::XzDecoder, write::XzEncoder};
+use zstd::stream::{read::Decoder as ZstdDecoder, write::Encoder as ZstdEncoder};
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Default)]
 pub enum CompressionFormat {
     Gz,
+    #[default]
     Xz,
+    Zst,
 }
 
 impl CompressionFormat {
@@ -15,6 +18,7 @@ impl CompressionFormat {
         match path.as_ref().extension().and_then(|e| e.to_str()) {
             Some("gz") => Some(CompressionFormat::Gz),
             Some("xz") => Some(CompressionFormat::Xz),
+            Some("zst") => Some(CompressionFormat::Zst),
             _ => None,
         }
     }
@@ -23,6 +27,7 @@ impl CompressionFormat {
         match self {
             CompressionFormat::Gz => "gz",
             CompressionFormat::Xz => "xz",
+            CompressionFormat::Zst => "zst",
         }
     }
 
@@ -71,6 +76,17 @@ impl CompressionFormat {
                 );
                 Box::new(compressor)
             }
+            CompressionFormat::Zst => {
+                // 19 is the highest level that doesn't need `--ultra` amounts
+                // of memory to decompress.
+                let mut compressor = ZstdEncoder::new(std::io::BufWriter::new(file), 19)
+                    .context("failed to start .zst file")?;
+                // Checksum the contents, like the zstd command line tool does.
+                compressor
+                    .include_checksum(true)
+                    .context("failed to start .zst file")?;
+                Box::new(compressor)
+            }
         })
     }
 
@@ -79,27 +95,63 @@ impl CompressionFormat {
         Ok(match self {
             CompressionFormat::Gz => Box::new(GzDecoder::new(file)),
             CompressionFormat::Xz => Box::new(XzDecoder::new(file)),
+            CompressionFormat::Zst => {
+                Box::new(ZstdDecoder::new(file).context("failed to read .zst file")?)
+            }
         })
     }
 }
 
+impl FromStr for CompressionFormat {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "gz" => Ok(CompressionFormat::Gz),
+            "xz" => Ok(CompressionFormat::Xz),
+            "zst" => Ok(CompressionFormat::Zst),
+            other => anyhow::bail!("unknown compression format: {}", other),
+        }
+    }
+}
+
+impl fmt::Display for CompressionFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.extension(), f)
+    }
+}
+
 /// This struct wraps Vec<CompressionFormat> in order to parse the value from the command line.
+/// A `zip` in the list asks for a zip archive next to the tarballs.
 #[derive(Debug, Clone)]
-pub struct CompressionFormats(Vec<CompressionFormat>);
+pub struct CompressionFormats {
+    formats: Vec<CompressionFormat>,
+    zip: bool,
+}
 
 impl TryFrom<&'_ str> for CompressionFormats {
     type Error = Error;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        let mut parsed = Vec::new();
-        for format in value.split(',') {
-            match format.trim() {
-                "gz" => parsed.push(CompressionFormat::Gz),
-                "xz" => parsed.push(CompressionFormat::Xz),
-                other => anyhow::bail!("unknown compression format: {}", other),
+        let formats: Vec<_> = value.split(',').collect();
+        Self::try_from(formats.as_slice())
+    }
+}
+
+impl<S: AsRef<str>> TryFrom<&'_ [S]> for CompressionFormats {
+    type Error = Error;
+
+    fn try_from(value: &[S]) -> Result<Self, Self::Error> {
+        let mut formats = Vec::new();
+        let mut zip = false;
+        for format in value {
+            if format.as_ref().trim() == "zip" {
+                zip = true;
+            } else {
+                formats.push(format.as_ref().parse()?);
             }
         }
-        Ok(CompressionFormats(parsed))
+        Ok(CompressionFormats { formats, zip })
     }
 }
 
@@ -117,13 +169,13 @@ impl fmt::Display for CompressionFormats {
             if i != 0 {
                 write!(f, ",")?;
             }
-            fmt::Display::fmt(
-                match format {
-                    CompressionFormat::Xz => "xz",
-                    CompressionFormat::Gz => "gz",
-                },
-                f,
-            )?;
+            fmt::Display::fmt(&format, f)?;
+        }
+        if self.zip {
+            if !self.formats.is_empty() {
+                write!(f, ",")?;
+            }
+            write!(f, "zip")?;
         }
         Ok(())
     }
@@ -131,13 +183,22 @@ impl fmt::Display for CompressionFormats {
 
 impl Default for CompressionFormats {
     fn default() -> Self {
-        Self(vec![CompressionFormat::Gz, CompressionFormat::Xz])
+        Self {
+            formats: vec![CompressionFormat::Gz, CompressionFormat::Xz],
+            zip: false,
+        }
     }
 }
 
 impl CompressionFormats {
+    /// Iterates over the formats to compress tarballs with.
     pub(crate) fn iter(&self) -> impl Iterator<Item = CompressionFormat> + '_ {
-        self.0.iter().map(|i| *i)
+        self.formats.iter().copied()
+    }
+
+    /// Returns whether a zip archive is wanted.
+    pub(crate) fn zip(&self) -> bool {
+        self.zip
     }
 }
 
@@ -159,11 +220,19 @@ impl<W: Send + Write> Encoder for XzEncoder<W> {
     }
 }
 
+impl<W: Send + Write> Encoder for ZstdEncoder<'static, W> {
+    fn finish(self: Box<Self>) -> Result<(), Error> {
+        ZstdEncoder::finish(*self).context("failed to finish .zst file")?;
+        Ok(())
+    }
+}
+
 pub(crate) struct CombinedEncoder {
     encoders: Vec<Box<dyn Encoder>>,
 }
 
 impl CombinedEncoder {
+    #[allow(clippy::new_ret_no_self)]
     pub(crate) fn new(encoders: Vec<Box<dyn Encoder>>) -> Box<dyn Encoder> {
         Box::new(Self { encoders })
     }
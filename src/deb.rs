@@ -0,0 +1,261 @@
+use crate::compression::CompressionFormat;
+use crate::package::{
+    parent_dirs, relative, timestamp, InstalledKind, Layout, PackageTar, UnpackedInstaller,
+};
+use crate::util::*;
+use anyhow::{bail, Context, Result};
+use md5::{Digest, Md5};
+use std::fmt::Write as _;
+use std::io;
+use std::path::Path;
+
+actor! {
+    #[derive(Debug)]
+    pub struct Deb {
+        /// The installer tarball to turn into a Debian package
+        #[clap(value_name = "FILE")]
+        input_tarball: String = "",
+
+        /// The prefix the package installs into
+        #[clap(value_name = "DIR")]
+        prefix: String = "/usr",
+
+        /// Where the files under etc/ go, /etc for the /usr prefix and <prefix>/etc otherwise
+        #[clap(value_name = "DIR")]
+        sysconfdir: String = "",
+
+        /// The name of the Debian package, the installer's package name by default
+        #[clap(value_name = "NAME")]
+        package_name: String = "",
+
+        /// The version of the Debian package
+        #[clap(value_name = "VERSION")]
+        version: String = "",
+
+        /// The Debian architecture, the one of this machine by default
+        #[clap(value_name = "ARCH")]
+        architecture: String = "",
+
+        /// The maintainer of the package, as "Name <email>"
+        #[clap(value_name = "MAINTAINER")]
+        maintainer: String = "",
+
+        /// The one-line description of the package, listing the components by default
+        #[clap(value_name = "TEXT")]
+        description: String = "",
+
+        /// The packages this one depends on, in the syntax of the Depends field
+        #[clap(value_name = "DEPENDS")]
+        depends: String = "",
+
+        /// The archive section of the package
+        #[clap(value_name = "SECTION")]
+        section: String = "devel",
+
+        /// The priority of the package
+        #[clap(value_name = "PRIORITY")]
+        priority: String = "optional",
+
+        /// The format used to compress the control and data tarballs: gz, xz or zst
+        #[clap(value_name = "FORMAT", default_value_t)]
+        compression_format: CompressionFormat,
+
+        /// The directory to do temporary work
+        #[clap(value_name = "DIR")]
+        work_dir: String = "./workdir",
+
+        /// The location to put the package
+        #[clap(value_name = "DIR")]
+        output_dir: String = "./dist",
+    }
+}
+
+impl Deb {
+    /// Builds `<name>_<version>_<arch>.deb` from the installer tarball, with
+    /// the files where install.sh would put them under the prefix
+    pub fn run(self) -> Result<()> {
+        if self.input_tarball.is_empty() {
+            bail!("the installer tarball to package is required, with --input-tarball");
+        }
+        if self.version.is_empty() || self.maintainer.is_empty() {
+            bail!("a Debian package needs --version and --maintainer");
+        }
+        let layout = Layout::new(&self.prefix, &self.sysconfdir)?;
+        create_dir_all(&self.work_dir)?;
+        let installer = UnpackedInstaller::unpack(&self.input_tarball, self.work_dir.as_ref())?;
+        let paths = installer.installed_paths(&layout)?;
+
+        let name = if self.package_name.is_empty() {
+            installer.name().to_owned()
+        } else {
+            self.package_name.clone()
+        };
+        check_package_name(&name)?;
+        let architecture = if self.architecture.is_empty() {
+            host_architecture()?.to_owned()
+        } else {
+            self.architecture.clone()
+        };
+        let mtime = timestamp()?;
+
+        // The files, with the directories above them.
+        let deb_dir = Path::new(&self.work_dir).join(format!("{}.deb", name));
+        if deb_dir.exists() {
+            remove_dir_all(&deb_dir)?;
+        }
+        create_dir_all(&deb_dir)?;
+        let format = self.compression_format;
+        let data = format!("data.tar.{}", format.extension());
+        let mut tar = PackageTar::new(format.encode(deb_dir.join("data.tar"))?, mtime);
+        tar.append_dir(Path::new("."))?;
+        let parents = parent_dirs(paths.keys());
+        for dir in &parents {
+            tar.append_dir(&relative(dir))?;
+        }
+        for (path, installed) in &paths {
+            if !parents.contains(path) {
+                tar.append_installed(&relative(path), installed)?;
+            }
+        }
+        tar.into_inner()?.finish()?;
+
+        // The metadata, and the checksums and configuration files dpkg tracks.
+        let mut installed_size = parents.len() as u64;
+        let mut md5sums = String::new();
+        let mut conffiles = String::new();
+        for (path, installed) in paths.iter().filter(|(path, _)| !parents.contains(*path)) {
+            installed_size += match installed.kind {
+                InstalledKind::File { size, .. } => size.div_ceil(1024),
+                InstalledKind::Dir | InstalledKind::Symlink(_) => 1,
+            };
+            if let InstalledKind::File { .. } = installed.kind {
+                let mut hasher = Md5::new();
+                io::copy(&mut open_file(&installed.source)?, &mut hasher)
+                    .with_context(|| format!("failed to read '{}'", installed.source.display()))?;
+                let hash = to_hex(&hasher.finalize());
+                writeln!(md5sums, "{}  {}", hash, path_to_str(&relative(path))?)?;
+                if layout.is_config(path) {
+                    writeln!(conffiles, "{}", path_to_str(path)?)?;
+                }
+            }
+        }
+        let control = self.control(&name, &architecture, installed_size, &installer.components)?;
+
+        let control_tar = format!("control.tar.{}", format.extension());
+        let mut tar = PackageTar::new(format.encode(deb_dir.join("control.tar"))?, mtime);
+        tar.append_dir(Path::new("."))?;
+        tar.append_data(Path::new("control"), 0o644, control.as_bytes())?;
+        tar.append_data(Path::new("md5sums"), 0o644, md5sums.as_bytes())?;
+        if !conffiles.is_empty() {
+            tar.append_data(Path::new("conffiles"), 0o644, conffiles.as_bytes())?;
+        }
+        tar.into_inner()?.finish()?;
+
+        // Wrap it all up in an ar archive, in the order dpkg expects.
+        create_dir_all(&self.output_dir)?;
+        let version = match self.version.split_once(':') {
+            Some((_epoch, version)) => version,
+            None => &self.version,
+        };
+        let output =
+            Path::new(&self.output_dir).join(format!("{}_{}_{}.deb", name, version, architecture));
+        if output.exists() {
+            remove_file(&output)?;
+        }
+        let mut archive = ar::Builder::new(create_new_file(&output)?);
+        let mut append = |name: &str, data: &mut dyn io::Read, size: u64| -> Result<()> {
+            let mut header = ar::Header::new(name.as_bytes().to_vec(), size);
+            header.set_mtime(mtime);
+            header.set_mode(0o100644);
+            archive
+                .append(&header, data)
+                .with_context(|| format!("failed to write '{}'", output.display()))
+        };
+        append("debian-binary", &mut &b"2.0\n"[..], 4)?;
+        for member in [&control_tar, &data] {
+            let path = deb_dir.join(member);
+            let size = std::fs::metadata(&path)
+                .with_context(|| format!("failed to read metadata of '{}'", path.display()))?
+                .len();
+            append(member, &mut open_file(&path)?, size)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the contents of the `control` file.
+    fn control(
+        &self,
+        name: &str,
+        architecture: &str,
+        installed_size: u64,
+        components: &[String],
+    ) -> Result<String> {
+        let description = if self.description.is_empty() {
+            format!("{} components", components.join(", "))
+        } else {
+            self.description.clone()
+        };
+        let mut fields = vec![
+            ("Package", name),
+            ("Version", &self.version),
+            ("Architecture", architecture),
+            ("Maintainer", &self.maintainer),
+        ];
+        let installed_size = installed_size.to_string();
+        fields.push(("Installed-Size", &installed_size));
+        if !self.depends.is_empty() {
+            fields.push(("Depends", &self.depends));
+        }
+        fields.push(("Section", &self.section));
+        fields.push(("Priority", &self.priority));
+        fields.push(("Description", &description));
+
+        let mut control = String::new();
+        for (field, value) in fields {
+            if value.is_empty() || value.contains('\n') {
+                bail!("invalid {} for a Debian package: {:?}", field, value);
+            }
+            writeln!(control, "{}: {}", field, value)?;
+        }
+        // The extended description lists what the components are.
+        writeln!(control, " Installs the components:")?;
+        for component in components {
+            writeln!(control, " * {}", component)?;
+        }
+        Ok(control)
+    }
+}
+
+/// Checks that `name` is valid as a Debian package name.
+fn check_package_name(name: &str) -> Result<()> {
+    let valid = name.len() >= 2
+        && name.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "+-.".contains(c));
+    if !valid {
+        bail!(
+            "'{}' is not a valid Debian package name, pick one with --package-name",
+            name
+        );
+    }
+    Ok(())
+}
+
+/// Returns the Debian name of the architecture this was built for.
+fn host_architecture() -> Result<&'static str> {
+    Ok(match host_arch() {
+        "x86_64" => "amd64",
+        "x86" => "i386",
+        "aarch64" => "arm64",
+        "arm" => "armhf",
+        "powerpc64le" => "ppc64el",
+        "riscv64" => "riscv64",
+        "s390x" => "s390x",
+        "loongarch64" => "loong64",
+        other => bail!(
+            "no Debian architecture known for '{}', give one with --architecture",
+            other
+        ),
+    })
+}
@@ -0,0 +1,268 @@
+use super::Scripter;
+use super::Tarballer;
+use crate::compression::CompressionFormats;
+use crate::package::{ImagePath, InstalledKind, UnpackedInstaller};
+use crate::util::*;
+use anyhow::{bail, Context, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+actor! {
+    #[derive(Debug)]
+    pub struct Delta {
+        /// The name of the product, for display
+        #[clap(value_name = "NAME")]
+        product_name: String = "Product",
+
+        /// The name of the package tarball, <new package name>-delta by default
+        #[clap(value_name = "NAME")]
+        package_name: String = "",
+
+        /// The directory under lib/ where the manifest lives
+        #[clap(value_name = "DIR")]
+        rel_manifest_dir: String = "packagelib",
+
+        /// The string to print after successful installation
+        #[clap(value_name = "MESSAGE")]
+        success_message: String = "Installed.",
+
+        /// Places to look for legacy manifests to uninstall
+        #[clap(value_name = "DIRS")]
+        legacy_manifest_dirs: String = "",
+
+        /// The installer tarball of the installed version to upgrade from
+        #[clap(value_name = "FILE")]
+        old_tarball: String = "",
+
+        /// The installer tarball of the version to upgrade to
+        #[clap(value_name = "FILE")]
+        new_tarball: String = "",
+
+        /// The directory to do temporary work
+        #[clap(value_name = "DIR")]
+        work_dir: String = "./workdir",
+
+        /// The location to put the final image and tarball
+        #[clap(value_name = "DIR")]
+        output_dir: String = "./dist",
+
+        /// The formats used to compress the tarball, and zip to also write a zip archive
+        #[clap(value_name = "FORMAT", default_value_t)]
+        compression_formats: CompressionFormats,
+    }
+}
+
+impl Delta {
+    /// Builds an installer that upgrades an installation of the old version to
+    /// the new one, carrying only the files that changed. Its install.sh
+    /// checks that the installed components are the old version, and takes
+    /// the other files from the installation.
+    pub fn run(self) -> Result<()> {
+        if self.old_tarball.is_empty() || self.new_tarball.is_empty() {
+            bail!("a delta needs the installers of both versions, with --old-tarball and --new-tarball");
+        }
+        create_dir_all(&self.work_dir)?;
+        let work_dir = Path::new(&self.work_dir);
+        let old = unpack_clean(&self.old_tarball, &work_dir.join("delta-old"))?;
+        let new = unpack_clean(&self.new_tarball, &work_dir.join("delta-new"))?;
+
+        let package_name = if self.package_name.is_empty() {
+            format!("{}-delta", new.name())
+        } else {
+            self.package_name.clone()
+        };
+        let package_dir = work_dir.join(&package_name);
+        if package_dir.exists() {
+            remove_dir_all(&package_dir)?;
+        }
+        create_dir_all(&package_dir)?;
+
+        // Components that are new in this version are carried whole.
+        for component in &new.components {
+            let component_dir = package_dir.join(component);
+            create_dir(&component_dir)?;
+            if old.components.contains(component) {
+                write_component_delta(&old, &new, component, &component_dir)?;
+            } else {
+                copy_recursive(&new.dir.join(component), &component_dir, CopyMode::Copy)?;
+            }
+        }
+
+        // The rest of the package, with an install script that knows about
+        // deltas, and the version they upgrade from.
+        for entry in fs::read_dir(&new.dir)
+            .with_context(|| format!("failed to read '{}'", new.dir.display()))?
+        {
+            let entry = entry?;
+            let name = entry.file_name();
+            if name == "install.sh" || new.components.iter().any(|c| name == c.as_str()) {
+                continue;
+            }
+            if entry.file_type()?.is_dir() {
+                create_dir(package_dir.join(&name))?;
+                copy_recursive(&entry.path(), &package_dir.join(&name), CopyMode::Copy)?;
+            } else {
+                copy(entry.path(), package_dir.join(&name))?;
+            }
+        }
+        writeln!(
+            create_new_file(package_dir.join("delta-base"))?,
+            "{}",
+            old.name()
+        )
+        .context("failed to write the delta base")?;
+        let output_script = package_dir.join("install.sh");
+        let mut scripter = Scripter::default();
+        scripter
+            .product_name(self.product_name.clone())
+            .rel_manifest_dir(self.rel_manifest_dir.clone())
+            .success_message(self.success_message.clone())
+            .legacy_manifest_dirs(self.legacy_manifest_dirs.clone())
+            .output_script(path_to_str(&output_script)?.into());
+        scripter.run()?;
+
+        // Make the tarballs.
+        create_dir_all(&self.output_dir)?;
+        let output = Path::new(&self.output_dir).join(&package_name);
+        let mut tarballer = Tarballer::default();
+        tarballer
+            .work_dir(self.work_dir.clone())
+            .input(package_name)
+            .output(path_to_str(&output)?.into())
+            .compression_formats(self.compression_formats.clone());
+        tarballer.run()
+    }
+}
+
+/// Unpacks an installer tarball into `dir`, which is emptied first so that
+/// files left over in bulk directories don't end up in the delta.
+fn unpack_clean(tarball: &str, dir: &Path) -> Result<UnpackedInstaller> {
+    if dir.exists() {
+        remove_dir_all(dir)?;
+    }
+    create_dir_all(dir)?;
+    UnpackedInstaller::unpack(tarball, dir)
+}
+
+/// Writes the image of `component` of the new installer into `dst`, leaving
+/// out the files that are the same in the old one. The full `manifest.in` and
+/// `hashes.in` of the new version go along, with the `hashes.in` of the old
+/// version as `base-hashes.in`, and the files left out as `keep:` directives
+/// in `delta.in`. The files of the old version that the new one doesn't have
+/// need none, as install.sh removes every file an upgrade doesn't keep.
+fn write_component_delta(
+    old: &UnpackedInstaller,
+    new: &UnpackedInstaller,
+    component: &str,
+    dst: &Path,
+) -> Result<()> {
+    let old_dir = old.dir.join(component);
+    let new_dir = new.dir.join(component);
+    let old_hashes = read_hashes(&old_dir)?;
+    let new_hashes = read_hashes(&new_dir)?;
+    let old_paths = old.component_paths(component)?;
+    let new_paths = new.component_paths(component)?;
+    let old_modes: BTreeMap<&Path, u32> = old_paths
+        .iter()
+        .filter_map(|p| match p.kind {
+            InstalledKind::File { mode, .. } => Some((p.path.as_path(), mode)),
+            _ => None,
+        })
+        .collect();
+
+    // install.sh copies bulk directories with names containing newlines
+    // whole, since its lists of unchanged files are line-based.
+    let whole: BTreeSet<&Path> = old_paths
+        .iter()
+        .chain(&new_paths)
+        .filter(|p| path_bytes(&p.path).map_or(true, |b| b.contains(&b'\n')))
+        .filter_map(|p| p.bulk_dir.as_deref())
+        .collect();
+
+    let mut directives = BTreeSet::new();
+    for image_path in &new_paths {
+        let target = dst.join(&image_path.path);
+        match &image_path.kind {
+            InstalledKind::Dir => create_dir_all(&target)?,
+            InstalledKind::Symlink(_) => {
+                create_parent(&target)?;
+                copy(&image_path.source, &target)?;
+            }
+            InstalledKind::File { mode, .. } => {
+                let escaped = escape_path(&image_path.path)?;
+                // Files whose mode changed go along, as install.sh can only
+                // give kept files the new mode from the image.
+                let old_mode = old_modes.get(image_path.path.as_path());
+                if old_mode == Some(mode)
+                    && is_unchanged(image_path, &escaped, &old_hashes, &new_hashes, &whole)
+                {
+                    directives.insert(format!("keep:{}\n", escaped));
+                    continue;
+                }
+                create_parent(&target)?;
+                copy(&image_path.source, &target)?;
+            }
+        }
+    }
+
+    for name in ["manifest.in", "hashes.in", "size.in", "xattrs.in"] {
+        if new_dir.join(name).exists() {
+            copy(new_dir.join(name), dst.join(name))?;
+        }
+    }
+    copy(old_dir.join("hashes.in"), dst.join("base-hashes.in"))?;
+    let directives: String = directives.into_iter().collect();
+    create_new_file(dst.join("delta.in"))?
+        .write_all(directives.as_bytes())
+        .context("failed to write delta.in")
+}
+
+/// Returns whether a file can be left out of the delta and taken from the
+/// installation: it has the same contents in both versions, and a name that
+/// install.sh can keep across upgrades. Its mode has to be the same too, which
+/// the caller checks.
+fn is_unchanged(
+    image_path: &ImagePath,
+    escaped: &str,
+    old_hashes: &BTreeMap<String, String>,
+    new_hashes: &BTreeMap<String, String>,
+    whole: &BTreeSet<&Path>,
+) -> bool {
+    !escaped.contains('\\')
+        && old_hashes.contains_key(escaped)
+        && old_hashes.get(escaped) == new_hashes.get(escaped)
+        && !image_path
+            .bulk_dir
+            .as_deref()
+            .is_some_and(|dir| whole.contains(dir))
+}
+
+/// Reads the `hashes.in` of a component, by escaped path.
+fn read_hashes(component_dir: &Path) -> Result<BTreeMap<String, String>> {
+    let path = component_dir.join("hashes.in");
+    if !path.exists() {
+        bail!(
+            "'{}' is missing, deltas need installers that record the hashes of their files",
+            path.display()
+        );
+    }
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("failed to read '{}'", path.display()))?;
+    let mut hashes = BTreeMap::new();
+    for line in contents.lines() {
+        match line.split_once(' ') {
+            Some((hash, file)) => hashes.insert(file.to_owned(), hash.to_owned()),
+            None => bail!("malformed line in '{}': {}", path.display(), line),
+        };
+    }
+    Ok(hashes)
+}
+
+fn create_parent(path: &Path) -> Result<()> {
+    match path.parent() {
+        Some(parent) => create_dir_all(parent),
+        None => Ok(()),
+    }
+}
@@ -1,11 +1,16 @@
 use super::Scripter;
 use super::Tarballer;
 use crate::compression::CompressionFormats;
+use crate::package::check_component_name;
+use crate::spec::PackageSpec;
+use crate::tarballer::{Entry, Source, TarFormat};
 use crate::util::*;
-use anyhow::{bail, format_err, Context, Result};
-use std::collections::BTreeSet;
+use anyhow::{bail, Context, Result};
+use globset::{GlobSet, GlobSetBuilder};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 actor! {
     #[derive(Debug)]
@@ -38,10 +43,18 @@ actor! {
         #[clap(value_name = "DIR")]
         non_installed_overlay: String = "",
 
-        /// Path prefixes of directories that should be installed/uninstalled in bulk
+        /// Paths or glob patterns of directories that should be installed/uninstalled in bulk
         #[clap(value_name = "DIRS")]
         bulk_dirs: String = "",
 
+        /// Glob patterns of image paths to install, all of them if empty
+        #[clap(value_name = "GLOBS")]
+        include: String = "",
+
+        /// Glob patterns of image paths to leave out, in addition to those in .installerignore
+        #[clap(value_name = "GLOBS")]
+        exclude: String = "",
+
         /// The directory containing the installation medium
         #[clap(value_name = "DIR")]
         image_dir: String = "./install_image",
@@ -54,15 +67,74 @@ actor! {
         #[clap(value_name = "DIR")]
         output_dir: String = "./dist",
 
-        /// The formats used to compress the tarball
+        /// The formats used to compress the tarball, and zip to also write a zip archive
         #[clap(value_name = "FORMAT", default_value_t)]
         compression_formats: CompressionFormats,
+
+        /// How files are staged into the work directory: copy, hardlink or reflink
+        #[clap(value_name = "MODE", default_value_t)]
+        copy_mode: CopyMode,
+
+        /// The flavor of tar headers to write: gnu, ustar or pax
+        #[clap(value_name = "FORMAT", default_value_t)]
+        tar_format: TarFormat,
+
+        /// Store files with identical contents once, as hardlinks to the first copy
+        dedup: bool,
+
+        /// Record extended attributes, including capabilities and ACLs, for the installer to restore
+        xattrs: bool,
+
+        /// Store only the data regions of files with holes (pax only)
+        sparse: bool,
+
+        /// Write the tarball straight from the image dir, without staging it in the work dir
+        stream: bool,
+
+        /// Also write <package>.run, a single executable file that unpacks and runs the installer
+        self_extracting: bool,
+
+        /// A TOML or JSON package spec, whose settings replace the corresponding flags
+        /// unless those are given too
+        #[clap(value_name = "FILE")]
+        spec: String = "",
+
+        /// A component given as its name and image dir, replacing --component-name and
+        /// --image-dir; repeat it to put several components in the package
+        #[clap(value_names = &["NAME", "DIR"], number_of_values = 2)]
+        component: Vec<String>,
+
+        /// The bulk dirs of a component given with --component, like --bulk-dirs
+        #[clap(value_names = &["NAME", "DIRS"], number_of_values = 2)]
+        component_bulk_dirs: Vec<String>,
+
+        // The flags given on the command line, which take precedence over the spec
+        given_flags: BTreeSet<String>,
     }
 }
 
 impl Generator {
     /// Generates the actual installer tarball
-    pub fn run(self) -> Result<()> {
+    pub fn run(mut self) -> Result<()> {
+        let mut components = vec![];
+        if !self.spec.is_empty() {
+            components = self.apply_spec()?;
+        }
+        if components.is_empty() {
+            components = self.components()?;
+        }
+        let mut names = BTreeSet::new();
+        for component in &components {
+            check_component_name(&component.name)?;
+            if !names.insert(&component.name) {
+                bail!("component '{}' is given more than once", component.name);
+            }
+        }
+
+        if self.stream {
+            return self.run_streaming(&components);
+        }
+
         create_dir_all(&self.work_dir)?;
 
         let package_dir = Path::new(&self.work_dir).join(&self.package_name);
@@ -70,15 +142,27 @@ impl Generator {
             remove_dir_all(&package_dir)?;
         }
 
-        // Copy the image and write the manifest
-        let component_dir = package_dir.join(&self.component_name);
-        create_dir_all(&component_dir)?;
-        copy_and_manifest(self.image_dir.as_ref(), &component_dir, &self.bulk_dirs)?;
+        // Copy the images and write the manifests
+        for component in &components {
+            let component_dir = package_dir.join(&component.name);
+            create_dir_all(&component_dir)?;
+            let image_dir = Path::new(&component.image_dir);
+            let filter = PathFilter::new(image_dir, &component.include, &component.exclude)?;
+            copy_and_manifest(
+                image_dir,
+                &component_dir,
+                &component.bulk_dirs,
+                self.copy_mode,
+                &filter,
+                self.xattrs,
+            )?;
+        }
 
-        // Write the component name
-        let components = package_dir.join("components");
-        writeln!(create_new_file(components)?, "{}", self.component_name)
-            .context("failed to write the component file")?;
+        // Write the component names
+        let mut file = create_new_file(package_dir.join("components"))?;
+        for component in &components {
+            writeln!(file, "{}", component.name).context("failed to write the component file")?;
+        }
 
         // Write the installer version (only used by combine-installers.sh)
         let version = package_dir.join("rust-installer-version");
@@ -91,18 +175,17 @@ impl Generator {
 
         // Copy the overlay
         if !self.non_installed_overlay.is_empty() {
-            copy_recursive(self.non_installed_overlay.as_ref(), &package_dir)?;
+            copy_recursive(
+                self.non_installed_overlay.as_ref(),
+                &package_dir,
+                self.copy_mode,
+            )?;
         }
 
         // Generate the install script
         let output_script = package_dir.join("install.sh");
-        let mut scripter = Scripter::default();
-        scripter
-            .product_name(self.product_name)
-            .rel_manifest_dir(self.rel_manifest_dir)
-            .success_message(self.success_message)
-            .legacy_manifest_dirs(self.legacy_manifest_dirs)
-            .output_script(path_to_str(&output_script)?.into());
+        let mut scripter = self.scripter();
+        scripter.output_script(path_to_str(&output_script)?.into());
         scripter.run()?;
 
         // Make the tarballs
@@ -110,69 +193,417 @@ impl Generator {
         let output = Path::new(&self.output_dir).join(&self.package_name);
         let mut tarballer = Tarballer::default();
         tarballer
-            .work_dir(self.work_dir)
-            .input(self.package_name)
+            .work_dir(self.work_dir.clone())
+            .input(self.package_name.clone())
             .output(path_to_str(&output)?.into())
-            .compression_formats(self.compression_formats.clone());
+            .compression_formats(self.compression_formats.clone())
+            .tar_format(self.tar_format)
+            .dedup(self.dedup)
+            .xattrs(self.xattrs)
+            .sparse(self.sparse);
         tarballer.run()?;
 
+        self.write_self_extracting(&output)
+    }
+
+    /// Generates the installer tarball directly from the image, synthesizing
+    /// the generated files in memory instead of staging them in the work dir
+    fn run_streaming(self, components: &[ComponentImage]) -> Result<()> {
+        let package = Path::new(&self.package_name);
+        let mut dirs = BTreeMap::new();
+        let mut files = BTreeMap::new();
+        dirs.insert(package.to_owned(), Source::Dir(0o755));
+
+        // Walk the images and build the manifests
+        for component in components {
+            let prefix = package.join(&component.name);
+            dirs.insert(prefix.clone(), Source::Dir(0o755));
+            let image_dir = Path::new(&component.image_dir);
+            let filter = PathFilter::new(image_dir, &component.include, &component.exclude)?;
+            let mut manifest = Manifest::new(&component.bulk_dirs)?;
+            let mut sizes = InstalledSizes::default();
+            let mut hashes = FileHashes::default();
+            let mut xattr_lines = BTreeSet::new();
+            walk_with_callback(image_dir, &filter, |path, file_type| {
+                if self.xattrs {
+                    add_xattr_lines(&mut xattr_lines, image_dir, path)?;
+                }
+                manifest.add(path, file_type)?;
+                sizes.add(image_dir, path, file_type)?;
+                hashes.add(image_dir, path, file_type)?;
+                add_source(&mut dirs, &mut files, &prefix, image_dir, path, file_type)
+            })?;
+            manifest.warn_unmatched();
+            let manifest = manifest.contents().into_bytes();
+            files.insert(prefix.join("manifest.in"), Source::Memory(manifest, 0o644));
+            let sizes = sizes.contents()?.into_bytes();
+            files.insert(prefix.join("size.in"), Source::Memory(sizes, 0o644));
+            let hashes = hashes.contents().into_bytes();
+            files.insert(prefix.join("hashes.in"), Source::Memory(hashes, 0o644));
+            if !xattr_lines.is_empty() {
+                let xattr_lines = xattr_lines.into_iter().collect::<String>().into_bytes();
+                files.insert(prefix.join("xattrs.in"), Source::Memory(xattr_lines, 0o644));
+            }
+        }
+
+        // Synthesize the component names and installer version
+        let components = components
+            .iter()
+            .map(|c| format!("{}\n", c.name))
+            .collect::<String>()
+            .into_bytes();
+        files.insert(
+            package.join("components"),
+            Source::Memory(components, 0o644),
+        );
+        let version = format!("{}\n", crate::RUST_INSTALLER_VERSION).into_bytes();
+        files.insert(
+            package.join("rust-installer-version"),
+            Source::Memory(version, 0o644),
+        );
+
+        // Add the overlay
+        if !self.non_installed_overlay.is_empty() {
+            let overlay = Path::new(&self.non_installed_overlay);
+            walk_with_callback(overlay, &PathFilter::default(), |path, file_type| {
+                add_source(&mut dirs, &mut files, package, overlay, path, file_type)
+            })?;
+        }
+
+        // Generate the install script
+        let script = self.scripter().script().into_bytes();
+        files.insert(package.join("install.sh"), Source::Memory(script, 0o755));
+
+        // Make the tarballs
+        create_dir_all(&self.output_dir)?;
+        let output = Path::new(&self.output_dir).join(package);
+        let entries = |map: BTreeMap<PathBuf, Source>| {
+            map.into_iter()
+                .map(|(path, source)| Entry { path, source })
+                .collect()
+        };
+        let mut tarballer = Tarballer::default();
+        tarballer
+            .output(path_to_str(&output)?.into())
+            .compression_formats(self.compression_formats.clone())
+            .tar_format(self.tar_format)
+            .dedup(self.dedup)
+            .xattrs(self.xattrs)
+            .sparse(self.sparse);
+        tarballer.write_entries(entries(dirs), entries(files))?;
+
+        self.write_self_extracting(&output)
+    }
+
+    /// Writes the self-extracting installer next to the tarballs at `output`,
+    /// if asked to
+    fn write_self_extracting(&self, output: &Path) -> Result<()> {
+        if self.self_extracting {
+            self.scripter().write_self_extracting(
+                &self.package_name,
+                &self.compression_formats,
+                output,
+            )?;
+        }
         Ok(())
     }
-}
 
-/// Copies the `src` directory recursively to `dst`, writing `manifest.in` too.
-fn copy_and_manifest(src: &Path, dst: &Path, bulk_dirs: &str) -> Result<()> {
-    let mut manifest = create_new_file(dst.join("manifest.in"))?;
-    let bulk_dirs: Vec<_> = bulk_dirs
-        .split(',')
-        .filter(|s| !s.is_empty())
-        .map(Path::new)
-        .collect();
-
-    let mut paths = BTreeSet::new();
-    copy_with_callback(src, dst, |path, file_type| {
-        // We need paths to be compatible with both Unix and Windows.
-        if path
-            .components()
-            .filter_map(|c| c.as_os_str().to_str())
-            .any(|s| s.contains('\\'))
-        {
+    /// Replaces the settings given in the package spec, returning its components
+    fn apply_spec(&mut self) -> Result<Vec<ComponentImage>> {
+        let spec = PackageSpec::load(&self.spec)?;
+        apply_spec!(
+            self,
+            spec,
+            self.given_flags,
+            product_name,
+            package_name,
+            rel_manifest_dir,
+            success_message,
+            legacy_manifest_dirs,
+            non_installed_overlay,
+            work_dir,
+            output_dir,
+            compression_formats,
+            copy_mode,
+            tar_format,
+            dedup,
+            xattrs,
+            sparse,
+            self_extracting,
+        );
+        let mut components = vec![];
+        for spec in &spec.components {
+            let mut component = ComponentImage {
+                name: spec.name.clone(),
+                image_dir: spec.image_dir.clone(),
+                bulk_dirs: spec.bulk_dirs.clone().unwrap_or_default(),
+                include: split_list(&self.include),
+                exclude: split_list(&self.exclude),
+            };
+            apply_spec!(component, spec, self.given_flags, include, exclude);
+            components.push(component);
+        }
+        Ok(components)
+    }
+
+    /// Returns the components given by the flags
+    fn components(&self) -> Result<Vec<ComponentImage>> {
+        if self.component.is_empty() {
+            if !self.component_bulk_dirs.is_empty() {
+                bail!("--component-bulk-dirs is for components given with --component");
+            }
+            return Ok(vec![ComponentImage {
+                name: self.component_name.clone(),
+                image_dir: self.image_dir.clone(),
+                bulk_dirs: split_list(&self.bulk_dirs),
+                include: split_list(&self.include),
+                exclude: split_list(&self.exclude),
+            }]);
+        }
+        let mut bulk_dirs = BTreeMap::new();
+        for pair in self.component_bulk_dirs.chunks(2) {
+            match pair {
+                [name, dirs] => bulk_dirs.insert(name.as_str(), split_list(dirs)),
+                _ => bail!("expected NAME and DIRS for --component-bulk-dirs"),
+            };
+        }
+        let mut components = vec![];
+        for pair in self.component.chunks(2) {
+            match pair {
+                [name, image_dir] if !image_dir.is_empty() => components.push(ComponentImage {
+                    name: name.clone(),
+                    image_dir: image_dir.clone(),
+                    bulk_dirs: bulk_dirs.remove(name.as_str()).unwrap_or_default(),
+                    include: split_list(&self.include),
+                    exclude: split_list(&self.exclude),
+                }),
+                _ => bail!("expected NAME and DIR for --component, got {:?}", pair),
+            }
+        }
+        if let Some(name) = bulk_dirs.keys().next() {
             bail!(
-                "rust-installer doesn't support '\\' in path components: {:?}",
-                path
+                "--component-bulk-dirs is given for '{}', which isn't a --component",
+                name
             );
         }
+        Ok(components)
+    }
 
-        // Normalize to Unix-style path separators.
-        let normalized_string;
-        let mut string = path.to_str().ok_or_else(|| {
-            format_err!(
-                "rust-installer doesn't support non-Unicode paths: {:?}",
-                path
-            )
-        })?;
-        if string.contains('\\') {
-            normalized_string = string.replace('\\', "/");
-            string = &normalized_string;
+    /// Configures a `Scripter` for this installer, without an output script
+    fn scripter(&self) -> Scripter {
+        let mut scripter = Scripter::default();
+        scripter
+            .product_name(self.product_name.clone())
+            .rel_manifest_dir(self.rel_manifest_dir.clone())
+            .success_message(self.success_message.clone())
+            .legacy_manifest_dirs(self.legacy_manifest_dirs.clone());
+        scripter
+    }
+}
+
+/// A component of the package, and the image it's made from
+struct ComponentImage {
+    name: String,
+    image_dir: String,
+    bulk_dirs: Vec<String>,
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+/// Records a path under `root` to be written into the tarball below `prefix`.
+fn add_source(
+    dirs: &mut BTreeMap<PathBuf, Source>,
+    files: &mut BTreeMap<PathBuf, Source>,
+    prefix: &Path,
+    root: &Path,
+    path: &Path,
+    file_type: fs::FileType,
+) -> Result<()> {
+    let name = prefix.join(path);
+    let source = Source::Path(root.join(path));
+    if file_type.is_dir() {
+        dirs.insert(name, source);
+    } else {
+        files.insert(name, source);
+    }
+    Ok(())
+}
+
+/// Copies the paths of the `src` directory selected by `filter` recursively to
+/// `dst`, writing `manifest.in`, `size.in` and `hashes.in` too. With `xattrs`, also lists the
+/// extended attributes in `xattrs.in` and carries over those that can be set on
+/// the copies.
+fn copy_and_manifest(
+    src: &Path,
+    dst: &Path,
+    bulk_dirs: &[String],
+    mode: CopyMode,
+    filter: &PathFilter,
+    xattrs: bool,
+) -> Result<()> {
+    let mut file = create_new_file(dst.join("manifest.in"))?;
+    let mut manifest = Manifest::new(bulk_dirs)?;
+    let mut sizes = InstalledSizes::default();
+    let mut hashes = FileHashes::default();
+    let mut xattr_lines = BTreeSet::new();
+    copy_with_callback(src, dst, mode, filter, |path, file_type| {
+        if xattrs {
+            add_xattr_lines(&mut xattr_lines, src, path)?;
+        }
+        sizes.add(src, path, file_type)?;
+        hashes.add(src, path, file_type)?;
+        manifest.add(path, file_type)
+    })?;
+    manifest.warn_unmatched();
+    file.write_all(manifest.contents().as_bytes())?;
+    create_new_file(dst.join("size.in"))?.write_all(sizes.contents()?.as_bytes())?;
+    create_new_file(dst.join("hashes.in"))?.write_all(hashes.contents().as_bytes())?;
+
+    if !xattr_lines.is_empty() {
+        let mut file = create_new_file(dst.join("xattrs.in"))?;
+        for line in xattr_lines {
+            file.write_all(line.as_bytes())?;
+        }
+        drop(file);
+        apply_xattrs(dst)?;
+    }
+    Ok(())
+}
+
+/// Adds the `xattrs.in` lines for `path`, relative to the image root `root`.
+fn add_xattr_lines(lines: &mut BTreeSet<String>, root: &Path, path: &Path) -> Result<()> {
+    let name = escape_path(path)?;
+    for (attr, value) in read_xattrs(&root.join(path))? {
+        lines.insert(xattr_line(&attr, &value, &name)?);
+    }
+    Ok(())
+}
+
+/// The `manifest.in` directives of a component image.
+struct Manifest<'a> {
+    /// The `--bulk-dirs` patterns, and whether each has matched a path yet.
+    bulk_patterns: Vec<(&'a str, bool)>,
+    bulk_globs: GlobSet,
+    /// The bulk directories found so far.
+    bulk_dirs: Vec<PathBuf>,
+    paths: BTreeSet<String>,
+}
+
+impl<'a> Manifest<'a> {
+    fn new(bulk_dirs: &'a [String]) -> Result<Self> {
+        let bulk_patterns: Vec<_> = bulk_dirs.iter().map(String::as_str).collect();
+        let mut bulk_globs = GlobSetBuilder::new();
+        for pattern in &bulk_patterns {
+            bulk_globs.add(path_glob(pattern, true)?);
         }
+        Ok(Manifest {
+            bulk_patterns: bulk_patterns.into_iter().map(|p| (p, false)).collect(),
+            bulk_globs: bulk_globs.build()?,
+            bulk_dirs: vec![],
+            paths: BTreeSet::new(),
+        })
+    }
+
+    /// Adds the directive for `path`, relative to the image root, if it needs one.
+    fn add(&mut self, path: &Path, file_type: fs::FileType) -> Result<()> {
+        // Normalize to Unix-style path separators, escaping unusual names.
+        let string = escape_path(path)?;
 
         if file_type.is_dir() {
             // Only manifest directories that are explicitly bulk.
-            if bulk_dirs.contains(&path) {
-                paths.insert(format!("dir:{}\n", string));
+            let matches = self.bulk_globs.matches(path);
+            for &i in &matches {
+                self.bulk_patterns[i].1 = true;
+            }
+            if !matches.is_empty() {
+                self.paths.insert(format!("dir:{}\n", string));
+                self.bulk_dirs.push(path.to_owned());
             }
         } else {
-            // Only manifest files that aren't under bulk directories.
-            if !bulk_dirs.iter().any(|d| path.starts_with(d)) {
-                paths.insert(format!("file:{}\n", string));
+            // Only manifest files that aren't under bulk directories. Bulk
+            // patterns only match directories, so files they match are
+            // manifested like the others.
+            if !self.bulk_dirs.iter().any(|d| path.starts_with(d)) {
+                self.paths.insert(format!("file:{}\n", string));
             }
         }
         Ok(())
-    })?;
+    }
 
-    for path in paths {
-        manifest.write_all(path.as_bytes())?;
+    /// Warns about `--bulk-dirs` patterns that matched no directory, likely typos.
+    fn warn_unmatched(&self) {
+        for (pattern, _) in self.bulk_patterns.iter().filter(|(_, matched)| !matched) {
+            eprintln!(
+                "warning: bulk dir pattern '{}' matches no directory",
+                pattern
+            );
+        }
     }
 
-    Ok(())
+    /// Returns the sorted contents of `manifest.in`.
+    fn contents(&self) -> String {
+        self.paths.iter().map(String::as_str).collect()
+    }
+}
+
+/// The space a component takes once installed, for the installer to check
+/// against the free space of the target directories.
+#[derive(Default)]
+struct InstalledSizes {
+    /// Bytes by directory, keeping the first three levels of the image, which
+    /// are enough to tell the directories the installer can redirect apart.
+    dirs: BTreeMap<PathBuf, u64>,
+}
+
+impl InstalledSizes {
+    /// The allocation unit that sizes are rounded up to, as most filesystems use.
+    const BLOCK_SIZE: u64 = 4096;
+
+    /// Adds the size of `path`, relative to the image root `root`.
+    fn add(&mut self, root: &Path, path: &Path, file_type: fs::FileType) -> Result<()> {
+        if !file_type.is_file() {
+            return Ok(());
+        }
+        let len = fs::symlink_metadata(root.join(path))
+            .with_context(|| format!("failed to read metadata of '{}'", path.display()))?
+            .len();
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let dir = dir.components().take(3).collect();
+        *self.dirs.entry(dir).or_default() += len.div_ceil(Self::BLOCK_SIZE) * Self::BLOCK_SIZE;
+        Ok(())
+    }
+
+    /// Returns the contents of `size.in`: a line with the bytes and the
+    /// escaped path of each directory that takes any space.
+    fn contents(&self) -> Result<String> {
+        let mut contents = String::new();
+        for (dir, size) in self.dirs.iter().filter(|(_, &size)| size > 0) {
+            contents.push_str(&format!("{} {}\n", size, escape_path(dir)?));
+        }
+        Ok(contents)
+    }
+}
+
+/// The `hashes.in` lines of a component: the SHA-256 and escaped path of each
+/// file, which the installer records for checking the installation later.
+#[derive(Default)]
+struct FileHashes {
+    lines: BTreeSet<String>,
+}
+
+impl FileHashes {
+    /// Adds the hash of `path`, relative to the image root `root`.
+    fn add(&mut self, root: &Path, path: &Path, file_type: fs::FileType) -> Result<()> {
+        if file_type.is_file() {
+            let hash = sha256_file(root.join(path))?;
+            self.lines
+                .insert(format!("{} {}\n", hash, escape_path(path)?));
+        }
+        Ok(())
+    }
+
+    /// Returns the sorted contents of `hashes.in`.
+    fn contents(&self) -> String {
+        self.lines.iter().map(String::as_str).collect()
+    }
 }
@@ -0,0 +1,277 @@
+use crate::util::*;
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// The manifests an installer leaves in `<libdir>/<rel_manifest_dir>`: the
+/// `components` list and a `manifest-<component>` for each of them.
+pub struct Installation {
+    manifest_dir: PathBuf,
+}
+
+/// A line of the `hashes-<component>` the installer writes from `hashes.in`.
+#[derive(Debug, Clone)]
+pub struct InstalledHash {
+    /// The SHA-256 of the file, in hex.
+    pub hash: String,
+    /// The path of the file within the component image.
+    pub image_path: PathBuf,
+    /// The absolute path the file was installed at.
+    pub path: PathBuf,
+}
+
+/// A line of an installed manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InstalledPath {
+    /// A file, installed at this absolute path.
+    File(PathBuf),
+    /// A bulk directory, installed with everything below it.
+    Dir(PathBuf),
+}
+
+impl InstalledPath {
+    /// Returns whether this entry covers `path`: bulk directories own
+    /// everything below them.
+    pub fn owns(&self, path: &Path) -> bool {
+        match self {
+            InstalledPath::File(file) => file == path,
+            InstalledPath::Dir(dir) => path.starts_with(dir),
+        }
+    }
+}
+
+impl Installation {
+    /// Opens the installation in `prefix`, whose manifests are in
+    /// `<libdir>/<rel_manifest_dir>`, with `libdir` being `<prefix>/lib` if empty.
+    pub fn find(prefix: &str, libdir: &str, rel_manifest_dir: &str) -> Result<Self> {
+        let libdir = if libdir.is_empty() {
+            Path::new(prefix).join("lib")
+        } else {
+            PathBuf::from(libdir)
+        };
+        Installation::open(&libdir.join(rel_manifest_dir))
+    }
+
+    /// Opens the installation whose manifests are in `manifest_dir`.
+    pub fn open(manifest_dir: &Path) -> Result<Self> {
+        if !manifest_dir.join("components").is_file() {
+            bail!(
+                "unable to find installation manifest at '{}'",
+                manifest_dir.display()
+            );
+        }
+        Ok(Installation {
+            manifest_dir: manifest_dir.to_owned(),
+        })
+    }
+
+    /// Returns the names of the installed components, in installation order.
+    pub fn components(&self) -> Result<Vec<String>> {
+        let path = self.manifest_dir.join("components");
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read '{}'", path.display()))?;
+        Ok(contents
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(str::to_owned)
+            .collect())
+    }
+
+    /// Returns the paths installed by `component`.
+    pub fn manifest(&self, component: &str) -> Result<Vec<InstalledPath>> {
+        let path = self.manifest_dir.join(format!("manifest-{}", component));
+        let contents =
+            fs::read(&path).with_context(|| format!("failed to read '{}'", path.display()))?;
+        contents
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                if let Some(file) = line.strip_prefix(b"file:") {
+                    Ok(InstalledPath::File(unescape_path(file)?))
+                } else if let Some(dir) = line.strip_prefix(b"dir:") {
+                    Ok(InstalledPath::Dir(unescape_path(dir)?))
+                } else {
+                    bail!(
+                        "malformed line in '{}': {}",
+                        path.display(),
+                        String::from_utf8_lossy(line)
+                    )
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the hashes recorded for `component`, or `None` if it was
+    /// installed by an installer that didn't record them.
+    pub fn hashes(&self, component: &str) -> Result<Option<Vec<InstalledHash>>> {
+        let path = self.manifest_dir.join(format!("hashes-{}", component));
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents =
+            fs::read(&path).with_context(|| format!("failed to read '{}'", path.display()))?;
+        contents
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let mut fields = line.splitn(3, |&b| b == b'\t');
+                match (fields.next(), fields.next(), fields.next()) {
+                    (Some(hash), Some(image_path), Some(installed)) => Ok(InstalledHash {
+                        hash: String::from_utf8_lossy(hash).into_owned(),
+                        image_path: unescape_path(image_path)?,
+                        path: unescape_path(installed)?,
+                    }),
+                    _ => bail!(
+                        "malformed line in '{}': {}",
+                        path.display(),
+                        String::from_utf8_lossy(line)
+                    ),
+                }
+            })
+            .collect::<Result<_>>()
+            .map(Some)
+    }
+
+    /// Takes the lock install.sh takes while it changes this installation.
+    pub fn lock(&self, timeout: u64) -> Result<ManifestLock> {
+        ManifestLock::acquire(&self.manifest_dir, timeout)
+    }
+}
+
+/// The advisory lock on a manifest directory: the `<manifest dir>.lock`
+/// directory next to it, holding an `owner` file with the host name and pid of
+/// the process that took it. Released when dropped.
+pub struct ManifestLock {
+    dir: PathBuf,
+}
+
+impl ManifestLock {
+    /// Takes the lock like install.sh does, waiting up to `timeout` seconds
+    /// for another installation to finish, and breaking locks that were left
+    /// behind by processes on this host that are gone.
+    fn acquire(manifest_dir: &Path, timeout: u64) -> Result<Self> {
+        let mut dir = manifest_dir.as_os_str().to_owned();
+        dir.push(".lock");
+        let dir = PathBuf::from(dir);
+        let host = host_name();
+        let mut waited = 0;
+        loop {
+            match fs::create_dir(&dir) {
+                Ok(()) => break,
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+                Err(e) => {
+                    return Err(e)
+                        .with_context(|| format!("failed to create lock '{}'", dir.display()))
+                }
+            }
+
+            let owner = fs::read_to_string(dir.join("owner")).unwrap_or_default();
+            let owner = owner.trim();
+            if let Some((owner_host, pid)) = owner.rsplit_once(' ') {
+                let stale = owner_host == host && !process_exists(pid);
+                if stale && break_stale_lock(&dir, owner, pid)? {
+                    continue;
+                }
+            }
+
+            if waited >= timeout {
+                bail!(
+                    "another installation is in progress (lock '{}' held by {})",
+                    dir.display(),
+                    if owner.is_empty() {
+                        "an unknown process"
+                    } else {
+                        owner
+                    }
+                );
+            }
+            if waited == 0 {
+                eprintln!(
+                    "waiting up to {} seconds for another installation to finish",
+                    timeout
+                );
+            }
+            thread::sleep(Duration::from_secs(1));
+            waited += 1;
+        }
+
+        let lock = ManifestLock { dir };
+        fs::write(
+            lock.dir.join("owner"),
+            format!("{} {}\n", host, std::process::id()),
+        )
+        .with_context(|| format!("failed to write lock '{}'", lock.dir.display()))?;
+        Ok(lock)
+    }
+}
+
+/// Removes the lock `dir` if `owner`, whose process `pid` is gone, still holds
+/// it, and returns whether it could check. Processes break locks one at a
+/// time, holding `<dir>.break`, so no other process can take the lock between
+/// the check and the removal.
+fn break_stale_lock(dir: &Path, owner: &str, pid: &str) -> Result<bool> {
+    let mut guard = dir.as_os_str().to_owned();
+    guard.push(".break");
+    let guard = PathBuf::from(guard);
+    match fs::create_dir(&guard) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => return Ok(false),
+        Err(e) => {
+            return Err(e).with_context(|| format!("failed to break lock '{}'", dir.display()))
+        }
+    }
+    let current_owner = fs::read_to_string(dir.join("owner")).unwrap_or_default();
+    let removed = if current_owner.trim() == owner {
+        eprintln!(
+            "removing stale lock '{}' left by process {}",
+            dir.display(),
+            pid
+        );
+        remove_dir_all(dir)
+    } else {
+        Ok(())
+    };
+    let _ = fs::remove_dir(&guard);
+    removed.map(|()| true)
+}
+
+impl Drop for ManifestLock {
+    fn drop(&mut self) {
+        let _ = remove_dir_all::remove_dir_all(&self.dir);
+    }
+}
+
+/// Returns the host name, as `uname -n` prints it.
+#[cfg(unix)]
+fn host_name() -> String {
+    let mut name: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut name) } != 0 {
+        return String::new();
+    }
+    let nodename = unsafe { std::ffi::CStr::from_ptr(name.nodename.as_ptr()) };
+    nodename.to_string_lossy().into_owned()
+}
+
+#[cfg(not(unix))]
+fn host_name() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_default()
+}
+
+/// Returns whether the process with the given pid is still running.
+#[cfg(unix)]
+fn process_exists(pid: &str) -> bool {
+    match pid.parse::<libc::pid_t>() {
+        Ok(pid) if pid > 0 => {
+            let signaled = unsafe { libc::kill(pid, 0) } == 0;
+            signaled || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+        }
+        _ => true,
+    }
+}
+
+#[cfg(not(unix))]
+fn process_exists(_pid: &str) -> bool {
+    true
+}
@@ -1,15 +1,34 @@
 #[macro_use]
 mod util;
+#[macro_use]
+mod spec;
 
 mod combiner;
 mod compression;
+mod deb;
+mod delta;
 mod generator;
+mod installed;
+mod oci;
+mod package;
+mod pacman;
+mod query;
+mod repair;
+mod rpm;
 mod scripter;
+mod status;
 mod tarballer;
 
 pub use crate::combiner::Combiner;
+pub use crate::deb::Deb;
+pub use crate::delta::Delta;
 pub use crate::generator::Generator;
+pub use crate::pacman::Pacman;
+pub use crate::query::Query;
+pub use crate::repair::Repair;
+pub use crate::rpm::Rpm;
 pub use crate::scripter::Scripter;
+pub use crate::status::Status;
 pub use crate::tarballer::Tarballer;
 
 /// The installer version, output only to be used by combine-installers.sh.
@@ -1,5 +1,6 @@
 use anyhow::{Context, Result};
-use clap::{self, Parser};
+use clap::{self, CommandFactory, FromArgMatches, Parser};
+use std::collections::BTreeSet;
 
 #[derive(Parser)]
 struct CommandLine {
@@ -11,16 +12,57 @@ struct CommandLine {
 enum Subcommand {
     Generate(installer::Generator),
     Combine(installer::Combiner),
+    Deb(installer::Deb),
+    Delta(installer::Delta),
+    Pacman(installer::Pacman),
+    Query(installer::Query),
+    Repair(installer::Repair),
+    Rpm(installer::Rpm),
     Script(installer::Scripter),
+    Status(installer::Status),
     Tarball(installer::Tarballer),
 }
 
+/// Returns the flags of the subcommand that were given on the command line.
+fn given_flags(command: &clap::Command, matches: &clap::ArgMatches) -> BTreeSet<String> {
+    let mut given = BTreeSet::new();
+    if let Some((name, matches)) = matches.subcommand() {
+        let command = command.find_subcommand(name).expect("parsed subcommand exists");
+        for id in command.get_arguments().map(|arg| arg.get_id()) {
+            // Skips --help and --version, which clap doesn't record.
+            if matches.try_contains_id(id).unwrap_or(false)
+                && matches.value_source(id) == Some(clap::ValueSource::CommandLine)
+            {
+                // By the name of the field, which the id is the kebab-case of.
+                given.insert(id.replace('-', "_"));
+            }
+        }
+    }
+    given
+}
+
 fn main() -> Result<()> {
-    let command_line = CommandLine::parse();
+    let command = CommandLine::command();
+    let matches = command.clone().get_matches();
+    let command_line = CommandLine::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let given = given_flags(&command, &matches);
     match command_line.command {
-        Subcommand::Combine(combiner) => combiner.run().context("failed to combine installers")?,
-        Subcommand::Generate(generator) => generator.run().context("failed to generate installer")?,
+        Subcommand::Combine(mut combiner) => {
+            combiner.given_flags(given);
+            combiner.run().context("failed to combine installers")?
+        }
+        Subcommand::Deb(deb) => deb.run().context("failed to build Debian package")?,
+        Subcommand::Delta(delta) => delta.run().context("failed to make delta installer")?,
+        Subcommand::Generate(mut generator) => {
+            generator.given_flags(given);
+            generator.run().context("failed to generate installer")?
+        }
+        Subcommand::Pacman(pacman) => pacman.run().context("failed to build Arch Linux package")?,
+        Subcommand::Query(query) => query.run().context("failed to query installation")?,
+        Subcommand::Repair(repair) => repair.run().context("failed to repair installation")?,
+        Subcommand::Rpm(rpm) => rpm.run().context("failed to build RPM package")?,
         Subcommand::Script(scripter) => scripter.run().context("failed to generate installation script")?,
+        Subcommand::Status(status) => status.run().context("failed to check installation")?,
         Subcommand::Tarball(tarballer) => tarballer.run().context("failed to generate tarballs")?,
     }
     Ok(())
@@ -0,0 +1,214 @@
+use crate::compression::CompressionFormat;
+use crate::package::{parent_dirs, relative, timestamp, Layout, PackageTar, UnpackedInstaller};
+use crate::util::*;
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+const INDEX_MEDIA_TYPE: &str = "application/vnd.oci.image.index.v1+json";
+const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+const CONFIG_MEDIA_TYPE: &str = "application/vnd.oci.image.config.v1+json";
+const LAYER_MEDIA_TYPE: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
+
+/// A reference to a blob of the image layout, by its digest.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    media_type: &'static str,
+    digest: String,
+    size: u64,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    annotations: BTreeMap<&'static str, String>,
+}
+
+/// The `index.json` at the root of the layout, listing its images.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Index {
+    schema_version: u32,
+    media_type: &'static str,
+    manifests: Vec<Descriptor>,
+}
+
+/// The manifest of an image, pointing at its config and layers.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    schema_version: u32,
+    media_type: &'static str,
+    config: Descriptor,
+    layers: Vec<Descriptor>,
+}
+
+/// The config of an image, with the digests of its uncompressed layers.
+#[derive(Serialize)]
+struct ImageConfig {
+    architecture: String,
+    os: &'static str,
+    rootfs: RootFs,
+}
+
+#[derive(Serialize)]
+struct RootFs {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    diff_ids: Vec<String>,
+}
+
+/// Writes an OCI image layout into `dir`, holding a single image tagged
+/// `ref_name` whose one layer has the paths `installer` installs with `layout`.
+pub(crate) fn write_image_layout(
+    installer: &UnpackedInstaller,
+    layout: &Layout,
+    architecture: &str,
+    ref_name: &str,
+    dir: &Path,
+) -> Result<()> {
+    let paths = installer.installed_paths(layout)?;
+    let mtime = timestamp()?;
+    if dir.exists() {
+        remove_dir_all(dir)?;
+    }
+    let blobs = dir.join("blobs").join("sha256");
+    create_dir_all(&blobs)?;
+
+    // The layer holds the installed paths with the directories above them.
+    // The config identifies it by the digest of the uncompressed tarball.
+    let layer_tar = dir.join("layer.tar");
+    let mut tar = PackageTar::new(
+        HashingWriter {
+            writer: CompressionFormat::Gz.encode(&layer_tar)?,
+            hasher: Sha256::new(),
+        },
+        mtime,
+    );
+    let parents = parent_dirs(paths.keys());
+    for parent in &parents {
+        tar.append_dir(&relative(parent))?;
+    }
+    for (path, installed) in &paths {
+        if !parents.contains(path) {
+            tar.append_installed(&relative(path), installed)?;
+        }
+    }
+    let HashingWriter { writer, hasher } = tar.into_inner()?;
+    writer.finish()?;
+    let diff_id = format!("sha256:{}", to_hex(&hasher.finalize()));
+    let layer = add_file_blob(&blobs, &dir.join("layer.tar.gz"), LAYER_MEDIA_TYPE)?;
+
+    let config = ImageConfig {
+        architecture: architecture.to_owned(),
+        os: "linux",
+        rootfs: RootFs {
+            kind: "layers",
+            diff_ids: vec![diff_id],
+        },
+    };
+    let config = add_json_blob(&blobs, &config, CONFIG_MEDIA_TYPE)?;
+    let manifest = Manifest {
+        schema_version: 2,
+        media_type: MANIFEST_MEDIA_TYPE,
+        config,
+        layers: vec![layer],
+    };
+    let mut manifest = add_json_blob(&blobs, &manifest, MANIFEST_MEDIA_TYPE)?;
+    manifest
+        .annotations
+        .insert("org.opencontainers.image.ref.name", ref_name.to_owned());
+
+    let index = Index {
+        schema_version: 2,
+        media_type: INDEX_MEDIA_TYPE,
+        manifests: vec![manifest],
+    };
+    write_file(&dir.join("index.json"), &serde_json::to_vec(&index)?)?;
+    write_file(
+        &dir.join("oci-layout"),
+        br#"{"imageLayoutVersion":"1.0.0"}"#,
+    )
+}
+
+/// Moves the file at `path` into the blobs, named by its digest.
+fn add_file_blob(blobs: &Path, path: &Path, media_type: &'static str) -> Result<Descriptor> {
+    let hash = sha256_file(path)?;
+    let size = fs::metadata(path)
+        .with_context(|| format!("failed to read metadata of '{}'", path.display()))?
+        .len();
+    let blob = blobs.join(&hash);
+    fs::rename(path, &blob).with_context(|| {
+        format!(
+            "failed to move '{}' to '{}'",
+            path.display(),
+            blob.display()
+        )
+    })?;
+    Ok(Descriptor {
+        media_type,
+        digest: format!("sha256:{}", hash),
+        size,
+        annotations: BTreeMap::new(),
+    })
+}
+
+/// Writes `value` as JSON into the blobs, named by its digest.
+fn add_json_blob<T: Serialize>(
+    blobs: &Path,
+    value: &T,
+    media_type: &'static str,
+) -> Result<Descriptor> {
+    let json = serde_json::to_vec(value)?;
+    let hash = to_hex(&Sha256::digest(&json));
+    write_file(&blobs.join(&hash), &json)?;
+    Ok(Descriptor {
+        media_type,
+        digest: format!("sha256:{}", hash),
+        size: json.len() as u64,
+        annotations: BTreeMap::new(),
+    })
+}
+
+fn write_file(path: &Path, contents: &[u8]) -> Result<()> {
+    create_new_file(path)?
+        .write_all(contents)
+        .with_context(|| format!("failed to write '{}'", path.display()))
+}
+
+/// Hashes what's written through it.
+struct HashingWriter<W: Write> {
+    writer: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Returns the OCI name of the architecture this was built for.
+pub(crate) fn host_architecture() -> Result<&'static str> {
+    Ok(match host_arch() {
+        "x86_64" => "amd64",
+        "x86" => "386",
+        "aarch64" => "arm64",
+        "arm" => "arm",
+        "powerpc64le" => "ppc64le",
+        "riscv64" => "riscv64",
+        "s390x" => "s390x",
+        "loongarch64" => "loong64",
+        other => bail!(
+            "no OCI architecture known for '{}', give one with --oci-architecture",
+            other
+        ),
+    })
+}
@@ -0,0 +1,753 @@
+use crate::compression::CompressionFormat;
+use crate::util::*;
+use anyhow::{bail, format_err, Context, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, symlink_metadata};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use tar::{Archive, Builder, EntryType, Header};
+use walkdir::WalkDir;
+
+/// An installer package directory, usually unpacked from a tarball into a
+/// work directory, to be combined with others or turned into a native package.
+pub(crate) struct UnpackedInstaller {
+    /// The package directory, `<work dir>/<package name>`.
+    pub(crate) dir: PathBuf,
+    /// The components of the installer, in installation order.
+    pub(crate) components: Vec<String>,
+}
+
+impl UnpackedInstaller {
+    /// Unpacks `input_tarball` into `work_dir`, checking its entries, its
+    /// installer version and its components.
+    pub(crate) fn unpack(input_tarball: &str, work_dir: &Path) -> Result<Self> {
+        let compression = CompressionFormat::detect_from_path(input_tarball)
+            .ok_or_else(|| format_err!("couldn't figure out the format of {}", input_tarball))?;
+        let pkg_name = input_tarball.trim_end_matches(&format!(".tar.{}", compression.extension()));
+        let pkg_name = Path::new(pkg_name).file_name().unwrap();
+        let pkg_dir = work_dir.join(pkg_name);
+
+        // The input tarballs are untrusted, so every entry is validated
+        // before it's written into the work directory.
+        let archive = Archive::new(compression.decode(input_tarball)?);
+        unpack_checked(archive, work_dir, Path::new(pkg_name)).with_context(|| {
+            format!(
+                "unable to extract '{}' into '{}'",
+                &input_tarball,
+                work_dir.display()
+            )
+        })?;
+        Self::open(pkg_dir, input_tarball)
+    }
+
+    /// Opens the installer package directory `pkg_dir`, checking its
+    /// installer version and its components, which come from `origin`.
+    pub(crate) fn open(pkg_dir: PathBuf, origin: &str) -> Result<Self> {
+        // Verify the version number.
+        let mut version = String::new();
+        open_file(pkg_dir.join("rust-installer-version"))
+            .and_then(|mut file| Ok(file.read_to_string(&mut version)?))
+            .with_context(|| format!("failed to read version in '{}'", origin))?;
+        if version.trim().parse() != Ok(crate::RUST_INSTALLER_VERSION) {
+            bail!("incorrect installer version in {}", origin);
+        }
+
+        // Read and check the components.
+        let mut pkg_components = String::new();
+        open_file(pkg_dir.join("components"))
+            .and_then(|mut file| Ok(file.read_to_string(&mut pkg_components)?))
+            .with_context(|| format!("failed to read components in '{}'", origin))?;
+        let mut components = vec![];
+        for component in pkg_components.lines().filter(|c| !c.is_empty()) {
+            check_component_name(component)
+                .with_context(|| format!("invalid component in '{}'", origin))?;
+            if !symlink_metadata(pkg_dir.join(component))
+                .map(|m| m.is_dir())
+                .unwrap_or(false)
+            {
+                bail!(
+                    "component '{}' is not a directory in '{}'",
+                    component,
+                    origin
+                );
+            }
+            components.push(component.to_owned());
+        }
+
+        Ok(UnpackedInstaller {
+            dir: pkg_dir,
+            components,
+        })
+    }
+
+    /// Returns the name of the package, which the tarball unpacks into.
+    pub(crate) fn name(&self) -> &str {
+        self.dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default()
+    }
+
+    /// Returns the paths install.sh would install with `layout`, by their
+    /// installed path. Files of a later component replace those of an
+    /// earlier one, like they do when installing.
+    pub(crate) fn installed_paths(
+        &self,
+        layout: &Layout,
+    ) -> Result<BTreeMap<PathBuf, InstalledPath>> {
+        let mut paths = BTreeMap::new();
+        for component in &self.components {
+            for image_path in self.component_paths(component)? {
+                // Bulk directories go where the directory itself goes.
+                let path = match &image_path.bulk_dir {
+                    Some(dir) => {
+                        let path = layout.install_path(dir);
+                        let rel = image_path.path.strip_prefix(dir)?;
+                        if rel.as_os_str().is_empty() {
+                            path
+                        } else {
+                            path.join(rel)
+                        }
+                    }
+                    None => layout.install_path(&image_path.path),
+                };
+                insert_path(
+                    &mut paths,
+                    component,
+                    path,
+                    image_path.source,
+                    image_path.kind,
+                );
+            }
+        }
+        Ok(paths)
+    }
+
+    /// Returns the paths of the image of `component` that install.sh
+    /// installs, in the order of its manifest.
+    pub(crate) fn component_paths(&self, component: &str) -> Result<Vec<ImagePath>> {
+        let component_dir = self.dir.join(component);
+        let manifest = component_dir.join("manifest.in");
+        let contents = fs::read(&manifest)
+            .with_context(|| format!("failed to read '{}'", manifest.display()))?;
+        let mut paths = vec![];
+        for line in contents.split(|&b| b == b'\n').filter(|l| !l.is_empty()) {
+            let (bulk, file) = if let Some(file) = line.strip_prefix(b"file:") {
+                (false, file)
+            } else if let Some(dir) = line.strip_prefix(b"dir:") {
+                (true, dir)
+            } else {
+                bail!(
+                    "malformed line in '{}': {}",
+                    manifest.display(),
+                    String::from_utf8_lossy(line)
+                );
+            };
+            let file = unescape_path(file)?;
+            if !file.components().all(|c| matches!(c, Component::Normal(_))) {
+                bail!(
+                    "unsafe path in '{}': '{}'",
+                    manifest.display(),
+                    file.display()
+                );
+            }
+            let source = component_dir.join(&file);
+
+            if !bulk {
+                // install.sh copies files with `cp`, following symlinks.
+                let stat = fs::metadata(&source)
+                    .with_context(|| format!("failed to read '{}'", source.display()))?;
+                let executable = file.starts_with("bin") || is_executable(&stat);
+                let kind = InstalledKind::File {
+                    mode: if executable { 0o755 } else { 0o644 },
+                    size: stat.len(),
+                };
+                paths.push(ImagePath {
+                    path: file,
+                    bulk_dir: None,
+                    source,
+                    kind,
+                });
+                continue;
+            }
+
+            // Bulk directories are copied with `cp -R`, keeping symlinks.
+            for entry in WalkDir::new(&source).sort_by(|a, b| a.file_name().cmp(b.file_name())) {
+                let entry = entry
+                    .with_context(|| format!("failed to walk directory '{}'", source.display()))?;
+                let stat = entry
+                    .metadata()
+                    .with_context(|| format!("failed to read '{}'", entry.path().display()))?;
+                let kind = if stat.is_dir() {
+                    InstalledKind::Dir
+                } else if stat.file_type().is_symlink() {
+                    InstalledKind::Symlink(fs::read_link(entry.path()).with_context(|| {
+                        format!("failed to read symlink '{}'", entry.path().display())
+                    })?)
+                } else {
+                    let executable = is_executable(&stat);
+                    InstalledKind::File {
+                        mode: if executable { 0o755 } else { 0o644 },
+                        size: stat.len(),
+                    }
+                };
+                paths.push(ImagePath {
+                    path: entry.path().strip_prefix(&component_dir)?.to_owned(),
+                    bulk_dir: Some(file.clone()),
+                    source: entry.into_path(),
+                    kind,
+                });
+            }
+        }
+        Ok(paths)
+    }
+}
+
+/// Records an installed path, warning if it replaces another component's.
+fn insert_path(
+    paths: &mut BTreeMap<PathBuf, InstalledPath>,
+    component: &str,
+    path: PathBuf,
+    source: PathBuf,
+    kind: InstalledKind,
+) {
+    let installed = InstalledPath {
+        component: component.to_owned(),
+        source,
+        kind,
+    };
+    if let Some(old) = paths.insert(path.clone(), installed) {
+        if old.component != component && !matches!(old.kind, InstalledKind::Dir) {
+            eprintln!(
+                "warning: '{}' of component '{}' replaces that of component '{}'",
+                path.display(),
+                component,
+                old.component
+            );
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(stat: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    stat.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_stat: &fs::Metadata) -> bool {
+    false
+}
+
+/// Where install.sh puts the paths of a component image, with the default
+/// `bindir`, `libdir`, `datadir` and `mandir` of a prefix.
+pub(crate) struct Layout {
+    prefix: PathBuf,
+    sysconfdir: PathBuf,
+}
+
+impl Layout {
+    /// Returns the layout for the absolute `prefix`, with configuration files
+    /// in `sysconfdir`, or if that's empty in `/etc` for the `/usr` prefix and
+    /// in `<prefix>/etc` otherwise.
+    pub(crate) fn new(prefix: &str, sysconfdir: &str) -> Result<Self> {
+        let prefix = PathBuf::from(prefix);
+        let sysconfdir = if !sysconfdir.is_empty() {
+            PathBuf::from(sysconfdir)
+        } else if prefix == Path::new("/usr") {
+            PathBuf::from("/etc")
+        } else {
+            prefix.join("etc")
+        };
+        for dir in [&prefix, &sysconfdir] {
+            if !dir.is_absolute() {
+                bail!("'{}' is not an absolute path", dir.display());
+            }
+        }
+        Ok(Layout { prefix, sysconfdir })
+    }
+
+    /// Returns the absolute path that `file` of a component image is installed at.
+    pub(crate) fn install_path(&self, file: &Path) -> PathBuf {
+        match file.strip_prefix("etc") {
+            Ok(rest) => self.sysconfdir.join(rest),
+            Err(_) => self.prefix.join(file),
+        }
+    }
+
+    /// Returns whether an installed path is a configuration file.
+    pub(crate) fn is_config(&self, path: &Path) -> bool {
+        path.starts_with(&self.sysconfdir)
+    }
+}
+
+/// A path installed by a component.
+pub(crate) struct InstalledPath {
+    pub(crate) component: String,
+    /// Where it is in the unpacked installer.
+    pub(crate) source: PathBuf,
+    pub(crate) kind: InstalledKind,
+}
+
+/// A path of a component image that install.sh installs.
+pub(crate) struct ImagePath {
+    /// The path relative to the component directory.
+    pub(crate) path: PathBuf,
+    /// The bulk directory of the manifest it's in, if it's in one.
+    pub(crate) bulk_dir: Option<PathBuf>,
+    /// Where it is in the unpacked installer.
+    pub(crate) source: PathBuf,
+    pub(crate) kind: InstalledKind,
+}
+
+/// What kind of path is installed, with the mode install.sh gives files.
+pub(crate) enum InstalledKind {
+    Dir,
+    File { mode: u32, size: u64 },
+    Symlink(PathBuf),
+}
+
+/// Returns `path` relative to the root, as it's stored in package tarballs.
+pub(crate) fn relative(path: &Path) -> PathBuf {
+    path.strip_prefix("/").unwrap_or(path).to_owned()
+}
+
+/// Returns the directories above the `paths`, up to but not including the root.
+pub(crate) fn parent_dirs<'a>(paths: impl IntoIterator<Item = &'a PathBuf>) -> BTreeSet<PathBuf> {
+    let mut dirs = BTreeSet::new();
+    for path in paths {
+        for parent in path.ancestors().skip(1) {
+            if parent.parent().is_none() || !dirs.insert(parent.to_owned()) {
+                break;
+            }
+        }
+    }
+    dirs
+}
+
+/// Returns the time to stamp generated packages with: `SOURCE_DATE_EPOCH` if
+/// it's set, for reproducible packages, or else the current time.
+pub(crate) fn timestamp() -> Result<u64> {
+    match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch
+            .trim()
+            .parse()
+            .with_context(|| format!("invalid SOURCE_DATE_EPOCH '{}'", epoch)),
+        Err(_) => Ok(std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())),
+    }
+}
+
+/// Writes the tarballs inside native packages: every entry is owned by root
+/// and stamped with the same time, so only the contents and modes of the
+/// installed paths matter.
+pub(crate) struct PackageTar<W: Write> {
+    builder: Builder<W>,
+    mtime: u64,
+}
+
+impl<W: Write> PackageTar<W> {
+    pub(crate) fn new(writer: W, mtime: u64) -> Self {
+        PackageTar {
+            builder: Builder::new(writer),
+            mtime,
+        }
+    }
+
+    fn header(&self, entry_type: EntryType, mode: u32, size: u64) -> Header {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_mode(mode);
+        header.set_size(size);
+        header.set_mtime(self.mtime);
+        header.set_uid(0);
+        header.set_gid(0);
+        if let Some(gnu) = header.as_gnu_mut() {
+            gnu.set_username("root").ok();
+            gnu.set_groupname("root").ok();
+        }
+        header
+    }
+
+    /// Appends a directory.
+    pub(crate) fn append_dir(&mut self, name: &Path) -> Result<()> {
+        let mut header = self.header(EntryType::Directory, 0o755, 0);
+        self.builder
+            .append_data(&mut header, name, std::io::empty())
+            .with_context(|| format!("failed to tar '{}'", name.display()))
+    }
+
+    /// Appends a file with the given contents.
+    pub(crate) fn append_data(&mut self, name: &Path, mode: u32, data: &[u8]) -> Result<()> {
+        let mut header = self.header(EntryType::Regular, mode, data.len() as u64);
+        self.builder
+            .append_data(&mut header, name, data)
+            .with_context(|| format!("failed to tar '{}'", name.display()))
+    }
+
+    /// Appends an installed path.
+    pub(crate) fn append_installed(
+        &mut self,
+        name: &Path,
+        installed: &InstalledPath,
+    ) -> Result<()> {
+        match &installed.kind {
+            InstalledKind::Dir => self.append_dir(name),
+            InstalledKind::File { mode, size } => {
+                let mut header = self.header(EntryType::Regular, *mode, *size);
+                self.builder
+                    .append_data(&mut header, name, open_file(&installed.source)?)
+                    .with_context(|| format!("failed to tar '{}'", installed.source.display()))
+            }
+            InstalledKind::Symlink(target) => {
+                let mut header = self.header(EntryType::Symlink, 0o777, 0);
+                self.builder
+                    .append_link(&mut header, name, target)
+                    .with_context(|| format!("failed to tar '{}'", installed.source.display()))
+            }
+        }
+    }
+
+    /// Finishes the tarball, returning the writer.
+    pub(crate) fn into_inner(self) -> Result<W> {
+        self.builder
+            .into_inner()
+            .context("failed to finish writing .tar stream")
+    }
+}
+
+/// A file stored in the pax 1.0 sparse format, as the tarballer writes them
+/// with `--sparse`, which the tar crate doesn't decode. The entry has a
+/// placeholder path, and its data is a map of the segments of the file that
+/// aren't holes, followed by those segments.
+pub(crate) struct SparseFile {
+    /// The path of the file in the tarball.
+    pub(crate) path: PathBuf,
+    /// The size of the file, holes included.
+    pub(crate) size: u64,
+}
+
+impl SparseFile {
+    /// Returns the sparse file `entry` stores, if it's in the sparse format.
+    pub(crate) fn of_entry<R: Read>(entry: &mut tar::Entry<'_, R>) -> Result<Option<Self>> {
+        let mut records = BTreeMap::new();
+        if let Some(extensions) = entry.pax_extensions()? {
+            for extension in extensions {
+                let extension = extension?;
+                if let Ok(key) = extension.key() {
+                    if key.starts_with("GNU.sparse.") {
+                        records.insert(key.to_owned(), extension.value_bytes().to_vec());
+                    }
+                }
+            }
+        }
+        if records.is_empty() {
+            return Ok(None);
+        }
+        let record = |key: &str| records.get(key).map(Vec::as_slice);
+        let placeholder = entry.path()?.into_owned();
+        if record("GNU.sparse.major") != Some(b"1") || record("GNU.sparse.minor") != Some(b"0") {
+            bail!(
+                "'{}' is a sparse file in a format other than pax 1.0",
+                placeholder.display()
+            );
+        }
+        if entry.header().entry_type() != EntryType::Regular {
+            bail!(
+                "sparse file '{}' is not a regular file",
+                placeholder.display()
+            );
+        }
+        let (name, size) = match (record("GNU.sparse.name"), record("GNU.sparse.realsize")) {
+            (Some(name), Some(size)) => (name, size),
+            _ => bail!(
+                "sparse file '{}' lacks its name or size",
+                placeholder.display()
+            ),
+        };
+        let size = std::str::from_utf8(size)
+            .ok()
+            .and_then(|size| size.parse().ok())
+            .ok_or_else(|| {
+                format_err!("invalid size of sparse file '{}'", placeholder.display())
+            })?;
+        Ok(Some(SparseFile {
+            path: path_from_bytes(name.to_vec())?,
+            size,
+        }))
+    }
+
+    /// Writes the file from the `data` of its entry into `file`, leaving the
+    /// holes unwritten.
+    pub(crate) fn write(&self, data: &mut dyn Read, file: &mut fs::File) -> Result<()> {
+        let mut map_len = 0;
+        let mut read_number = |data: &mut dyn Read| -> Result<u64> {
+            let mut digits = vec![];
+            loop {
+                let mut byte = [0];
+                data.read_exact(&mut byte)
+                    .context("sparse file map is truncated")?;
+                map_len += 1;
+                match byte[0] {
+                    b'\n' => break,
+                    b'0'..=b'9' if digits.len() < 20 => digits.push(byte[0]),
+                    _ => bail!("malformed sparse file map"),
+                }
+            }
+            std::str::from_utf8(&digits)?
+                .parse()
+                .context("malformed sparse file map")
+        };
+        let count = read_number(data)?;
+        let mut segments = vec![];
+        for _ in 0..count {
+            let offset = read_number(data)?;
+            let len = read_number(data)?;
+            segments.push((offset, len));
+        }
+        // The map is padded to a whole block.
+        let padding = (512 - map_len % 512) % 512;
+        io::copy(&mut data.take(padding), &mut io::sink())?;
+
+        let mut end = 0;
+        for (offset, len) in segments {
+            if offset < end || offset.checked_add(len).map_or(true, |e| e > self.size) {
+                bail!(
+                    "sparse file map of '{}' is out of order or past its end",
+                    self.path.display()
+                );
+            }
+            file.seek(SeekFrom::Start(offset))?;
+            if io::copy(&mut data.take(len), file)? != len {
+                bail!("sparse file '{}' is truncated", self.path.display());
+            }
+            end = offset + len;
+        }
+        file.set_len(self.size)?;
+        Ok(())
+    }
+}
+
+/// Unpacks an untrusted installer tarball into `dst`. Every entry must be a
+/// plain file, directory, symlink or hardlink beneath `pkg_name`, and links
+/// may not point outside of it, even through other symlinks.
+fn unpack_checked<R: Read>(mut archive: Archive<R>, dst: &Path, pkg_name: &Path) -> Result<()> {
+    let mut symlinks = BTreeMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let sparse = SparseFile::of_entry(&mut entry)?;
+        let path = match &sparse {
+            Some(sparse) => sparse.path.clone(),
+            None => entry.path()?.into_owned(),
+        };
+        check_entry_path(&path, pkg_name, &symlinks)?;
+        if symlinks.contains_key(&path) {
+            bail!(
+                "'{}' is in the tarball twice, once as a symlink",
+                path.display()
+            );
+        }
+
+        match entry.header().entry_type() {
+            EntryType::Regular | EntryType::Continuous | EntryType::Directory => {}
+            EntryType::XGlobalHeader => continue,
+            EntryType::Symlink => {
+                let target = entry.link_name()?.unwrap_or_default().into_owned();
+                check_symlink_target(&path, &target, pkg_name, &symlinks)?;
+                symlinks.insert(path.clone(), target);
+            }
+            EntryType::Link => {
+                let target = entry.link_name()?.unwrap_or_default().into_owned();
+                check_entry_path(&target, pkg_name, &symlinks)
+                    .with_context(|| format!("invalid hardlink target for '{}'", path.display()))?;
+                // A hardlink to a symlink is a copy of it, which has to lead
+                // inside the package from its own place too.
+                if let Some(link_target) = symlinks.get(&target).cloned() {
+                    check_symlink_target(&path, &link_target, pkg_name, &symlinks)?;
+                    symlinks.insert(path.clone(), link_target);
+                }
+
+                // Unlike files, hardlinks can't be unpacked over a stale copy
+                // left in the work directory by an earlier run.
+                let stale = dst.join(&path);
+                if symlink_metadata(&stale).is_ok_and(|m| !m.is_dir()) {
+                    remove_file(&stale)?;
+                }
+            }
+            other => bail!(
+                "unsupported entry type {:?} for '{}'",
+                other,
+                path.display()
+            ),
+        }
+
+        if let Some(sparse) = sparse {
+            unpack_sparse(&mut entry, &sparse, &dst.join(&path))?;
+            continue;
+        }
+        if !entry.unpack_in(dst)? {
+            bail!("refusing to extract '{}'", path.display());
+        }
+    }
+
+    // A symlink can point through one that comes later in the tarball, so
+    // they're all checked again once they're all known.
+    for (path, target) in &symlinks {
+        if let Err(err) = check_symlink_target(path, target, pkg_name, &symlinks) {
+            remove_file(dst.join(path))?;
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+/// Unpacks the sparse file of `entry` at `dst`, with the mode of the entry.
+fn unpack_sparse<R: Read>(
+    entry: &mut tar::Entry<'_, R>,
+    sparse: &SparseFile,
+    dst: &Path,
+) -> Result<()> {
+    if let Some(parent) = dst.parent() {
+        create_dir_all(parent)?;
+    }
+    if symlink_metadata(dst).is_ok() {
+        remove_file(dst)?;
+    }
+    let mut file = create_new_file(dst)?;
+    sparse
+        .write(entry, &mut file)
+        .with_context(|| format!("failed to unpack '{}'", dst.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = entry.header().mode()? & 0o777;
+        file.set_permissions(fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+/// Checks that an entry path is relative, beneath `pkg_name`, and doesn't
+/// pass through one of the `symlinks` already extracted.
+fn check_entry_path(
+    path: &Path,
+    pkg_name: &Path,
+    symlinks: &BTreeMap<PathBuf, PathBuf>,
+) -> Result<()> {
+    if !path
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        bail!("unsafe path in tarball: '{}'", path.display());
+    }
+    if !path.starts_with(pkg_name) {
+        bail!(
+            "path '{}' is outside of the package directory '{}'",
+            path.display(),
+            pkg_name.display()
+        );
+    }
+    if let Some(link) = path.ancestors().skip(1).find(|a| symlinks.contains_key(*a)) {
+        bail!(
+            "path '{}' is beneath the symlink '{}'",
+            path.display(),
+            link.display()
+        );
+    }
+    Ok(())
+}
+
+/// Checks that the relative `target` of the symlink at `path` resolves to
+/// somewhere strictly inside `pkg_name`, following the `symlinks` it passes
+/// through, and without leaving the package on the way.
+fn check_symlink_target(
+    path: &Path,
+    target: &Path,
+    pkg_name: &Path,
+    symlinks: &BTreeMap<PathBuf, PathBuf>,
+) -> Result<()> {
+    resolve_symlink(path, target, pkg_name, symlinks, 0).with_context(|| {
+        format!(
+            "symlink '{}' points outside of the package: '{}'",
+            path.display(),
+            target.display()
+        )
+    })?;
+    Ok(())
+}
+
+/// Returns where the symlink at `path` leads, like `check_symlink_target`.
+/// `depth` counts the symlinks followed to get there, to stop on loops.
+fn resolve_symlink(
+    path: &Path,
+    target: &Path,
+    pkg_name: &Path,
+    symlinks: &BTreeMap<PathBuf, PathBuf>,
+    depth: usize,
+) -> Result<PathBuf> {
+    if depth > 40 {
+        bail!("too many levels of symlinks");
+    }
+    let mut resolved = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    for component in target.components() {
+        match component {
+            Component::Normal(c) => {
+                resolved.push(c);
+                if !resolved.starts_with(pkg_name) {
+                    bail!("target escapes the package");
+                }
+                if let Some(next) = symlinks.get(&resolved) {
+                    resolved = resolve_symlink(&resolved, next, pkg_name, symlinks, depth + 1)?;
+                }
+            }
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !resolved.pop() {
+                    bail!("target escapes the package");
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => bail!("target is absolute"),
+        }
+    }
+    if resolved == pkg_name || !resolved.starts_with(pkg_name) {
+        bail!("target escapes the package");
+    }
+    Ok(resolved)
+}
+
+/// Checks that a component name is safe to use as a directory name, and in
+/// the lists of install.sh.
+pub(crate) fn check_component_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || name == "."
+        || name == ".."
+        || name.contains(|c: char| c == '/' || c == '\\' || c == ',' || c.is_whitespace())
+    {
+        bail!("component name '{}' is not a valid directory name", name);
+    }
+    Ok(())
+}
+
+/// Checks a value of a field of a native package, like its version, which
+/// `format`, as in "an RPM", doesn't allow whitespace, `/` or any of the
+/// `forbidden` characters in.
+pub(crate) fn check_package_field(
+    format: &str,
+    field: &str,
+    value: &str,
+    forbidden: &str,
+) -> Result<()> {
+    if value.is_empty()
+        || value
+            .chars()
+            .any(|c| c.is_whitespace() || c == '/' || forbidden.contains(c))
+    {
+        bail!(
+            "'{}' is not a valid {} for {} package",
+            value,
+            field,
+            format
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,318 @@
+use crate::compression::CompressionFormat;
+use crate::package::{
+    check_package_field, parent_dirs, relative, timestamp, InstalledKind, Layout, PackageTar,
+    UnpackedInstaller,
+};
+use crate::util::*;
+use anyhow::{bail, Context, Result};
+use flate2::write::GzEncoder;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::path::Path;
+
+actor! {
+    #[derive(Debug)]
+    pub struct Pacman {
+        /// The installer tarball to turn into an Arch Linux package
+        #[clap(value_name = "FILE")]
+        input_tarball: String = "",
+
+        /// The prefix the package installs into
+        #[clap(value_name = "DIR")]
+        prefix: String = "/usr",
+
+        /// Where the files under etc/ go, /etc for the /usr prefix and <prefix>/etc otherwise
+        #[clap(value_name = "DIR")]
+        sysconfdir: String = "",
+
+        /// The name of the Arch Linux package, the installer's package name by default
+        #[clap(value_name = "NAME")]
+        package_name: String = "",
+
+        /// The version of the package, the pkgver of a PKGBUILD
+        #[clap(value_name = "VERSION")]
+        version: String = "",
+
+        /// The release of the package, the pkgrel of a PKGBUILD
+        #[clap(value_name = "RELEASE")]
+        release: String = "1",
+
+        /// The Arch Linux architecture, the one of this machine by default, or any
+        #[clap(value_name = "ARCH")]
+        architecture: String = "",
+
+        /// The one-line description of the package, listing the components by default
+        #[clap(value_name = "TEXT")]
+        description: String = "",
+
+        /// The home page of the package
+        #[clap(value_name = "URL")]
+        url: String = "",
+
+        /// Comma-separated licenses of the package
+        #[clap(value_name = "LICENSES")]
+        license: String = "",
+
+        /// The packager, as "Name <email>"
+        #[clap(value_name = "PACKAGER")]
+        packager: String = "Unknown Packager",
+
+        /// Comma-separated packages this one depends on, like "glibc>=2.17"
+        #[clap(value_name = "DEPENDS")]
+        depends: String = "",
+
+        /// The directory to do temporary work
+        #[clap(value_name = "DIR")]
+        work_dir: String = "./workdir",
+
+        /// The location to put the package
+        #[clap(value_name = "DIR")]
+        output_dir: String = "./dist",
+    }
+}
+
+impl Pacman {
+    /// Builds `<name>-<version>-<release>-<arch>.pkg.tar.zst` from the
+    /// installer tarball, with the files where install.sh would put them
+    /// under the prefix
+    pub fn run(self) -> Result<()> {
+        if self.input_tarball.is_empty() {
+            bail!("the installer tarball to package is required, with --input-tarball");
+        }
+        if self.version.is_empty() {
+            bail!("an Arch Linux package needs --version");
+        }
+        let layout = Layout::new(&self.prefix, &self.sysconfdir)?;
+        create_dir_all(&self.work_dir)?;
+        let installer = UnpackedInstaller::unpack(&self.input_tarball, self.work_dir.as_ref())?;
+        let paths = installer.installed_paths(&layout)?;
+
+        let name = if self.package_name.is_empty() {
+            installer.name().to_owned()
+        } else {
+            self.package_name.clone()
+        };
+        check_package_name(&name)?;
+        check_package_field("an Arch Linux", "version", &self.version, "-")?;
+        check_package_field("an Arch Linux", "release", &self.release, "-:")?;
+        let architecture = if self.architecture.is_empty() {
+            host_architecture()?.to_owned()
+        } else {
+            self.architecture.clone()
+        };
+        check_package_field("an Arch Linux", "architecture", &architecture, "-")?;
+        let mtime = timestamp()?;
+
+        // pacman tracks the directories above the files too, and keeps
+        // modified configuration files across upgrades when they're listed
+        // as backups.
+        let parents = parent_dirs(paths.keys());
+        let entries: BTreeSet<&Path> = parents
+            .iter()
+            .chain(paths.keys())
+            .map(|p| p.as_path())
+            .collect();
+        let mut size = 0;
+        let mut backups = vec![];
+        for (path, installed) in paths.iter().filter(|(path, _)| !parents.contains(*path)) {
+            match &installed.kind {
+                InstalledKind::File {
+                    size: file_size, ..
+                } => {
+                    size += file_size;
+                    if layout.is_config(path) {
+                        backups.push(relative(path));
+                    }
+                }
+                InstalledKind::Symlink(target) => size += target.as_os_str().len() as u64,
+                InstalledKind::Dir => {}
+            }
+        }
+        let mut pkginfo = self.pkginfo(&name, &architecture, mtime, size, &installer.components)?;
+        for backup in &backups {
+            writeln!(pkginfo, "backup = {}", path_to_str(backup)?)?;
+        }
+        for depend in split_list(&self.depends) {
+            let depend = depend.trim();
+            check_package_field("an Arch Linux", "dependency", depend, "")?;
+            writeln!(pkginfo, "depend = {}", depend)?;
+        }
+
+        // The .MTREE lets `pacman -Qkk` check the installed files.
+        let mut mtree = String::from("#mtree\n/set type=file uid=0 gid=0 mode=644\n");
+        let (md5, sha256) = digests(&mut pkginfo.as_bytes())?;
+        writeln!(
+            mtree,
+            "./.PKGINFO time={}.0 size={} md5digest={} sha256digest={}",
+            mtime,
+            pkginfo.len(),
+            md5,
+            sha256
+        )?;
+        for path in &entries {
+            let kind = match paths.get(*path) {
+                Some(installed) if !parents.contains(*path) => &installed.kind,
+                _ => &InstalledKind::Dir,
+            };
+            write!(
+                mtree,
+                "./{} time={}.0",
+                mtree_escape(&relative(path))?,
+                mtime
+            )?;
+            match kind {
+                InstalledKind::Dir => writeln!(mtree, " mode=755 type=dir")?,
+                InstalledKind::File { mode, size } => {
+                    let source = &paths[*path].source;
+                    let (md5, sha256) = digests(&mut open_file(source)?)
+                        .with_context(|| format!("failed to read '{}'", source.display()))?;
+                    if *mode != 0o644 {
+                        write!(mtree, " mode={:o}", mode)?;
+                    }
+                    writeln!(
+                        mtree,
+                        " size={} md5digest={} sha256digest={}",
+                        size, md5, sha256
+                    )?;
+                }
+                InstalledKind::Symlink(target) => {
+                    writeln!(mtree, " mode=777 type=link link={}", mtree_escape(target)?)?
+                }
+            }
+        }
+        let mut gz = GzEncoder::new(Vec::new(), flate2::Compression::best());
+        gz.write_all(mtree.as_bytes())
+            .context("failed to compress .MTREE")?;
+        let mtree = gz.finish().context("failed to compress .MTREE")?;
+
+        // The metadata goes first, so pacman finds it without reading the
+        // whole package.
+        create_dir_all(&self.output_dir)?;
+        let output = Path::new(&self.output_dir).join(format!(
+            "{}-{}-{}-{}.pkg.tar",
+            name, self.version, self.release, architecture
+        ));
+        let mut tar = PackageTar::new(CompressionFormat::Zst.encode(&output)?, mtime);
+        tar.append_data(Path::new(".PKGINFO"), 0o644, pkginfo.as_bytes())?;
+        tar.append_data(Path::new(".MTREE"), 0o644, &mtree)?;
+        for path in &entries {
+            match paths.get(*path) {
+                Some(installed) if !parents.contains(*path) => {
+                    tar.append_installed(&relative(path), installed)?
+                }
+                _ => tar.append_dir(&relative(path))?,
+            }
+        }
+        tar.into_inner()?.finish()
+    }
+
+    /// Returns the `.PKGINFO` file, up to the backups and dependencies.
+    fn pkginfo(
+        &self,
+        name: &str,
+        architecture: &str,
+        mtime: u64,
+        size: u64,
+        components: &[String],
+    ) -> Result<String> {
+        let description = if self.description.is_empty() {
+            format!("{} components", components.join(", "))
+        } else {
+            self.description.clone()
+        };
+        let version = format!("{}-{}", self.version, self.release);
+        let (mtime, size) = (mtime.to_string(), size.to_string());
+        let mut fields = vec![
+            ("pkgname", name),
+            ("pkgbase", name),
+            ("xdata", "pkgtype=pkg"),
+            ("pkgver", &version),
+            ("pkgdesc", &description),
+        ];
+        if !self.url.is_empty() {
+            fields.push(("url", &self.url));
+        }
+        fields.push(("builddate", &mtime));
+        fields.push(("packager", &self.packager));
+        fields.push(("size", &size));
+        fields.push(("arch", architecture));
+        let licenses = split_list(&self.license);
+        for license in &licenses {
+            fields.push(("license", license.trim()));
+        }
+
+        let mut pkginfo = String::from("# Generated by rust-installer\n");
+        for (field, value) in fields {
+            if value.is_empty() || value.contains('\n') {
+                bail!("invalid {} for an Arch Linux package: {:?}", field, value);
+            }
+            writeln!(pkginfo, "{} = {}", field, value)?;
+        }
+        Ok(pkginfo)
+    }
+}
+
+/// Returns the hex MD5 and SHA-256 digests of what `reader` reads.
+fn digests(reader: &mut dyn Read) -> Result<(String, String)> {
+    let mut md5 = Md5::new();
+    let mut sha256 = Sha256::new();
+    let mut buf = [0; 64 * 1024];
+    loop {
+        let len = reader.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        md5.update(&buf[..len]);
+        sha256.update(&buf[..len]);
+    }
+    Ok((to_hex(&md5.finalize()), to_hex(&sha256.finalize())))
+}
+
+/// Escapes a path for an mtree line, writing the bytes that aren't printable
+/// ASCII, or that mtree gives a meaning to, in octal.
+fn mtree_escape(path: &Path) -> Result<String> {
+    let mut escaped = String::new();
+    for &b in path_bytes(path)?.iter() {
+        if b.is_ascii_graphic() && !b"#=\\".contains(&b) {
+            escaped.push(b as char);
+        } else {
+            escaped.push_str(&format!("\\{:03o}", b));
+        }
+    }
+    Ok(escaped)
+}
+
+/// Checks that `name` is valid as an Arch Linux package name.
+fn check_package_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with(['-', '.'])
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "@._+-".contains(c));
+    if !valid {
+        bail!(
+            "'{}' is not a valid Arch Linux package name, pick one with --package-name",
+            name
+        );
+    }
+    Ok(())
+}
+
+/// Returns the Arch Linux name of the architecture this was built for.
+fn host_architecture() -> Result<&'static str> {
+    Ok(match host_arch() {
+        "x86_64" => "x86_64",
+        "x86" => "i686",
+        "aarch64" => "aarch64",
+        "arm" => "armv7h",
+        "riscv64" => "riscv64",
+        "loongarch64" => "loong64",
+        other => bail!(
+            "no Arch Linux architecture known for '{}', give one with --architecture",
+            other
+        ),
+    })
+}
@@ -0,0 +1,118 @@
+use crate::installed::{Installation, InstalledPath};
+use crate::util::*;
+use anyhow::{bail, Context, Result};
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use walkdir::WalkDir;
+
+actor! {
+    #[derive(Debug)]
+    pub struct Query {
+        /// The prefix the components were installed into
+        #[clap(value_name = "DIR")]
+        prefix: String = "/usr/local",
+
+        /// The directory holding the installation manifests, <prefix>/lib by default
+        #[clap(value_name = "DIR")]
+        libdir: String = "",
+
+        /// The directory under lib/ where the manifest lives
+        #[clap(value_name = "DIR")]
+        rel_manifest_dir: String = "packagelib",
+
+        /// Print the installed components that own this path
+        #[clap(value_name = "PATH")]
+        owner: String = "",
+
+        /// Print the paths installed by this component
+        #[clap(value_name = "COMPONENT")]
+        list_files: String = "",
+    }
+}
+
+impl Query {
+    /// Answers the query from the installed manifests, printing paths escaped
+    /// like in the manifests
+    pub fn run(self) -> Result<()> {
+        let installation = Installation::find(&self.prefix, &self.libdir, &self.rel_manifest_dir)?;
+
+        match (self.owner.is_empty(), self.list_files.is_empty()) {
+            (false, true) => self.print_owners(&installation),
+            (true, false) => self.print_files(&installation),
+            _ => bail!("expected exactly one of --owner and --list-files"),
+        }
+    }
+
+    fn print_owners(&self, installation: &Installation) -> Result<()> {
+        let candidates = absolute_paths(Path::new(&self.owner))?;
+        let mut found = false;
+        for component in installation.components()? {
+            let manifest = installation.manifest(&component)?;
+            if manifest
+                .iter()
+                .any(|entry| candidates.iter().any(|path| entry.owns(path)))
+            {
+                println!("{}", component);
+                found = true;
+            }
+        }
+        if !found {
+            bail!("no installed component owns '{}'", self.owner);
+        }
+        Ok(())
+    }
+
+    fn print_files(&self, installation: &Installation) -> Result<()> {
+        if !installation.components()?.contains(&self.list_files) {
+            bail!("component '{}' is not installed", self.list_files);
+        }
+        for entry in installation.manifest(&self.list_files)? {
+            match entry {
+                InstalledPath::File(file) => println!("{}", escape_path(&file)?),
+                InstalledPath::Dir(dir) if !dir.is_dir() => {
+                    eprintln!("warning: bulk directory '{}' is missing", dir.display());
+                }
+                InstalledPath::Dir(dir) => {
+                    let walker =
+                        WalkDir::new(&dir).sort_by(|a, b| a.file_name().cmp(b.file_name()));
+                    for entry in walker {
+                        let entry = entry.with_context(|| {
+                            format!("failed to walk directory '{}'", dir.display())
+                        })?;
+                        if !entry.file_type().is_dir() {
+                            println!("{}", escape_path(entry.path())?);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the absolute forms `path` may have been recorded under: with `.` and
+/// `..` resolved lexically, and with the symlinks of its parent resolved too.
+fn absolute_paths(path: &Path) -> Result<Vec<PathBuf>> {
+    let path = env::current_dir()
+        .context("failed to get the current directory")?
+        .join(path);
+    let mut lexical = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                lexical.pop();
+            }
+            component => lexical.push(component),
+        }
+    }
+
+    let mut paths = vec![lexical.clone()];
+    if let (Some(parent), Some(name)) = (lexical.parent(), lexical.file_name()) {
+        if let Ok(parent) = fs::canonicalize(parent) {
+            paths.push(parent.join(name));
+        }
+    }
+    Ok(paths)
+}
@@ -0,0 +1,225 @@
+use crate::compression::CompressionFormat;
+use crate::installed::{Installation, InstalledHash};
+use crate::package::SparseFile;
+use crate::status::{check_component, Damage};
+use crate::util::*;
+use anyhow::{bail, format_err, Context, Result};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use tar::{Archive, EntryType};
+
+actor! {
+    #[derive(Debug)]
+    pub struct Repair {
+        /// The prefix the components were installed into
+        #[clap(value_name = "DIR")]
+        prefix: String = "/usr/local",
+
+        /// The directory holding the installation manifests, <prefix>/lib by default
+        #[clap(value_name = "DIR")]
+        libdir: String = "",
+
+        /// The directory under lib/ where the manifest lives
+        #[clap(value_name = "DIR")]
+        rel_manifest_dir: String = "packagelib",
+
+        /// The installer tarball the components were installed from
+        #[clap(value_name = "FILE")]
+        input_tarball: String = "",
+
+        /// Seconds to wait for another installation into the prefix to finish
+        #[clap(value_name = "SECONDS")]
+        lock_timeout: String = "60",
+    }
+}
+
+/// The files to restore, by component and path within the component image.
+type Wanted = BTreeMap<(String, PathBuf), InstalledHash>;
+
+impl Repair {
+    /// Restores the missing and modified files of the installed components
+    /// from the installer tarball, leaving intact files alone
+    pub fn run(self) -> Result<()> {
+        if self.input_tarball.is_empty() {
+            bail!("the installer tarball to repair from is required, with --input-tarball");
+        }
+        let timeout = self
+            .lock_timeout
+            .parse()
+            .with_context(|| format!("invalid lock timeout '{}'", self.lock_timeout))?;
+        let installation = Installation::find(&self.prefix, &self.libdir, &self.rel_manifest_dir)?;
+        let _lock = installation.lock(timeout)?;
+
+        let mut wanted = Wanted::new();
+        for component in installation.components()? {
+            if installation.hashes(&component)?.is_none() {
+                bail!(
+                    "component '{}' was installed without hashes, reinstall it instead",
+                    component
+                );
+            }
+            for path in check_component(&installation, &component)? {
+                match (path.damage, path.recorded) {
+                    (Damage::Unexpected, _) => {
+                        eprintln!(
+                            "warning: leaving unexpected file '{}' alone",
+                            path.path.display()
+                        );
+                    }
+                    (_, Some(recorded)) => {
+                        wanted.insert((component.clone(), recorded.image_path.clone()), recorded);
+                    }
+                    (_, None) => bail!("no hash recorded for '{}'", path.path.display()),
+                }
+            }
+        }
+        if wanted.is_empty() {
+            println!("nothing to repair");
+            return Ok(());
+        }
+
+        // Restore the regular files, then the ones the tarball stores as
+        // hardlinks to earlier entries, which take a second pass.
+        let mut links = vec![];
+        for entry in self.open_tarball()?.entries()? {
+            let mut entry = entry?;
+            let sparse = SparseFile::of_entry(&mut entry)?;
+            let path = match &sparse {
+                Some(sparse) => sparse.path.clone(),
+                None => entry.path()?.into_owned(),
+            };
+            let key = match wanted_key(&path) {
+                Some(key) => key,
+                None => continue,
+            };
+            let recorded = match wanted.remove(&key) {
+                Some(recorded) => recorded,
+                None => continue,
+            };
+            let mode = entry.header().mode()?;
+            match entry.header().entry_type() {
+                EntryType::Regular | EntryType::Continuous | EntryType::GNUSparse => {
+                    restore(&mut entry, sparse.as_ref(), mode, &recorded)?;
+                }
+                EntryType::Link => {
+                    let target = entry
+                        .link_name()?
+                        .ok_or_else(|| format_err!("hardlink without a target"))?;
+                    links.push((target.into_owned(), mode, recorded));
+                }
+                _ => bail!(
+                    "'{}' is not a regular file in '{}'",
+                    recorded.image_path.display(),
+                    self.input_tarball
+                ),
+            }
+        }
+        if !links.is_empty() {
+            for entry in self.open_tarball()?.entries()? {
+                let mut entry = entry?;
+                let sparse = SparseFile::of_entry(&mut entry)?;
+                let path = match &sparse {
+                    Some(sparse) => sparse.path.clone(),
+                    None => entry.path()?.into_owned(),
+                };
+                if !links.iter().any(|(target, _, _)| *target == path) {
+                    continue;
+                }
+                let mut contents = vec![];
+                entry.read_to_end(&mut contents)?;
+                for (_, mode, recorded) in links.iter().filter(|(target, _, _)| *target == path) {
+                    restore(&mut &contents[..], sparse.as_ref(), *mode, recorded)?;
+                }
+                links.retain(|(target, _, _)| *target != path);
+            }
+        }
+
+        if let Some(((component, path), _)) = wanted.iter().next() {
+            bail!(
+                "'{}' of component '{}' is not in '{}'",
+                path.display(),
+                component,
+                self.input_tarball
+            );
+        }
+        if let Some((target, _, _)) = links.first() {
+            bail!(
+                "hardlink target '{}' is not in '{}'",
+                target.display(),
+                self.input_tarball
+            );
+        }
+        Ok(())
+    }
+
+    fn open_tarball(&self) -> Result<Archive<Box<dyn Read>>> {
+        let compression =
+            CompressionFormat::detect_from_path(&self.input_tarball).ok_or_else(|| {
+                format_err!("couldn't figure out the format of {}", self.input_tarball)
+            })?;
+        Ok(Archive::new(compression.decode(&self.input_tarball)?))
+    }
+}
+
+/// Returns the component and image path of a tarball entry, which is at
+/// `<package>/<component>/<path>`.
+fn wanted_key(path: &Path) -> Option<(String, PathBuf)> {
+    let mut components = path.components();
+    components.next()?;
+    let component = components.next()?.as_os_str().to_str()?.to_owned();
+    Some((component, components.as_path().to_owned()))
+}
+
+/// Writes the contents of a file, decoded if it's `sparse`, next to its
+/// installed path, checks them against the recorded hash, and moves them into
+/// place. Files get the modes install.sh gives them.
+fn restore(
+    contents: &mut dyn Read,
+    sparse: Option<&SparseFile>,
+    mode: u32,
+    recorded: &InstalledHash,
+) -> Result<()> {
+    let path = &recorded.path;
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+    let mut temp = OsString::from(path.as_os_str());
+    temp.push(".repair");
+    let temp = PathBuf::from(temp);
+    if temp.exists() {
+        remove_file(&temp)?;
+    }
+    let mut file = create_new_file(&temp)?;
+    match sparse {
+        Some(sparse) => sparse.write(contents, &mut file),
+        None => io::copy(contents, &mut file).map(drop).map_err(Into::into),
+    }
+    .with_context(|| format!("failed to write '{}'", temp.display()))?;
+    drop(file);
+
+    if sha256_file(&temp)? != recorded.hash {
+        remove_file(&temp)?;
+        bail!(
+            "'{}' in the tarball differs from the installed version",
+            recorded.image_path.display()
+        );
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let executable = mode & 0o111 != 0 || recorded.image_path.starts_with("bin");
+        let mode = if executable { 0o755 } else { 0o644 };
+        fs::set_permissions(&temp, fs::Permissions::from_mode(mode))
+            .with_context(|| format!("failed to set permissions of '{}'", temp.display()))?;
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+
+    fs::rename(&temp, path).with_context(|| format!("failed to replace '{}'", path.display()))?;
+    println!("repaired: {}", escape_path(path)?);
+    Ok(())
+}
@@ -0,0 +1,696 @@
+use crate::compression::{CompressionFormat, Encoder};
+use crate::package::{
+    check_package_field, timestamp, InstalledKind, InstalledPath, Layout, UnpackedInstaller,
+};
+use crate::util::*;
+use anyhow::{bail, format_err, Context, Result};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+actor! {
+    #[derive(Debug)]
+    pub struct Rpm {
+        /// The installer tarball to turn into an RPM package
+        #[clap(value_name = "FILE")]
+        input_tarball: String = "",
+
+        /// The prefix the package installs into
+        #[clap(value_name = "DIR")]
+        prefix: String = "/usr",
+
+        /// Where the files under etc/ go, /etc for the /usr prefix and <prefix>/etc otherwise
+        #[clap(value_name = "DIR")]
+        sysconfdir: String = "",
+
+        /// The name of the RPM package, the installer's package name by default
+        #[clap(value_name = "NAME")]
+        package_name: String = "",
+
+        /// The version of the RPM package
+        #[clap(value_name = "VERSION")]
+        version: String = "",
+
+        /// The release of the RPM package
+        #[clap(value_name = "RELEASE")]
+        release: String = "1",
+
+        /// The RPM architecture, the one of this machine by default
+        #[clap(value_name = "ARCH")]
+        architecture: String = "",
+
+        /// The one-line summary of the package, listing the components by default
+        #[clap(value_name = "TEXT")]
+        summary: String = "",
+
+        /// The license of the package
+        #[clap(value_name = "LICENSE")]
+        license: String = "",
+
+        /// Comma-separated capabilities the package requires, like "glibc >= 2.17"
+        #[clap(value_name = "REQUIRES")]
+        requires: String = "",
+
+        /// The format used to compress the payload: gz, xz or zst
+        #[clap(value_name = "FORMAT", default_value_t)]
+        compression_format: CompressionFormat,
+
+        /// The directory to do temporary work
+        #[clap(value_name = "DIR")]
+        work_dir: String = "./workdir",
+
+        /// The location to put the package
+        #[clap(value_name = "DIR")]
+        output_dir: String = "./dist",
+    }
+}
+
+impl Rpm {
+    /// Builds `<name>-<version>-<release>.<arch>.rpm` from the installer
+    /// tarball, with the files where install.sh would put them under the
+    /// prefix
+    pub fn run(self) -> Result<()> {
+        if self.input_tarball.is_empty() {
+            bail!("the installer tarball to package is required, with --input-tarball");
+        }
+        if self.version.is_empty() {
+            bail!("an RPM package needs --version");
+        }
+        let layout = Layout::new(&self.prefix, &self.sysconfdir)?;
+        create_dir_all(&self.work_dir)?;
+        let installer = UnpackedInstaller::unpack(&self.input_tarball, self.work_dir.as_ref())?;
+
+        let name = if self.package_name.is_empty() {
+            installer.name().to_owned()
+        } else {
+            self.package_name.clone()
+        };
+        check_package_field("an RPM", "name", &name, "")?;
+        check_package_field("an RPM", "version", &self.version, "-")?;
+        check_package_field("an RPM", "release", &self.release, "-")?;
+        let architecture = if self.architecture.is_empty() {
+            host_architecture()?.to_owned()
+        } else {
+            self.architecture.clone()
+        };
+        check_package_field("an RPM", "architecture", &architecture, "-")?;
+        let mtime = u32::try_from(timestamp()?).context("the timestamp doesn't fit in an RPM")?;
+
+        // RPM sorts the files by their full path, bytewise. Only bulk
+        // directories are owned by the package, rpm creates the others.
+        let mut files = vec![];
+        for (path, installed) in installer.installed_paths(&layout)? {
+            files.push(RpmFile::new(&path, installed, &layout)?);
+        }
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let rpm_dir = Path::new(&self.work_dir).join(format!("{}.rpm", name));
+        if rpm_dir.exists() {
+            remove_dir_all(&rpm_dir)?;
+        }
+        create_dir_all(&rpm_dir)?;
+        let format = self.compression_format;
+        let payload = rpm_dir.join(format!("payload.cpio.{}", format.extension()));
+        let payload_size =
+            write_payload(format.encode(rpm_dir.join("payload.cpio"))?, &files, mtime)?;
+        let payload_len = fs::metadata(&payload)
+            .with_context(|| format!("failed to read metadata of '{}'", payload.display()))?
+            .len();
+
+        let header = self
+            .header(
+                &name,
+                &architecture,
+                mtime,
+                &files,
+                &installer.components,
+                &payload,
+            )?
+            .into_bytes(RPMTAG_HEADERIMMUTABLE);
+
+        // The signature covers the header and the payload with digests.
+        let mut md5 = Md5::new();
+        md5.update(&header);
+        io::copy(&mut open_file(&payload)?, &mut md5)
+            .with_context(|| format!("failed to read '{}'", payload.display()))?;
+        let mut signature = RpmHeader::default();
+        let size = u32::try_from(header.len() as u64 + payload_len)
+            .context("the package is too large for RPM")?;
+        signature.add(RPMSIGTAG_SIZE, Value::Int32(vec![size]));
+        signature.add(RPMSIGTAG_MD5, Value::Bin(md5.finalize().to_vec()));
+        signature.add(RPMSIGTAG_PAYLOADSIZE, Value::Int32(vec![payload_size]));
+        signature.add(
+            RPMSIGTAG_SHA256,
+            Value::String(to_hex(&Sha256::digest(&header)).into_bytes()),
+        );
+        let mut signature = signature.into_bytes(RPMTAG_HEADERSIGNATURES);
+        signature.resize(signature.len().div_ceil(8) * 8, 0);
+
+        create_dir_all(&self.output_dir)?;
+        let nvr = format!("{}-{}-{}", name, self.version, self.release);
+        let output = Path::new(&self.output_dir).join(format!("{}.{}.rpm", nvr, architecture));
+        if output.exists() {
+            remove_file(&output)?;
+        }
+        let mut file = create_new_file(&output)?;
+        file.write_all(&lead(&nvr, &architecture))
+            .and_then(|()| file.write_all(&signature))
+            .and_then(|()| file.write_all(&header))
+            .with_context(|| format!("failed to write '{}'", output.display()))?;
+        io::copy(&mut open_file(&payload)?, &mut file)
+            .with_context(|| format!("failed to write '{}'", output.display()))?;
+        Ok(())
+    }
+
+    /// Returns the main header, describing the package and its files.
+    fn header(
+        &self,
+        name: &str,
+        architecture: &str,
+        mtime: u32,
+        files: &[RpmFile],
+        components: &[String],
+        payload: &Path,
+    ) -> Result<RpmHeader> {
+        let summary = if self.summary.is_empty() {
+            format!("{} components", components.join(", "))
+        } else {
+            self.summary.clone()
+        };
+        let description = format!("Installs the components: {}.", components.join(", "));
+        let string = |s: &str| Value::String(s.as_bytes().to_vec());
+        let strings = |s: Vec<&str>| Value::StringArray(s.into_iter().map(Into::into).collect());
+
+        let mut header = RpmHeader::default();
+        header.add(RPMTAG_HEADERI18NTABLE, strings(vec!["C"]));
+        header.add(RPMTAG_NAME, string(name));
+        header.add(RPMTAG_VERSION, string(&self.version));
+        header.add(RPMTAG_RELEASE, string(&self.release));
+        header.add(RPMTAG_SUMMARY, Value::I18nString(summary.into_bytes()));
+        header.add(
+            RPMTAG_DESCRIPTION,
+            Value::I18nString(description.into_bytes()),
+        );
+        header.add(RPMTAG_BUILDTIME, Value::Int32(vec![mtime]));
+        header.add(RPMTAG_GROUP, Value::I18nString(b"Unspecified".to_vec()));
+        header.add(RPMTAG_OS, string("linux"));
+        header.add(RPMTAG_ARCH, string(architecture));
+        if !self.license.is_empty() {
+            header.add(RPMTAG_LICENSE, string(&self.license));
+        }
+        // A binary package is one that names the source package it came from.
+        let nvr = format!("{}-{}-{}", name, self.version, self.release);
+        header.add(RPMTAG_SOURCERPM, string(&format!("{}.src.rpm", nvr)));
+
+        // Dependencies, including the rpm features the package needs.
+        let evr = format!("{}-{}", self.version, self.release);
+        header.add(RPMTAG_PROVIDENAME, strings(vec![name]));
+        header.add(RPMTAG_PROVIDEFLAGS, Value::Int32(vec![RPMSENSE_EQUAL]));
+        header.add(RPMTAG_PROVIDEVERSION, strings(vec![&evr]));
+        let mut requires = vec![
+            ("rpmlib(CompressedFileNames)", "3.0.4-1"),
+            ("rpmlib(FileDigests)", "4.6.0-1"),
+            ("rpmlib(PayloadFilesHavePrefix)", "4.0-1"),
+        ];
+        match self.compression_format {
+            CompressionFormat::Gz => {}
+            CompressionFormat::Xz => requires.push(("rpmlib(PayloadIsXz)", "5.2-1")),
+            CompressionFormat::Zst => requires.push(("rpmlib(PayloadIsZstd)", "5.4.18-1")),
+        }
+        let rpmlib = RPMSENSE_RPMLIB | RPMSENSE_LESS | RPMSENSE_EQUAL;
+        let mut requires: Vec<_> = requires
+            .into_iter()
+            .map(|(name, version)| (name, rpmlib, version))
+            .collect();
+        requires.extend(parse_requires(&self.requires)?);
+        header.add(
+            RPMTAG_REQUIRENAME,
+            strings(requires.iter().map(|r| r.0).collect()),
+        );
+        header.add(
+            RPMTAG_REQUIREFLAGS,
+            Value::Int32(requires.iter().map(|r| r.1).collect()),
+        );
+        header.add(
+            RPMTAG_REQUIREVERSION,
+            strings(requires.iter().map(|r| r.2).collect()),
+        );
+
+        // The files, with their directories and base names stored apart.
+        let mut size = 0u32;
+        let mut dirnames: Vec<Vec<u8>> = vec![];
+        let mut dir_index = BTreeMap::new();
+        let mut dirindexes = vec![];
+        let mut basenames = vec![];
+        for file in files {
+            size = size
+                .checked_add(file.size)
+                .ok_or_else(|| format_err!("the files are too large for RPM"))?;
+            let split = file.path.iter().rposition(|&b| b == b'/').unwrap_or(0) + 1;
+            let (dir, base) = file.path.split_at(split);
+            let index = *dir_index.entry(dir).or_insert_with(|| {
+                dirnames.push(dir.to_vec());
+                dirnames.len() as u32 - 1
+            });
+            dirindexes.push(index);
+            basenames.push(base.to_vec());
+        }
+        header.add(RPMTAG_SIZE, Value::Int32(vec![size]));
+        let count = files.len();
+        let each = |f: fn(&RpmFile) -> u32| files.iter().map(f).collect();
+        header.add(RPMTAG_FILESIZES, Value::Int32(each(|f| f.size)));
+        header.add(
+            RPMTAG_FILEMODES,
+            Value::Int16(files.iter().map(|f| f.mode as u16).collect()),
+        );
+        header.add(RPMTAG_FILERDEVS, Value::Int16(vec![0; count]));
+        header.add(RPMTAG_FILEMTIMES, Value::Int32(vec![mtime; count]));
+        header.add(
+            RPMTAG_FILEDIGESTS,
+            Value::StringArray(
+                files
+                    .iter()
+                    .map(|f| f.digest.clone().into_bytes())
+                    .collect(),
+            ),
+        );
+        header.add(
+            RPMTAG_FILELINKTOS,
+            Value::StringArray(files.iter().map(|f| f.link.clone()).collect()),
+        );
+        header.add(RPMTAG_FILEFLAGS, Value::Int32(each(|f| f.flags)));
+        header.add(RPMTAG_FILEUSERNAME, strings(vec!["root"; count]));
+        header.add(RPMTAG_FILEGROUPNAME, strings(vec!["root"; count]));
+        header.add(RPMTAG_FILEDEVICES, Value::Int32(vec![1; count]));
+        header.add(
+            RPMTAG_FILEINODES,
+            Value::Int32((1..=count as u32).collect()),
+        );
+        header.add(RPMTAG_FILELANGS, strings(vec![""; count]));
+        header.add(RPMTAG_DIRINDEXES, Value::Int32(dirindexes));
+        header.add(RPMTAG_BASENAMES, Value::StringArray(basenames));
+        header.add(RPMTAG_DIRNAMES, Value::StringArray(dirnames));
+        header.add(
+            RPMTAG_FILEDIGESTALGO,
+            Value::Int32(vec![PGPHASHALGO_SHA256]),
+        );
+
+        // The payload, and a digest of it as it's stored.
+        let (compressor, level) = match self.compression_format {
+            CompressionFormat::Gz => ("gzip", "9"),
+            CompressionFormat::Xz => ("xz", "9"),
+            CompressionFormat::Zst => ("zstd", "19"),
+        };
+        header.add(RPMTAG_PAYLOADFORMAT, string("cpio"));
+        header.add(RPMTAG_PAYLOADCOMPRESSOR, string(compressor));
+        header.add(RPMTAG_PAYLOADFLAGS, string(level));
+        header.add(
+            RPMTAG_PAYLOADDIGEST,
+            Value::StringArray(vec![sha256_file(payload)?.into_bytes()]),
+        );
+        header.add(
+            RPMTAG_PAYLOADDIGESTALGO,
+            Value::Int32(vec![PGPHASHALGO_SHA256]),
+        );
+        Ok(header)
+    }
+}
+
+/// A path owned by the package, as the header and the payload describe it.
+struct RpmFile {
+    /// The absolute installed path.
+    path: Vec<u8>,
+    installed: InstalledPath,
+    /// The mode, including the file type bits.
+    mode: u32,
+    size: u32,
+    /// The SHA-256 of a file, in hex, or empty.
+    digest: String,
+    /// The target of a symlink, or empty.
+    link: Vec<u8>,
+    flags: u32,
+}
+
+impl RpmFile {
+    fn new(path: &Path, installed: InstalledPath, layout: &Layout) -> Result<Self> {
+        let (mode, size, digest, link) = match &installed.kind {
+            InstalledKind::Dir => (S_IFDIR | 0o755, 0, String::new(), vec![]),
+            InstalledKind::File { mode, size } => {
+                let size = u32::try_from(*size).map_err(|_| {
+                    format_err!("'{}' is too large for RPM", installed.source.display())
+                })?;
+                (
+                    S_IFREG | mode,
+                    size,
+                    sha256_file(&installed.source)?,
+                    vec![],
+                )
+            }
+            InstalledKind::Symlink(target) => {
+                let target = path_bytes(target)?.into_owned();
+                (S_IFLNK | 0o777, target.len() as u32, String::new(), target)
+            }
+        };
+        let flags = match installed.kind {
+            InstalledKind::File { .. } if layout.is_config(path) => RPMFILE_CONFIG,
+            _ => 0,
+        };
+        Ok(RpmFile {
+            path: path_bytes(path)?.into_owned(),
+            installed,
+            mode,
+            size,
+            digest,
+            link,
+            flags,
+        })
+    }
+}
+
+/// Writes the files as a cpio archive in the "new ASCII" format, with paths
+/// starting with `./`, into `encoder`, returning its uncompressed size.
+fn write_payload(encoder: Box<dyn Encoder>, files: &[RpmFile], mtime: u32) -> Result<u32> {
+    let mut cpio = Cpio {
+        writer: io::BufWriter::new(encoder),
+        written: 0,
+    };
+    for (ino, file) in files.iter().enumerate() {
+        let mut name = b"./".to_vec();
+        name.extend_from_slice(file.path.strip_prefix(b"/").unwrap_or(&file.path));
+        let nlink = if file.mode & S_IFDIR == S_IFDIR { 2 } else { 1 };
+        let entry = CpioEntry {
+            ino: ino as u32 + 1,
+            mode: file.mode,
+            nlink,
+            mtime,
+            size: file.size,
+        };
+        match &file.installed.kind {
+            InstalledKind::File { .. } => {
+                cpio.append(&name, &entry, &mut open_file(&file.installed.source)?)
+            }
+            InstalledKind::Symlink(_) => cpio.append(&name, &entry, &mut &file.link[..]),
+            InstalledKind::Dir => cpio.append(&name, &entry, &mut io::empty()),
+        }
+        .with_context(|| {
+            format!(
+                "failed to add '{}' to the payload",
+                file.installed.source.display()
+            )
+        })?;
+    }
+    let trailer = CpioEntry {
+        ino: 0,
+        mode: 0,
+        nlink: 1,
+        mtime: 0,
+        size: 0,
+    };
+    cpio.append(b"TRAILER!!!", &trailer, &mut io::empty())?;
+    let written = u32::try_from(cpio.written).context("the payload is too large for RPM")?;
+    cpio.writer
+        .into_inner()
+        .map_err(|e| e.into_error())
+        .context("failed to finish writing the payload")?
+        .finish()?;
+    Ok(written)
+}
+
+/// The header fields of a cpio entry that vary.
+struct CpioEntry {
+    ino: u32,
+    mode: u32,
+    nlink: u32,
+    mtime: u32,
+    size: u32,
+}
+
+/// Writes a cpio archive, counting its size.
+struct Cpio<W: Write> {
+    writer: W,
+    written: u64,
+}
+
+impl<W: Write> Cpio<W> {
+    fn append(&mut self, name: &[u8], entry: &CpioEntry, data: &mut dyn Read) -> Result<()> {
+        let header = format!(
+            "070701{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}",
+            entry.ino,
+            entry.mode,
+            0,
+            0,
+            entry.nlink,
+            entry.mtime,
+            entry.size,
+            0,
+            0,
+            0,
+            0,
+            name.len() + 1,
+            0
+        );
+        self.write(header.as_bytes())?;
+        self.write(name)?;
+        self.write(&[0])?;
+        self.pad()?;
+        let copied = io::copy(&mut data.take(entry.size.into()), &mut self.writer)?;
+        if copied != u64::from(entry.size) {
+            bail!("file changed size while reading");
+        }
+        self.written += copied;
+        self.pad()
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.writer.write_all(bytes)?;
+        self.written += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Pads the archive to a multiple of four bytes.
+    fn pad(&mut self) -> Result<()> {
+        let padding = (4 - self.written % 4) % 4;
+        self.write(&[0; 3][..padding as usize])
+    }
+}
+
+/// A value of an RPM header entry.
+enum Value {
+    Int16(Vec<u16>),
+    Int32(Vec<u32>),
+    String(Vec<u8>),
+    Bin(Vec<u8>),
+    StringArray(Vec<Vec<u8>>),
+    I18nString(Vec<u8>),
+}
+
+impl Value {
+    /// Returns the type, count and alignment of the value.
+    fn layout(&self) -> (u32, usize, usize) {
+        match self {
+            Value::Int16(v) => (3, v.len(), 2),
+            Value::Int32(v) => (4, v.len(), 4),
+            Value::String(_) => (6, 1, 1),
+            Value::Bin(v) => (7, v.len(), 1),
+            Value::StringArray(v) => (8, v.len(), 1),
+            Value::I18nString(_) => (9, 1, 1),
+        }
+    }
+
+    fn write(&self, data: &mut Vec<u8>) {
+        match self {
+            Value::Int16(v) => v.iter().for_each(|i| data.extend(i.to_be_bytes())),
+            Value::Int32(v) => v.iter().for_each(|i| data.extend(i.to_be_bytes())),
+            Value::Bin(v) => data.extend(v),
+            Value::String(s) | Value::I18nString(s) => {
+                data.extend(s);
+                data.push(0);
+            }
+            Value::StringArray(v) => {
+                for s in v {
+                    data.extend(s);
+                    data.push(0);
+                }
+            }
+        }
+    }
+}
+
+/// The entries of an RPM header structure, as used for both the signature
+/// and the main header.
+#[derive(Default)]
+struct RpmHeader {
+    entries: Vec<(u32, Value)>,
+}
+
+impl RpmHeader {
+    fn add(&mut self, tag: u32, value: Value) {
+        self.entries.push((tag, value));
+    }
+
+    /// Encodes the header, sorted by tag, as a single immutable region marked
+    /// by `region_tag`: the first index entry points at a trailer at the end
+    /// of the data, whose negative offset spans the whole index.
+    fn into_bytes(mut self, region_tag: u32) -> Vec<u8> {
+        self.entries.sort_by_key(|(tag, _)| *tag);
+        let mut index = vec![];
+        let mut data = vec![];
+        for (tag, value) in &self.entries {
+            let (kind, count, align) = value.layout();
+            data.resize(data.len().div_ceil(align) * align, 0);
+            index.push([*tag, kind, data.len() as u32, count as u32]);
+            value.write(&mut data);
+        }
+        let entries = index.len() as u32 + 1;
+        let trailer = [region_tag, 7, (entries * 16).wrapping_neg(), 16];
+        index.insert(0, [region_tag, 7, data.len() as u32, 16]);
+        trailer.iter().for_each(|i| data.extend(i.to_be_bytes()));
+
+        let mut bytes = vec![0x8e, 0xad, 0xe8, 0x01, 0, 0, 0, 0];
+        bytes.extend(entries.to_be_bytes());
+        bytes.extend((data.len() as u32).to_be_bytes());
+        for entry in index {
+            entry.iter().for_each(|i| bytes.extend(i.to_be_bytes()));
+        }
+        bytes.extend(data);
+        bytes
+    }
+}
+
+/// Returns the lead, the fixed-size start of an RPM file that only older
+/// tools look at.
+fn lead(nvr: &str, architecture: &str) -> Vec<u8> {
+    let archnum: u16 = match architecture {
+        "i386" | "i686" | "x86_64" => 1,
+        "ppc64" | "ppc64le" => 16,
+        "s390x" => 15,
+        "armv7hl" => 12,
+        "aarch64" => 19,
+        "riscv64" => 22,
+        _ => 0,
+    };
+    let mut lead = vec![0xed, 0xab, 0xee, 0xdb, 3, 0];
+    lead.extend(0u16.to_be_bytes());
+    lead.extend(archnum.to_be_bytes());
+    let mut name = [0; 66];
+    let len = nvr.len().min(65);
+    name[..len].copy_from_slice(&nvr.as_bytes()[..len]);
+    lead.extend(name);
+    lead.extend(1u16.to_be_bytes());
+    lead.extend(5u16.to_be_bytes());
+    lead.extend([0; 16]);
+    lead
+}
+
+/// Parses comma-separated requirements, each a name optionally followed by a
+/// comparison and a version.
+fn parse_requires(requires: &str) -> Result<Vec<(&str, u32, &str)>> {
+    let mut parsed = vec![];
+    for require in requires.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+        let words: Vec<_> = require.split_whitespace().collect();
+        let (name, flags, version) = match words[..] {
+            [name] => (name, 0, ""),
+            [name, op, version] => {
+                let flags = match op {
+                    "<" => RPMSENSE_LESS,
+                    "<=" => RPMSENSE_LESS | RPMSENSE_EQUAL,
+                    "=" => RPMSENSE_EQUAL,
+                    ">=" => RPMSENSE_GREATER | RPMSENSE_EQUAL,
+                    ">" => RPMSENSE_GREATER,
+                    _ => bail!("unknown comparison '{}' in requirement '{}'", op, require),
+                };
+                (name, flags, version)
+            }
+            _ => bail!(
+                "expected NAME or NAME OP VERSION for a requirement, got '{}'",
+                require
+            ),
+        };
+        parsed.push((name, flags, version));
+    }
+    Ok(parsed)
+}
+
+/// Returns the RPM name of the architecture this was built for.
+fn host_architecture() -> Result<&'static str> {
+    Ok(match host_arch() {
+        "x86_64" => "x86_64",
+        "x86" => "i686",
+        "aarch64" => "aarch64",
+        "arm" => "armv7hl",
+        "powerpc64le" => "ppc64le",
+        "powerpc64" => "ppc64",
+        "riscv64" => "riscv64",
+        "s390x" => "s390x",
+        other => bail!(
+            "no RPM architecture known for '{}', give one with --architecture",
+            other
+        ),
+    })
+}
+
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+const RPMTAG_HEADERSIGNATURES: u32 = 62;
+const RPMTAG_HEADERIMMUTABLE: u32 = 63;
+const RPMTAG_HEADERI18NTABLE: u32 = 100;
+
+const RPMSIGTAG_SHA256: u32 = 273;
+const RPMSIGTAG_SIZE: u32 = 1000;
+const RPMSIGTAG_MD5: u32 = 1004;
+const RPMSIGTAG_PAYLOADSIZE: u32 = 1007;
+
+const RPMTAG_NAME: u32 = 1000;
+const RPMTAG_VERSION: u32 = 1001;
+const RPMTAG_RELEASE: u32 = 1002;
+const RPMTAG_SUMMARY: u32 = 1004;
+const RPMTAG_DESCRIPTION: u32 = 1005;
+const RPMTAG_BUILDTIME: u32 = 1006;
+const RPMTAG_SIZE: u32 = 1009;
+const RPMTAG_LICENSE: u32 = 1014;
+const RPMTAG_GROUP: u32 = 1016;
+const RPMTAG_OS: u32 = 1021;
+const RPMTAG_ARCH: u32 = 1022;
+const RPMTAG_FILESIZES: u32 = 1028;
+const RPMTAG_FILEMODES: u32 = 1030;
+const RPMTAG_FILERDEVS: u32 = 1033;
+const RPMTAG_FILEMTIMES: u32 = 1034;
+const RPMTAG_FILEDIGESTS: u32 = 1035;
+const RPMTAG_FILELINKTOS: u32 = 1036;
+const RPMTAG_FILEFLAGS: u32 = 1037;
+const RPMTAG_FILEUSERNAME: u32 = 1039;
+const RPMTAG_FILEGROUPNAME: u32 = 1040;
+const RPMTAG_SOURCERPM: u32 = 1044;
+const RPMTAG_PROVIDENAME: u32 = 1047;
+const RPMTAG_REQUIREFLAGS: u32 = 1048;
+const RPMTAG_REQUIRENAME: u32 = 1049;
+const RPMTAG_REQUIREVERSION: u32 = 1050;
+const RPMTAG_FILEDEVICES: u32 = 1095;
+const RPMTAG_FILEINODES: u32 = 1096;
+const RPMTAG_FILELANGS: u32 = 1097;
+const RPMTAG_PROVIDEFLAGS: u32 = 1112;
+const RPMTAG_PROVIDEVERSION: u32 = 1113;
+const RPMTAG_DIRINDEXES: u32 = 1116;
+const RPMTAG_BASENAMES: u32 = 1117;
+const RPMTAG_DIRNAMES: u32 = 1118;
+const RPMTAG_PAYLOADFORMAT: u32 = 1124;
+const RPMTAG_PAYLOADCOMPRESSOR: u32 = 1125;
+const RPMTAG_PAYLOADFLAGS: u32 = 1126;
+const RPMTAG_FILEDIGESTALGO: u32 = 5011;
+const RPMTAG_PAYLOADDIGEST: u32 = 5092;
+const RPMTAG_PAYLOADDIGESTALGO: u32 = 5093;
+
+const RPMSENSE_LESS: u32 = 1 << 1;
+const RPMSENSE_GREATER: u32 = 1 << 2;
+const RPMSENSE_EQUAL: u32 = 1 << 3;
+const RPMSENSE_RPMLIB: u32 = 1 << 24;
+
+const RPMFILE_CONFIG: u32 = 1;
+
+const PGPHASHALGO_SHA256: u32 = 8;
@@ -1,8 +1,12 @@
+use crate::compression::CompressionFormats;
 use crate::util::*;
-use anyhow::{Context, Result};
-use std::io::Write;
+use anyhow::{format_err, Context, Result};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
 
-const TEMPLATE: &'static str = include_str!("../install-template.sh");
+const TEMPLATE: &str = include_str!("../install-template.sh");
+const RUN_TEMPLATE: &str = include_str!("../run-template.sh");
 
 actor! {
     #[derive(Debug)]
@@ -32,6 +36,17 @@ actor! {
 impl Scripter {
     /// Generates the actual installer script
     pub fn run(self) -> Result<()> {
+        let script = self.script();
+
+        create_new_executable(&self.output_script)?
+            .write_all(script.as_ref())
+            .with_context(|| format!("failed to write output script '{}'", self.output_script))?;
+
+        Ok(())
+    }
+
+    /// Renders the installer script from the template
+    pub(crate) fn script(&self) -> String {
         // Replace dashes in the success message with spaces (our arg handling botches spaces)
         // TODO: still needed? Kept for compatibility for now.
         let product_name = self.product_name.replace('-', " ");
@@ -40,7 +55,7 @@ impl Scripter {
         // TODO: still needed? Kept for compatibility for now.
         let success_message = self.success_message.replace('-', " ");
 
-        let script = TEMPLATE
+        TEMPLATE
             .replace("%%TEMPLATE_PRODUCT_NAME%%", &sh_quote(&product_name))
             .replace("%%TEMPLATE_REL_MANIFEST_DIR%%", &self.rel_manifest_dir)
             .replace("%%TEMPLATE_SUCCESS_MESSAGE%%", &sh_quote(&success_message))
@@ -51,12 +66,66 @@ impl Scripter {
             .replace(
                 "%%TEMPLATE_RUST_INSTALLER_VERSION%%",
                 &sh_quote(&crate::RUST_INSTALLER_VERSION),
-            );
+            )
+    }
 
-        create_new_executable(&self.output_script)?
-            .write_all(script.as_ref())
-            .with_context(|| format!("failed to write output script '{}'", self.output_script))?;
+    /// Writes the self-extracting installer `<output>.run`: a stub that
+    /// unpacks and runs the installer tarball `<output>.tar.<ext>` appended
+    /// to it, using the first of `formats`
+    pub(crate) fn write_self_extracting(
+        &self,
+        package_name: &str,
+        formats: &CompressionFormats,
+        output: &Path,
+    ) -> Result<()> {
+        let format = formats
+            .iter()
+            .next()
+            .ok_or_else(|| format_err!("a self-extracting installer needs a compression format"))?;
+        let mut tarball = output.as_os_str().to_owned();
+        tarball.push(format!(".tar.{}", format.extension()));
+        let size = fs::metadata(&tarball)
+            .with_context(|| {
+                format!(
+                    "failed to read metadata of '{}'",
+                    Path::new(&tarball).display()
+                )
+            })?
+            .len();
+        let hash = sha256_file(&tarball)?;
+
+        let product_name = self.product_name.replace('-', " ");
+        let render = |offset: usize| {
+            RUN_TEMPLATE
+                .replace("%%TEMPLATE_PRODUCT_NAME%%", &sh_quote(&product_name))
+                .replace("%%TEMPLATE_PACKAGE_NAME%%", &sh_quote(&package_name))
+                .replace("%%TEMPLATE_PAYLOAD_FORMAT%%", format.extension())
+                .replace("%%TEMPLATE_PAYLOAD_OFFSET%%", &offset.to_string())
+                .replace("%%TEMPLATE_PAYLOAD_SIZE%%", &size.to_string())
+                .replace("%%TEMPLATE_PAYLOAD_SHA256%%", &hash)
+        };
+        // The stub states its own length, which is where the payload starts,
+        // so render it until the length it states is right.
+        let mut offset = 0;
+        let stub = loop {
+            let stub = render(offset);
+            if stub.len() == offset {
+                break stub;
+            }
+            offset = stub.len();
+        };
 
+        let mut run_file = output.as_os_str().to_owned();
+        run_file.push(".run");
+        let run_file = Path::new(&run_file);
+        if run_file.exists() {
+            remove_file(run_file)?;
+        }
+        let mut file = create_new_executable(run_file)?;
+        file.write_all(stub.as_bytes())
+            .with_context(|| format!("failed to write '{}'", run_file.display()))?;
+        io::copy(&mut open_file(&tarball)?, &mut file)
+            .with_context(|| format!("failed to write '{}'", run_file.display()))?;
         Ok(())
     }
 }
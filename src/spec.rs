@@ -0,0 +1,167 @@
+use crate::compression::CompressionFormats;
+use crate::tarballer::TarFormat;
+use crate::util::*;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+
+/// A declarative description of a package, read with `--spec` from a TOML
+/// file, or a JSON file if its name ends in `.json`. Keys are named like the
+/// corresponding flags, lists are written as arrays, and relative paths are
+/// resolved against the directory of the spec.
+///
+/// ```toml
+/// product-name = "Rust"
+/// package-name = "rustc-nightly-x86_64-unknown-linux-gnu"
+/// legacy-manifest-dirs = ["rustlib", "cargo"]
+/// compression-formats = ["gz", "xz"]
+///
+/// [[component]]
+/// name = "rustc"
+/// image-dir = "build/image"
+/// bulk-dirs = ["share/doc/rust/html"]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct PackageSpec {
+    pub product_name: Option<String>,
+    pub package_name: Option<String>,
+    pub rel_manifest_dir: Option<String>,
+    pub success_message: Option<String>,
+    pub legacy_manifest_dirs: Option<Vec<String>>,
+    pub non_installed_overlay: Option<String>,
+    pub work_dir: Option<String>,
+    pub output_dir: Option<String>,
+    pub compression_formats: Option<Vec<String>>,
+    pub copy_mode: Option<String>,
+    pub tar_format: Option<String>,
+    pub dedup: Option<bool>,
+    pub xattrs: Option<bool>,
+    pub sparse: Option<bool>,
+    pub self_extracting: Option<bool>,
+    /// The installers to combine, only used by `combine`.
+    pub input_tarballs: Option<Vec<String>>,
+    /// The components to generate, only used by `generate`.
+    #[serde(rename = "component")]
+    pub components: Vec<ComponentSpec>,
+}
+
+/// A `[[component]]` table of a package spec.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ComponentSpec {
+    pub name: String,
+    pub image_dir: String,
+    pub bulk_dirs: Option<Vec<String>>,
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+}
+
+impl PackageSpec {
+    /// Reads the spec at `path`, resolving the paths it contains.
+    pub fn load(path: &str) -> Result<Self> {
+        let contents =
+            fs::read_to_string(path).with_context(|| format!("failed to read spec '{}'", path))?;
+        let mut spec: PackageSpec = if path.ends_with(".json") {
+            serde_json::from_str(&contents)
+                .with_context(|| format!("failed to parse spec '{}'", path))?
+        } else {
+            toml::from_str(&contents).with_context(|| format!("failed to parse spec '{}'", path))?
+        };
+
+        let base = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        let resolve = |p: &mut String| -> Result<()> {
+            *p = path_to_str(&base.join(&*p))?.to_owned();
+            Ok(())
+        };
+        let dirs = vec![
+            &mut spec.non_installed_overlay,
+            &mut spec.work_dir,
+            &mut spec.output_dir,
+        ];
+        for p in dirs.into_iter().flatten() {
+            resolve(p)?;
+        }
+        for p in spec.input_tarballs.iter_mut().flatten() {
+            resolve(p)?;
+        }
+        for component in &mut spec.components {
+            resolve(&mut component.image_dir)?;
+        }
+        Ok(spec)
+    }
+}
+
+/// A spec value that can be set on an actor field of type `T`, in place of
+/// its flag.
+pub trait SpecValue<T> {
+    fn to_field(&self) -> Result<T>;
+}
+
+impl SpecValue<String> for String {
+    fn to_field(&self) -> Result<String> {
+        Ok(self.clone())
+    }
+}
+
+impl SpecValue<bool> for bool {
+    fn to_field(&self) -> Result<bool> {
+        Ok(*self)
+    }
+}
+
+impl SpecValue<CopyMode> for String {
+    fn to_field(&self) -> Result<CopyMode> {
+        self.parse()
+    }
+}
+
+impl SpecValue<TarFormat> for String {
+    fn to_field(&self) -> Result<TarFormat> {
+        self.parse()
+    }
+}
+
+impl SpecValue<CompressionFormats> for Vec<String> {
+    fn to_field(&self) -> Result<CompressionFormats> {
+        CompressionFormats::try_from(self.as_slice())
+    }
+}
+
+impl SpecValue<Vec<String>> for Vec<String> {
+    fn to_field(&self) -> Result<Vec<String>> {
+        Ok(self.clone())
+    }
+}
+
+impl SpecValue<String> for Vec<String> {
+    /// Joins the list for `legacy_manifest_dirs`, which install.sh splits at
+    /// commas and whitespace.
+    fn to_field(&self) -> Result<String> {
+        if let Some(item) = self
+            .iter()
+            .find(|item| item.contains(|c: char| c == ',' || c.is_whitespace()))
+        {
+            bail!("install.sh can't look for manifests in {:?}", item);
+        }
+        Ok(self.join(","))
+    }
+}
+
+/// Replaces each of the listed fields of an actor with the value set for it
+/// in a spec, if any, unless its flag is among the flags `given` on the
+/// command line.
+macro_rules! apply_spec {
+    ($actor:expr, $spec:expr, $given:expr, $( $field:ident ),* $(,)?) => {
+        $(
+            if let Some(value) = &$spec.$field {
+                if !$given.contains(stringify!($field)) {
+                    $actor.$field = crate::spec::SpecValue::to_field(value)
+                        .with_context(|| format!("invalid {} in spec", stringify!($field)))?;
+                }
+            }
+        )*
+    };
+}
@@ -0,0 +1,145 @@
+use crate::installed::{Installation, InstalledHash, InstalledPath};
+use crate::util::*;
+use anyhow::{bail, Context, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+actor! {
+    #[derive(Debug)]
+    pub struct Status {
+        /// The prefix the components were installed into
+        #[clap(value_name = "DIR")]
+        prefix: String = "/usr/local",
+
+        /// The directory holding the installation manifests, <prefix>/lib by default
+        #[clap(value_name = "DIR")]
+        libdir: String = "",
+
+        /// The directory under lib/ where the manifest lives
+        #[clap(value_name = "DIR")]
+        rel_manifest_dir: String = "packagelib",
+    }
+}
+
+impl Status {
+    /// Prints the missing, modified and unexpected paths of the installed
+    /// components, failing if there are any
+    pub fn run(self) -> Result<()> {
+        let installation = Installation::find(&self.prefix, &self.libdir, &self.rel_manifest_dir)?;
+        let mut damaged = 0;
+        for component in installation.components()? {
+            for path in check_component(&installation, &component)? {
+                println!("{}: {}", path.damage, escape_path(&path.path)?);
+                damaged += 1;
+            }
+        }
+        if damaged > 0 {
+            bail!("{} installed paths are damaged", damaged);
+        }
+        Ok(())
+    }
+}
+
+/// How an installed path differs from what was installed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Damage {
+    /// The path is gone.
+    Missing,
+    /// The file doesn't have the recorded contents anymore.
+    Modified,
+    /// The file is in a bulk directory, but wasn't installed.
+    Unexpected,
+}
+
+impl fmt::Display for Damage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Damage::Missing => "missing",
+            Damage::Modified => "modified",
+            Damage::Unexpected => "unexpected",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A damaged path of an installed component.
+#[derive(Debug)]
+pub struct DamagedPath {
+    pub damage: Damage,
+    pub path: PathBuf,
+    /// What was installed at the path, if the installer recorded it.
+    pub recorded: Option<InstalledHash>,
+}
+
+/// Compares the `file:` and `dir:` entries of the manifest of `component`
+/// with the filesystem. Without recorded hashes, from installers that didn't
+/// keep them, only missing entries are found.
+pub fn check_component(installation: &Installation, component: &str) -> Result<Vec<DamagedPath>> {
+    let hashes = installation.hashes(component)?;
+    if hashes.is_none() {
+        eprintln!(
+            "warning: no hashes recorded for component '{}', only checking for missing paths",
+            component
+        );
+    }
+    let recorded: BTreeMap<_, _> = hashes
+        .iter()
+        .flatten()
+        .map(|hash| (hash.path.clone(), hash))
+        .collect();
+
+    let mut damaged = vec![];
+    let mut add = |damage, path: &Path| {
+        damaged.push(DamagedPath {
+            damage,
+            path: path.to_owned(),
+            recorded: recorded.get(path).map(|&hash| hash.clone()),
+        })
+    };
+    for entry in installation.manifest(component)? {
+        match entry {
+            InstalledPath::File(path) => {
+                if !path.is_file() {
+                    add(Damage::Missing, &path);
+                } else if let Some(hash) = recorded.get(&path) {
+                    if sha256_file(&path)? != hash.hash {
+                        add(Damage::Modified, &path);
+                    }
+                }
+            }
+            InstalledPath::Dir(dir) => {
+                let mut found = BTreeSet::new();
+                if dir.is_dir() {
+                    let walker =
+                        WalkDir::new(&dir).sort_by(|a, b| a.file_name().cmp(b.file_name()));
+                    for file in walker {
+                        let file = file.with_context(|| {
+                            format!("failed to walk directory '{}'", dir.display())
+                        })?;
+                        if !file.file_type().is_file() {
+                            continue;
+                        }
+                        match recorded.get(file.path()) {
+                            Some(hash) if sha256_file(file.path())? != hash.hash => {
+                                add(Damage::Modified, file.path())
+                            }
+                            None if hashes.is_some() => add(Damage::Unexpected, file.path()),
+                            _ => {}
+                        }
+                        found.insert(file.into_path());
+                    }
+                } else if hashes.is_none() {
+                    add(Damage::Missing, &dir);
+                }
+                for path in recorded.keys() {
+                    if path.starts_with(&dir) && !found.contains(path) {
+                        add(Damage::Missing, path);
+                    }
+                }
+            }
+        }
+    }
+    Ok(damaged)
+}
@@ -1,12 +1,21 @@
-use anyhow::{bail, Context, Result};
-use std::fs::{read_link, symlink_metadata};
-use std::io::{empty, BufWriter, Write};
-use std::path::Path;
-use tar::{Builder, Header};
+use anyhow::{bail, Context, Error, Result};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs::{self, read_link, symlink_metadata};
+use std::io::{self, empty, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::{fmt, str::FromStr};
+use tar::{Builder, EntryType, Header};
 use walkdir::WalkDir;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, DateTime, ZipWriter};
 
 use crate::{
     compression::{CombinedEncoder, CompressionFormats},
+    oci,
+    package::{timestamp, Layout, UnpackedInstaller},
     util::*,
 };
 
@@ -25,15 +34,139 @@ actor! {
         #[clap(value_name = "DIR")]
         work_dir: String = "./workdir",
 
-        /// The formats used to compress the tarball.
+        /// The formats used to compress the tarball, and zip to also write a zip archive.
         #[clap(value_name = "FORMAT", default_value_t)]
         compression_formats: CompressionFormats,
+
+        /// The flavor of tar headers to write: gnu, ustar or pax.
+        #[clap(value_name = "FORMAT", default_value_t)]
+        tar_format: TarFormat,
+
+        /// Store files with identical contents once, as hardlinks to the first copy.
+        dedup: bool,
+
+        /// Record extended attributes, including capabilities and ACLs (pax only).
+        xattrs: bool,
+
+        /// Store only the data regions of files with holes (pax only).
+        sparse: bool,
+
+        /// Also write an OCI image layout to <output>.oci, with a layer of the files installed under this prefix.
+        #[clap(value_name = "DIR")]
+        oci_prefix: String = "",
+
+        /// The architecture of the OCI image, the one of this machine by default.
+        #[clap(value_name = "ARCH")]
+        oci_architecture: String = "",
+    }
+}
+
+/// The flavor of tar headers written by the tarballer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum TarFormat {
+    /// GNU headers, with `@LongLink` entries for long paths.
+    #[default]
+    Gnu,
+    /// Plain POSIX ustar headers, which can't store long paths or large files.
+    Ustar,
+    /// POSIX ustar headers with pax extended records where needed.
+    Pax,
+}
+
+impl TarFormat {
+    fn header(&self) -> Header {
+        match self {
+            TarFormat::Gnu => Header::new_gnu(),
+            TarFormat::Ustar | TarFormat::Pax => Header::new_ustar(),
+        }
+    }
+}
+
+impl FromStr for TarFormat {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "gnu" => Ok(TarFormat::Gnu),
+            "ustar" => Ok(TarFormat::Ustar),
+            "pax" => Ok(TarFormat::Pax),
+            other => bail!("unknown tar format: {}", other),
+        }
+    }
+}
+
+impl fmt::Display for TarFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(
+            match self {
+                TarFormat::Gnu => "gnu",
+                TarFormat::Ustar => "ustar",
+                TarFormat::Pax => "pax",
+            },
+            f,
+        )
     }
 }
 
 impl Tarballer {
     /// Generates the actual tarballs
     pub fn run(self) -> Result<()> {
+        // Checks everything the OCI image layout needs before writing any
+        // tarball, so a bad input leaves no partial output.
+        let oci_image = if self.oci_prefix.is_empty() {
+            None
+        } else {
+            let layout = Layout::new(&self.oci_prefix, "")?;
+            let input = Path::new(&self.work_dir).join(&self.input);
+            let installer = UnpackedInstaller::open(input.clone(), path_to_str(&input)?)?;
+            let architecture = if self.oci_architecture.is_empty() {
+                oci::host_architecture()?.to_owned()
+            } else {
+                self.oci_architecture.clone()
+            };
+            Some((layout, installer, architecture))
+        };
+        let (dirs, files) = get_recursive_paths(&self.work_dir, &self.input)
+            .context("failed to collect file paths")?;
+        let source = |path: PathBuf| Entry {
+            source: Source::Path(Path::new(&self.work_dir).join(&path)),
+            path,
+        };
+        let dirs = dirs.into_iter().map(source).collect();
+        let files = files.into_iter().map(source).collect();
+        self.write_entries(dirs, files)?;
+        if let Some((layout, installer, architecture)) = oci_image {
+            self.write_image_layout(&layout, &installer, &architecture)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the OCI image layout from the component manifests of the input,
+    /// opened as `installer`
+    fn write_image_layout(
+        &self,
+        layout: &Layout,
+        installer: &UnpackedInstaller,
+        architecture: &str,
+    ) -> Result<()> {
+        let dir = self.output.clone() + ".oci";
+        oci::write_image_layout(
+            installer,
+            layout,
+            architecture,
+            installer.name(),
+            dir.as_ref(),
+        )
+        .context("failed to write OCI image layout")
+    }
+
+    /// Writes `dirs` and `files` into the tarballs, ignoring `input`,
+    /// `work_dir` and the OCI options. Directories must be given with parents
+    /// before their children.
+    pub(crate) fn write_entries(&self, dirs: Vec<Entry>, mut files: Vec<Entry>) -> Result<()> {
+        if (self.xattrs || self.sparse) && self.tar_format != TarFormat::Pax {
+            bail!("extended attributes and sparse files need the pax tar format");
+        }
         let tarball_name = self.output.clone() + ".tar";
         let encoder = CombinedEncoder::new(
             self.compression_formats
@@ -45,32 +178,69 @@ impl Tarballer {
         // Sort files by their suffix, to group files with the same name from
         // different locations (likely identical) and files with the same
         // extension (likely containing similar data).
-        let (dirs, mut files) = get_recursive_paths(&self.work_dir, &self.input)
-            .context("failed to collect file paths")?;
-        files.sort_by(|a, b| a.bytes().rev().cmp(b.bytes().rev()));
+        files.sort_by_cached_key(|entry| {
+            let mut bytes = path_bytes(&entry.path).map_or_else(|_| vec![], Cow::into_owned);
+            bytes.reverse();
+            bytes
+        });
+
+        // Only files sharing their size with another file need to be hashed.
+        let mut dedup = Dedup::default();
+        if self.dedup {
+            for entry in &files {
+                if let Source::Path(src) = &entry.source {
+                    let stat = symlink_metadata(src)?;
+                    if stat.is_file() {
+                        *dedup.sizes.entry(stat.len()).or_insert(0) += 1;
+                    }
+                }
+            }
+        }
 
         // Write the tar into both encoded files. We write all directories
         // first, so files may be directly created. (See rust-lang/rustup.rs#1092.)
         let buf = BufWriter::with_capacity(1024 * 1024, encoder);
-        let mut builder = Builder::new(buf);
+        let mut writer = TarWriter {
+            builder: Builder::new(buf),
+            format: self.tar_format,
+            xattrs: self.xattrs,
+            sparse: self.sparse,
+            dedup,
+        };
+        let mut zip = if self.compression_formats.zip() {
+            let zip_name = self.output.clone() + ".zip";
+            if Path::new(&zip_name).exists() {
+                remove_file(&zip_name)?;
+            }
+            let file = BufWriter::new(create_new_file(&zip_name)?);
+            Some(ZipWriter::new(file))
+        } else {
+            None
+        };
 
         let pool = rayon::ThreadPoolBuilder::new()
             .num_threads(2)
             .build()
             .unwrap();
         pool.install(move || {
-            for path in dirs {
-                let src = Path::new(&self.work_dir).join(&path);
-                builder
-                    .append_dir(&path, &src)
-                    .with_context(|| format!("failed to tar dir '{}'", src.display()))?;
+            for entry in dirs.iter().chain(&files) {
+                let display = match &entry.source {
+                    Source::Path(src) => src.display(),
+                    Source::Memory(..) | Source::Dir(_) => entry.path.display(),
+                };
+                writer
+                    .append_source(&entry.source, &entry.path)
+                    .with_context(|| format!("failed to tar '{}'", display))?;
+                if let Some(zip) = &mut zip {
+                    append_zip(zip, &entry.source, &entry.path)
+                        .with_context(|| format!("failed to zip '{}'", display))?;
+                }
             }
-            for path in files {
-                let src = Path::new(&self.work_dir).join(&path);
-                append_path(&mut builder, &src, &path)
-                    .with_context(|| format!("failed to tar file '{}'", src.display()))?;
+            if let Some(mut zip) = zip {
+                zip.finish().context("failed to finish writing .zip file")?;
             }
-            builder
+            writer
+                .builder
                 .into_inner()
                 .context("failed to finish writing .tar stream")?
                 .into_inner()
@@ -83,34 +253,425 @@ impl Tarballer {
     }
 }
 
-fn append_path<W: Write>(builder: &mut Builder<W>, src: &Path, path: &String) -> Result<()> {
-    let stat = symlink_metadata(src)?;
+/// Where the contents of a tarball entry come from.
+pub(crate) enum Source {
+    /// A file, directory or symlink on disk.
+    Path(PathBuf),
+    /// A file synthesized in memory, with the given mode.
+    Memory(Vec<u8>, u32),
+    /// A directory synthesized in memory, with the given mode.
+    Dir(u32),
+}
+
+/// A single entry to be written into a tarball.
+pub(crate) struct Entry {
+    /// The path of the entry within the tarball.
+    pub(crate) path: PathBuf,
+    pub(crate) source: Source,
+}
+
+/// Tracks the files already written to a tarball, so that later files with
+/// identical contents and mode can be written as hardlinks to them.
+#[derive(Default)]
+struct Dedup {
+    /// How many files there are of each size. Empty unless deduplicating.
+    sizes: HashMap<u64, usize>,
+    /// The first path written for each `(size, mode, sha256)`.
+    seen: HashMap<(u64, u32, Vec<u8>), PathBuf>,
+}
+
+impl Dedup {
+    /// Returns the path of an earlier file identical to `src`, or records
+    /// `src` as `path` if there's none.
+    fn duplicate_of(&mut self, src: &Path, mode: u32, path: &Path) -> Result<Option<PathBuf>> {
+        let size = symlink_metadata(src)?.len();
+        if self.sizes.get(&size).map_or(true, |&n| n < 2) {
+            return Ok(None);
+        }
+        let mut hasher = Sha256::new();
+        io::copy(&mut open_file(src)?, &mut hasher)?;
+        let key = (size, mode, hasher.finalize().to_vec());
+        if let Some(target) = self.seen.get(&key) {
+            return Ok(Some(target.clone()));
+        }
+        self.seen.insert(key, path.to_owned());
+        Ok(None)
+    }
+}
+
+/// Appends entries to a tarball, encoding them as the options require.
+struct TarWriter<W: Write> {
+    builder: Builder<W>,
+    format: TarFormat,
+    xattrs: bool,
+    sparse: bool,
+    dedup: Dedup,
+}
+
+impl<W: Write> TarWriter<W> {
+    fn append_source(&mut self, source: &Source, path: &Path) -> Result<()> {
+        let mut header = self.format.header();
+        match source {
+            Source::Path(src) => {
+                let stat = symlink_metadata(src)?;
+                header.set_metadata(&stat);
+                let mut records = vec![];
+                if self.xattrs {
+                    for (name, value) in read_xattrs(src)? {
+                        records.push((format!("SCHILY.xattr.{}", name), value));
+                    }
+                }
+                if stat.file_type().is_symlink() {
+                    let link = read_link(src)?;
+                    self.append_entry(&mut header, path, Some(&link), records, empty())?;
+                } else if stat.is_dir() {
+                    self.append_entry(&mut header, path, None, records, empty())?;
+                } else {
+                    mark_executable(&mut header, src)?;
+                    // Files carrying attributes are never linked, since the
+                    // link would share the attributes of its target.
+                    let duplicate = if records.is_empty() {
+                        self.dedup.duplicate_of(src, header.mode()?, path)?
+                    } else {
+                        None
+                    };
+                    if let Some(target) = duplicate {
+                        header.set_entry_type(EntryType::Link);
+                        header.set_size(0);
+                        self.append_entry(&mut header, path, Some(&target), records, empty())?;
+                        return Ok(());
+                    }
+                    let mut file = open_file(src)?;
+                    let segments = if self.sparse {
+                        data_segments(&file, stat.len())
+                    } else {
+                        None
+                    };
+                    match segments {
+                        Some(segments) => {
+                            self.append_sparse(&mut header, path, records, file, segments)?
+                        }
+                        None => {
+                            // Probing for holes may have moved the file offset.
+                            file.seek(SeekFrom::Start(0))?;
+                            self.append_entry(&mut header, path, None, records, file)?
+                        }
+                    }
+                }
+            }
+            Source::Memory(data, mode) => {
+                memory_header(&mut header, EntryType::Regular, *mode, data.len() as u64)?;
+                self.append_entry(&mut header, path, None, vec![], &data[..])?;
+            }
+            Source::Dir(mode) => {
+                memory_header(&mut header, EntryType::Directory, *mode, 0)?;
+                self.append_entry(&mut header, path, None, vec![], empty())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends a sparse file in the pax 1.0 sparse format: the header names a
+    /// placeholder path, and the data starts with a map of the `segments`.
+    fn append_sparse(
+        &mut self,
+        header: &mut Header,
+        path: &Path,
+        mut records: Vec<(String, Vec<u8>)>,
+        file: fs::File,
+        segments: Vec<(u64, u64)>,
+    ) -> Result<()> {
+        let mut map = format!("{}\n", segments.len());
+        for (offset, len) in &segments {
+            map.push_str(&format!("{}\n{}\n", offset, len));
+        }
+        let mut map = map.into_bytes();
+        map.resize(map.len().div_ceil(512) * 512, 0);
+
+        let name = path_bytes(path)?.into_owned();
+        let file_name = path.file_name().unwrap_or_default();
+        let placeholder = match path.parent() {
+            Some(parent) => parent.join("GNUSparseFile.0").join(file_name),
+            None => Path::new("GNUSparseFile.0").join(file_name),
+        };
+        let realsize = header.entry_size()?;
+        records.push(("GNU.sparse.major".to_owned(), b"1".to_vec()));
+        records.push(("GNU.sparse.minor".to_owned(), b"0".to_vec()));
+        records.push(("GNU.sparse.name".to_owned(), name));
+        records.push((
+            "GNU.sparse.realsize".to_owned(),
+            realsize.to_string().into(),
+        ));
+
+        let data_size: u64 = segments.iter().map(|(_, len)| len).sum();
+        header.set_size(map.len() as u64 + data_size);
+        let data = io::Cursor::new(map).chain(SparseReader {
+            file,
+            segments: segments.into_iter(),
+            remaining: 0,
+        });
+        self.append_entry(header, &placeholder, None, records, data)
+    }
+
+    /// Appends an entry, encoding its path, link name and any extra pax
+    /// `records` as the format requires.
+    fn append_entry<R: Read>(
+        &mut self,
+        header: &mut Header,
+        path: &Path,
+        link: Option<&Path>,
+        mut records: Vec<(String, Vec<u8>)>,
+        data: R,
+    ) -> Result<()> {
+        let builder = &mut self.builder;
+        match self.format {
+            TarFormat::Gnu => match link {
+                Some(link) => builder.append_link(header, path, link)?,
+                None => builder.append_data(header, path, data)?,
+            },
+            TarFormat::Ustar => {
+                let hint = "try the pax tar format";
+                header
+                    .set_path(path)
+                    .with_context(|| format!("path doesn't fit in a ustar header, {}", hint))?;
+                if let Some(link) = link {
+                    header
+                        .set_link_name(link)
+                        .with_context(|| format!("link doesn't fit in a ustar header, {}", hint))?;
+                }
+                if header.entry_size()? > USTAR_MAX_SIZE {
+                    bail!("file is too large for a ustar header, {}", hint);
+                }
+                header.set_cksum();
+                builder.append(header, data)?;
+            }
+            TarFormat::Pax => {
+                let name = path_bytes(path)?;
+                if !name.is_ascii() || header.set_path(path).is_err() {
+                    records.push(("path".to_owned(), name.to_vec()));
+                    if let Some(ustar) = header.as_ustar_mut() {
+                        ustar.prefix = [0; 155];
+                    }
+                    set_placeholder_name(&mut header.as_old_mut().name, &name);
+                }
+                if let Some(link) = link {
+                    let link_bytes = path_bytes(link)?;
+                    if !link_bytes.is_ascii() || header.set_link_name(link).is_err() {
+                        records.push(("linkpath".to_owned(), link_bytes.to_vec()));
+                        set_placeholder_name(&mut header.as_old_mut().linkname, &link_bytes);
+                    }
+                }
+                if records.iter().any(|(key, value)| {
+                    matches!(key.as_str(), "path" | "linkpath" | "GNU.sparse.name")
+                        && std::str::from_utf8(value).is_err()
+                }) {
+                    // Names that aren't UTF-8 are stored as raw bytes.
+                    records.insert(0, ("hdrcharset".to_owned(), b"BINARY".to_vec()));
+                }
+                let size = header.entry_size()?;
+                if size > USTAR_MAX_SIZE {
+                    records.push(("size".to_owned(), size.to_string().into_bytes()));
+                }
+                if !records.is_empty() {
+                    append_pax_records(builder, &name, &records)?;
+                }
+                header.set_cksum();
+                builder.append(header, data)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Marks files that usually should be executable as such on Windows, which
+/// doesn't really have a mode, so `tar` never marks files executable.
+fn mark_executable(header: &mut Header, src: &Path) -> Result<()> {
+    if cfg!(windows) {
+        // Use an extension whitelist to update files that usually should be so.
+        const EXECUTABLES: [&str; 4] = ["exe", "dll", "py", "sh"];
+        if let Some(ext) = src.extension().and_then(|s| s.to_str()) {
+            if EXECUTABLES.contains(&ext) {
+                let mode = header.mode()?;
+                header.set_mode(mode | 0o111);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Appends an entry to a zip archive, with the mode and time it gets in the
+/// tarball. Zip has no hardlinks, so identical files are stored in full.
+fn append_zip<W: Write + Seek>(zip: &mut ZipWriter<W>, source: &Source, path: &Path) -> Result<()> {
+    let name = zip_name(path)?;
     let mut header = Header::new_gnu();
-    header.set_metadata(&stat);
-    if stat.file_type().is_symlink() {
-        let link = read_link(src)?;
-        header.set_link_name(&link)?;
-        builder.append_data(&mut header, path, &mut empty())?;
+    match source {
+        Source::Path(src) => {
+            let stat = symlink_metadata(src)?;
+            header.set_metadata(&stat);
+            if stat.file_type().is_symlink() {
+                let target = zip_name(&read_link(src)?)?;
+                zip.add_symlink(name, target, zip_options(&header)?)?;
+            } else if stat.is_dir() {
+                zip.add_directory(name, zip_options(&header)?)?;
+            } else {
+                mark_executable(&mut header, src)?;
+                let options = zip_options(&header)?.large_file(stat.len() >= u32::MAX.into());
+                zip.start_file(name, options)?;
+                io::copy(&mut open_file(src)?, zip)?;
+            }
+        }
+        Source::Memory(data, mode) => {
+            memory_header(&mut header, EntryType::Regular, *mode, data.len() as u64)?;
+            zip.start_file(name, zip_options(&header)?)?;
+            zip.write_all(data)?;
+        }
+        Source::Dir(mode) => {
+            memory_header(&mut header, EntryType::Directory, *mode, 0)?;
+            zip.add_directory(name, zip_options(&header)?)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns `path` as it's named in a zip archive, with `/` separators.
+fn zip_name(path: &Path) -> Result<String> {
+    let name = path_to_str(path)?;
+    Ok(if cfg!(windows) {
+        name.replace('\\', "/")
     } else {
-        if cfg!(windows) {
-            // Windows doesn't really have a mode, so `tar` never marks files executable.
-            // Use an extension whitelist to update files that usually should be so.
-            const EXECUTABLES: [&'static str; 4] = ["exe", "dll", "py", "sh"];
-            if let Some(ext) = src.extension().and_then(|s| s.to_str()) {
-                if EXECUTABLES.contains(&ext) {
-                    let mode = header.mode()?;
-                    header.set_mode(mode | 0o111);
+        name.to_owned()
+    })
+}
+
+/// Returns the zip options for an entry with the mode and time of `header`.
+fn zip_options(header: &Header) -> Result<FileOptions> {
+    Ok(FileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .compression_level(Some(9))
+        .unix_permissions(header.mode()?)
+        .last_modified_time(zip_time(header.mtime()?)))
+}
+
+/// Converts a Unix time to the MS-DOS date and time of zip entries, in UTC.
+/// Times outside of the 1980 to 2107 range they cover become 1980-01-01.
+fn zip_time(time: u64) -> DateTime {
+    // The civil date of a day count, from Howard Hinnant's date algorithms.
+    let z = (time / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    let seconds = time % 86400;
+    u16::try_from(year)
+        .ok()
+        .and_then(|year| {
+            DateTime::from_date_and_time(
+                year,
+                month as u8,
+                day as u8,
+                (seconds / 3600) as u8,
+                (seconds / 60 % 60) as u8,
+                (seconds % 60) as u8,
+            )
+            .ok()
+        })
+        .unwrap_or_default()
+}
+
+/// Fills in a header for an entry synthesized in memory, stamped like the
+/// generated packages, with `SOURCE_DATE_EPOCH` if it's set.
+fn memory_header(header: &mut Header, entry_type: EntryType, mode: u32, size: u64) -> Result<()> {
+    header.set_entry_type(entry_type);
+    header.set_mode(mode);
+    header.set_size(size);
+    header.set_mtime(timestamp()?);
+    Ok(())
+}
+
+/// The largest size that fits in the octal size field of a ustar header.
+const USTAR_MAX_SIZE: u64 = 0o77777777777;
+
+/// Reads the data segments of a sparse file back to back.
+struct SparseReader {
+    file: fs::File,
+    segments: std::vec::IntoIter<(u64, u64)>,
+    remaining: u64,
+}
+
+impl Read for SparseReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.remaining == 0 {
+            match self.segments.next() {
+                Some((offset, len)) => {
+                    self.file.seek(SeekFrom::Start(offset))?;
+                    self.remaining = len;
                 }
+                None => return Ok(0),
             }
         }
-        let file = open_file(src)?;
-        builder.append_data(&mut header, path, &file)?;
+        let max = self.remaining.min(buf.len() as u64) as usize;
+        let n = self.file.read(&mut buf[..max])?;
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "sparse file shrank while reading",
+            ));
+        }
+        self.remaining -= n as u64;
+        Ok(n)
     }
+}
+
+/// Appends a pax extended header carrying `records` for the entry at `path`.
+fn append_pax_records<W: Write>(
+    builder: &mut Builder<W>,
+    path: &[u8],
+    records: &[(String, Vec<u8>)],
+) -> Result<()> {
+    let mut data = vec![];
+    for (key, value) in records {
+        // Each record is "<len> <key>=<value>\n", where <len> counts its own digits.
+        let rest = key.len() + value.len() + 3;
+        let mut len = rest + 1;
+        while len != rest + len.to_string().len() {
+            len = rest + len.to_string().len();
+        }
+        data.extend_from_slice(format!("{} {}=", len, key).as_bytes());
+        data.extend_from_slice(value);
+        data.push(b'\n');
+    }
+
+    let mut header = Header::new_ustar();
+    let file_name = path.rsplit(|&b| b == b'/').next().unwrap_or_default();
+    let mut name = b"PaxHeaders/".to_vec();
+    name.extend_from_slice(file_name);
+    set_placeholder_name(&mut header.as_old_mut().name, &name);
+    header.set_entry_type(EntryType::XHeader);
+    header.set_mode(0o644);
+    header.set_size(data.len() as u64);
+    header.set_cksum();
+    builder.append(&header, &data[..])?;
     Ok(())
 }
 
+/// Fills a header name field with an ASCII stand-in for a name stored in a
+/// pax record, keeping its tail since that's where the file name is.
+fn set_placeholder_name(field: &mut [u8], name: &[u8]) {
+    let name: Vec<u8> = name
+        .iter()
+        .map(|&b| if b.is_ascii() && b != 0 { b } else { b'_' })
+        .collect();
+    let tail = &name[name.len().saturating_sub(field.len())..];
+    field.iter_mut().for_each(|b| *b = 0);
+    field[..tail.len()].copy_from_slice(tail);
+}
+
 /// Returns all `(directories, files)` under the source path.
-fn get_recursive_paths<P, Q>(root: P, name: Q) -> Result<(Vec<String>, Vec<String>)>
+fn get_recursive_paths<P, Q>(root: P, name: Q) -> Result<(Vec<PathBuf>, Vec<PathBuf>)>
 where
     P: AsRef<Path>,
     Q: AsRef<Path>,
@@ -131,7 +692,6 @@ where
     for entry in WalkDir::new(root.join(name)) {
         let entry = entry?;
         let path = entry.path().strip_prefix(root)?;
-        let path = path_to_str(&path)?;
 
         if entry.file_type().is_dir() {
             dirs.push(path.to_owned());
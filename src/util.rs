@@ -1,6 +1,11 @@
-use anyhow::{format_err, Context, Result};
+use anyhow::{bail, format_err, Context, Error, Result};
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
 use std::fs;
-use std::path::Path;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::{fmt, str::FromStr};
 use walkdir::WalkDir;
 
 // Needed to set the script mode to executable.
@@ -26,7 +31,7 @@ pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<u64> {
         symlink_file(link, &to)?;
         Ok(0)
     } else {
-        let amt = fs::copy(&from, &to).with_context(|| {
+        let amt = copy_file(from.as_ref(), to.as_ref()).with_context(|| {
             format!(
                 "failed to copy '{}' to '{}'",
                 from.as_ref().display(),
@@ -37,6 +42,364 @@ pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<u64> {
     }
 }
 
+/// Copies a regular file like `fs::copy`, but keeps the holes of sparse files.
+fn copy_file(from: &Path, to: &Path) -> io::Result<u64> {
+    let mut src = fs::File::open(from)?;
+    let metadata = src.metadata()?;
+    let segments = match data_segments(&src, metadata.len()) {
+        Some(segments) => segments,
+        None => return fs::copy(from, to),
+    };
+    let mut dst = fs::File::create(to)?;
+    for (offset, len) in segments {
+        src.seek(SeekFrom::Start(offset))?;
+        dst.seek(SeekFrom::Start(offset))?;
+        io::copy(&mut (&mut src).take(len), &mut dst)?;
+    }
+    dst.set_len(metadata.len())?;
+    dst.set_permissions(metadata.permissions())?;
+    Ok(metadata.len())
+}
+
+/// Returns the `(offset, length)` data segments of a file with holes, ending
+/// with an empty segment at its size if it ends in a hole. Returns `None` if
+/// the file has no holes, or if the platform can't tell.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn data_segments(file: &fs::File, size: u64) -> Option<Vec<(u64, u64)>> {
+    use std::os::unix::io::AsRawFd;
+
+    let fd = file.as_raw_fd();
+    let mut segments = vec![];
+    let mut offset = 0;
+    while offset < size {
+        // SAFETY: `fd` stays open for the duration of the calls.
+        let data = unsafe { libc::lseek(fd, offset as libc::off_t, libc::SEEK_DATA) };
+        if data < 0 {
+            if io::Error::last_os_error().raw_os_error() == Some(libc::ENXIO) {
+                // There's no more data before the end of the file.
+                break;
+            }
+            return None;
+        }
+        let hole = unsafe { libc::lseek(fd, data, libc::SEEK_HOLE) };
+        if hole < 0 {
+            return None;
+        }
+        segments.push((data as u64, (hole - data) as u64));
+        offset = hole as u64;
+    }
+    if segments == [(0, size)] || size == 0 {
+        return None;
+    }
+    if segments
+        .last()
+        .map_or(true, |(offset, len)| offset + len < size)
+    {
+        segments.push((size, 0));
+    }
+    Some(segments)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn data_segments(_file: &fs::File, _size: u64) -> Option<Vec<(u64, u64)>> {
+    None
+}
+
+/// Returns the architecture this was built for, as `std::env::consts::ARCH`
+/// names it but with `powerpc64le` for little-endian `powerpc64`, like target
+/// triples do. The package formats each map it to their own names.
+pub fn host_arch() -> &'static str {
+    match std::env::consts::ARCH {
+        "powerpc64" if cfg!(target_endian = "little") => "powerpc64le",
+        arch => arch,
+    }
+}
+
+/// Returns the bytes of a path as stored in a tarball or manifest, with `/` separators.
+pub fn path_bytes(path: &Path) -> Result<Cow<'_, [u8]>> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Ok(Cow::Borrowed(path.as_os_str().as_bytes()))
+    }
+    #[cfg(not(unix))]
+    {
+        let path = path
+            .to_str()
+            .ok_or_else(|| anyhow::format_err!("path is not valid Unicode '{}'", path.display()))?;
+        Ok(Cow::Owned(path.replace('\\', "/").into_bytes()))
+    }
+}
+
+/// Escapes a relative path for `manifest.in`, with `/` separators. Backslashes,
+/// newlines, other control characters and bytes that aren't UTF-8 are written
+/// as `\\`, `\n` and `\0ooo` octal escapes, which the installer decodes with
+/// `printf %b`.
+pub fn escape_path(path: &Path) -> Result<String> {
+    let mut escaped = String::new();
+    let mut rest = &*path_bytes(path)?;
+    while !rest.is_empty() {
+        let (valid, invalid) = match std::str::from_utf8(rest) {
+            Ok(valid) => (valid, &[][..]),
+            Err(e) => {
+                let (valid, invalid) = rest.split_at(e.valid_up_to());
+                let len = e.error_len().unwrap_or(invalid.len());
+                (std::str::from_utf8(valid)?, &invalid[..len])
+            }
+        };
+        for c in valid.chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                '\n' => escaped.push_str("\\n"),
+                c if c.is_control() => {
+                    let mut buf = [0; 4];
+                    for b in c.encode_utf8(&mut buf).bytes() {
+                        escaped.push_str(&format!("\\0{:03o}", b));
+                    }
+                }
+                c => escaped.push(c),
+            }
+        }
+        for b in invalid {
+            escaped.push_str(&format!("\\0{:03o}", b));
+        }
+        rest = &rest[valid.len() + invalid.len()..];
+    }
+    Ok(escaped)
+}
+
+/// Reverses `escape_path`, for the paths of the manifests, which the installer
+/// also escapes when it records installed paths.
+pub fn unescape_path(escaped: &[u8]) -> Result<PathBuf> {
+    let mut bytes = Vec::with_capacity(escaped.len());
+    let mut rest = escaped;
+    while let Some((&b, tail)) = rest.split_first() {
+        rest = tail;
+        if b != b'\\' {
+            bytes.push(b);
+            continue;
+        }
+        match rest.split_first() {
+            Some((b'\\', tail)) => {
+                bytes.push(b'\\');
+                rest = tail;
+            }
+            Some((b'n', tail)) => {
+                bytes.push(b'\n');
+                rest = tail;
+            }
+            Some((b'0', tail)) => {
+                let len = tail
+                    .iter()
+                    .take(3)
+                    .take_while(|b| (b'0'..=b'7').contains(b))
+                    .count();
+                let mut value = 0u32;
+                for digit in &tail[..len] {
+                    value = value * 8 + u32::from(digit - b'0');
+                }
+                bytes.push(value as u8);
+                rest = &tail[len..];
+            }
+            _ => bytes.push(b),
+        }
+    }
+    path_from_bytes(bytes)
+}
+
+/// Reverses `path_bytes`.
+pub fn path_from_bytes(bytes: Vec<u8>) -> Result<PathBuf> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        Ok(PathBuf::from(std::ffi::OsString::from_vec(bytes)))
+    }
+    #[cfg(not(unix))]
+    {
+        let path = String::from_utf8(bytes).context("path is not valid Unicode")?;
+        Ok(PathBuf::from(path))
+    }
+}
+
+/// How regular files are placed when staging a directory tree.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum CopyMode {
+    /// Always make a full copy.
+    #[default]
+    Copy,
+    /// Hardlink to the source, falling back to a copy across filesystems.
+    Hardlink,
+    /// Clone the source's extents where the filesystem supports it, falling
+    /// back to a copy otherwise.
+    Reflink,
+}
+
+impl FromStr for CopyMode {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "copy" => Ok(CopyMode::Copy),
+            "hardlink" => Ok(CopyMode::Hardlink),
+            "reflink" => Ok(CopyMode::Reflink),
+            other => anyhow::bail!("unknown copy mode: {}", other),
+        }
+    }
+}
+
+impl fmt::Display for CopyMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(
+            match self {
+                CopyMode::Copy => "copy",
+                CopyMode::Hardlink => "hardlink",
+                CopyMode::Reflink => "reflink",
+            },
+            f,
+        )
+    }
+}
+
+/// Places the file `from` at `to` according to `mode`. Linking and cloning
+/// fall back to a plain copy if the filesystem doesn't support them.
+///
+/// Note that with `CopyMode::Hardlink` the destination shares its inode with
+/// the source, so it must not be modified in place.
+pub fn link_or_copy<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q, mode: CopyMode) -> Result<()> {
+    let (from, to) = (from.as_ref(), to.as_ref());
+    if mode == CopyMode::Copy || fs::symlink_metadata(from)?.file_type().is_symlink() {
+        copy(from, to)?;
+        return Ok(());
+    }
+    let linked = match mode {
+        CopyMode::Hardlink => fs::hard_link(from, to),
+        _ => reflink(from, to),
+    };
+    if linked.is_err() {
+        copy(from, to)?;
+    }
+    Ok(())
+}
+
+/// Clones `from` into the new file `to` with the `FICLONE` ioctl.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn reflink(from: &Path, to: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let src = fs::File::open(from)?;
+    let dst = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(to)?;
+    // SAFETY: both descriptors are open for the duration of the call.
+    if unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE as _, src.as_raw_fd()) } == -1 {
+        let err = io::Error::last_os_error();
+        drop(dst);
+        let _ = fs::remove_file(to);
+        return Err(err);
+    }
+    dst.set_permissions(src.metadata()?.permissions())?;
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn reflink(_from: &Path, _to: &Path) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Returns the extended attributes of `path`, without following symlinks.
+#[cfg(unix)]
+pub fn read_xattrs(path: &Path) -> Result<Vec<(String, Vec<u8>)>> {
+    let context = || format!("failed to read extended attributes of '{}'", path.display());
+    let mut attrs = vec![];
+    for name in xattr::list(path).with_context(context)? {
+        let name = name.into_string().map_err(|name| {
+            format_err!("extended attribute name is not valid UTF-8: {:?}", name)
+        })?;
+        if let Some(value) = xattr::get(path, &name).with_context(context)? {
+            attrs.push((name, value));
+        }
+    }
+    attrs.sort();
+    Ok(attrs)
+}
+
+#[cfg(not(unix))]
+pub fn read_xattrs(_path: &Path) -> Result<Vec<(String, Vec<u8>)>> {
+    Ok(vec![])
+}
+
+/// Formats one line of an `xattrs.in` file: the attribute name, its value in
+/// the hex notation understood by `setfattr`, and the path, separated by tabs.
+pub fn xattr_line(name: &str, value: &[u8], path: &str) -> Result<String> {
+    if name.contains(['\t', '\n']) || path.contains(['\t', '\n']) {
+        bail!(
+            "rust-installer doesn't support extended attribute '{}' on {:?}",
+            name,
+            path
+        );
+    }
+    let value = if value.is_empty() {
+        "\"\"".to_owned()
+    } else {
+        format!("0x{}", to_hex(value))
+    };
+    Ok(format!("{}\t{}\t{}\n", name, value, path))
+}
+
+/// Sets the extended attributes listed in `dir/xattrs.in` on the files
+/// beneath `dir`. Attributes that need more privileges or aren't supported
+/// by the filesystem are skipped, since the installer restores them again.
+pub fn apply_xattrs(dir: &Path) -> Result<()> {
+    let list = dir.join("xattrs.in");
+    if !list.exists() {
+        return Ok(());
+    }
+    let contents = fs::read_to_string(&list)
+        .with_context(|| format!("failed to read '{}'", list.display()))?;
+    for line in contents.lines() {
+        let mut fields = line.splitn(3, '\t');
+        let (name, value, path) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(name), Some(value), Some(path)) => (name, value, path),
+            _ => bail!("malformed line in '{}': {:?}", list.display(), line),
+        };
+        let value = match value.strip_prefix("0x") {
+            Some(hex) => (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(hex.get(i..i + 2).unwrap_or("?"), 16))
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("malformed value in '{}'", list.display()))?,
+            None => vec![],
+        };
+        set_xattr(&dir.join(path), name, &value)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_xattr(path: &Path, name: &str, value: &[u8]) -> Result<()> {
+    match xattr::set(path, name, value) {
+        Err(e)
+            if e.kind() == io::ErrorKind::PermissionDenied
+                || e.kind() == io::ErrorKind::Unsupported =>
+        {
+            Ok(())
+        }
+        result => result.with_context(|| {
+            format!(
+                "failed to set extended attribute '{}' on '{}'",
+                name,
+                path.display()
+            )
+        }),
+    }
+}
+
+#[cfg(not(unix))]
+fn set_xattr(_path: &Path, _name: &str, _value: &[u8]) -> Result<()> {
+    Ok(())
+}
+
 /// Wraps `fs::create_dir` with a nicer error message.
 pub fn create_dir<P: AsRef<Path>>(path: P) -> Result<()> {
     fs::create_dir(&path)
@@ -80,6 +443,19 @@ pub fn open_file<P: AsRef<Path>>(path: P) -> Result<fs::File> {
     Ok(file)
 }
 
+/// Returns the SHA-256 of the contents of a file, in hex.
+pub fn sha256_file<P: AsRef<Path>>(path: P) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut open_file(&path)?, &mut hasher)
+        .with_context(|| format!("failed to read file '{}'", path.as_ref().display()))?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Returns `bytes` in lowercase hex, as digests are written.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
 /// Wraps `remove_dir_all` with a nicer error message.
 pub fn remove_dir_all<P: AsRef<Path>>(path: P) -> Result<()> {
     remove_dir_all::remove_dir_all(path.as_ref())
@@ -96,52 +472,193 @@ pub fn remove_file<P: AsRef<Path>>(path: P) -> Result<()> {
 
 /// Copies the `src` directory recursively to `dst`. Both are assumed to exist
 /// when this function is called.
-pub fn copy_recursive(src: &Path, dst: &Path) -> Result<()> {
-    copy_with_callback(src, dst, |_, _| Ok(()))
+pub fn copy_recursive(src: &Path, dst: &Path, mode: CopyMode) -> Result<()> {
+    copy_with_callback(src, dst, mode, &PathFilter::default(), |_, _| Ok(()))
 }
 
 /// Copies the `src` directory recursively to `dst`. Both are assumed to exist
 /// when this function is called. Invokes a callback for each path visited.
-pub fn copy_with_callback<F>(src: &Path, dst: &Path, mut callback: F) -> Result<()>
+/// Paths rejected by `filter` are neither copied nor visited.
+pub fn copy_with_callback<F>(
+    src: &Path,
+    dst: &Path,
+    mode: CopyMode,
+    filter: &PathFilter,
+    mut callback: F,
+) -> Result<()>
 where
     F: FnMut(&Path, fs::FileType) -> Result<()>,
 {
-    for entry in WalkDir::new(src).min_depth(1) {
-        let entry = entry?;
-        let file_type = entry.file_type();
-        let path = entry.path().strip_prefix(src)?;
+    walk_with_callback(src, filter, |path, file_type| {
         let dst = dst.join(path);
 
         if file_type.is_dir() {
             create_dir(&dst)?;
         } else {
-            copy(entry.path(), dst)?;
+            link_or_copy(src.join(path), dst, mode)?;
+        }
+        callback(path, file_type)
+    })
+}
+
+/// Walks the `src` directory recursively, invoking a callback with each path
+/// relative to `src`. Directories are visited before their contents, and
+/// excluded directories aren't descended into. Directories that the include
+/// patterns don't select are only visited if something below them is.
+pub fn walk_with_callback<F>(src: &Path, filter: &PathFilter, mut callback: F) -> Result<()>
+where
+    F: FnMut(&Path, fs::FileType) -> Result<()>,
+{
+    let relative = |path: &Path| path.strip_prefix(src).unwrap_or(path).to_owned();
+    let entries = WalkDir::new(src)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|entry| !filter.is_excluded(&relative(entry.path())));
+    // The unselected directories above the current path, not visited yet.
+    let mut pending: Vec<(PathBuf, fs::FileType)> = vec![];
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path().strip_prefix(src)?;
+        while pending
+            .last()
+            .is_some_and(|(dir, _)| !path.starts_with(dir))
+        {
+            pending.pop();
+        }
+        if !filter.is_included(path) {
+            if entry.file_type().is_dir() {
+                pending.push((path.to_owned(), entry.file_type()));
+            }
+            continue;
         }
-        callback(&path, file_type)?;
+        for (dir, file_type) in pending.drain(..) {
+            callback(&dir, file_type)?;
+        }
+        callback(path, entry.file_type())?;
     }
     Ok(())
 }
 
+/// The file at the root of an image listing more paths to exclude, one glob
+/// pattern per line.
+pub const IGNORE_FILE: &str = ".installerignore";
+
+/// Glob patterns selecting the paths of an image to walk. The default filter
+/// accepts everything.
+///
+/// Patterns without a `/` match a file or directory name at any depth, like
+/// `*.pdb` or `__pycache__`; others match the path from the image root, like
+/// `share/doc/*.html`. Excluding a directory excludes everything below it, and
+/// including a directory includes everything below it.
+#[derive(Debug, Default)]
+pub struct PathFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl PathFilter {
+    /// Creates a filter from the `include` and `exclude` glob lists, adding
+    /// the exclusions from `root/.installerignore` if it exists.
+    pub fn new(root: &Path, include: &[String], exclude: &[String]) -> Result<Self> {
+        let mut exclude = exclude.to_vec();
+
+        let ignore_file = root.join(IGNORE_FILE);
+        if ignore_file.is_file() {
+            let contents = fs::read_to_string(&ignore_file)
+                .with_context(|| format!("failed to read '{}'", ignore_file.display()))?;
+            exclude.extend(
+                contents
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(str::to_owned),
+            );
+        }
+
+        let include = if include.is_empty() {
+            None
+        } else {
+            Some(glob_set(include)?)
+        };
+        Ok(PathFilter {
+            include,
+            exclude: glob_set(&exclude)?,
+        })
+    }
+
+    /// Whether `path`, relative to the image root, is skipped with everything below it.
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        path == Path::new(IGNORE_FILE) || self.exclude.is_match(path)
+    }
+
+    /// Whether the file at `path`, relative to the image root, is selected by
+    /// the include patterns.
+    pub fn is_included(&self, path: &Path) -> bool {
+        match &self.include {
+            Some(include) => path.ancestors().any(|p| include.is_match(p)),
+            None => true,
+        }
+    }
+}
+
+/// Returns the items of a comma-separated list given as a flag.
+pub fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .filter(|s| !s.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Compiles glob patterns relative to an image root, where `*` doesn't match `/`.
+pub fn glob_set<S: AsRef<str>>(patterns: &[S]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(path_glob(pattern.as_ref(), false)?);
+    }
+    Ok(builder.build()?)
+}
+
+/// Compiles a glob pattern matching paths relative to an image root. Unless
+/// `anchored`, a pattern without a `/` matches a name at any depth.
+pub fn path_glob(pattern: &str, anchored: bool) -> Result<Glob> {
+    let glob = match pattern.trim_start_matches('/').trim_end_matches('/') {
+        p if anchored || p.contains('/') => p.to_owned(),
+        p => format!("**/{}", p),
+    };
+    GlobBuilder::new(&glob)
+        .literal_separator(true)
+        .build()
+        .with_context(|| format!("invalid glob pattern '{}'", pattern))
+}
+
 macro_rules! actor_field_default {
-    () => { Default::default() };
-    (= $expr:expr) => { $expr.into() }
+    () => {
+        Default::default()
+    };
+    (= $expr:expr) => {
+        $expr.into()
+    };
 }
 
 /// Creates an "actor" with default values, setters for all fields, and Clap parser support.
+/// Fields without attributes come last, and aren't command line flags.
 macro_rules! actor {
     ($( #[ $attr:meta ] )+ pub struct $name:ident {
         $( $( #[ $field_attr:meta ] )+ $field:ident : $type:ty $(= $default:tt)*, )*
+        $( $state:ident : $state_type:ty, )*
     }) => {
         $( #[ $attr ] )+
         #[derive(clap::Args)]
         pub struct $name {
             $( $( #[ $field_attr ] )+ #[clap(long, $(default_value = $default)*)] $field : $type, )*
+            $( #[clap(skip)] $state : $state_type, )*
         }
 
         impl Default for $name {
             fn default() -> $name {
                 $name {
                     $($field : actor_field_default!($(= $default)*), )*
+                    $($state : Default::default(), )*
                 }
             }
         }
@@ -151,6 +668,10 @@ macro_rules! actor {
                 self.$field = value;
                 self
             })*
+            $(pub fn $state(&mut self, value: $state_type) -> &mut Self {
+                self.$state = value;
+                self
+            })*
         }
     }
 }
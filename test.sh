@@ -88,6 +88,23 @@ try() {
     set -e
 }
 
+# Like try, but keeps arguments that contain spaces whole
+try_quoted() {
+    set +e
+    _cmd="$*"
+    _output=`"$@" 2>&1`
+    if [ $? -ne 0 ]; then
+	echo \$ "$_cmd"
+	# Using /bin/echo to avoid escaping
+	$ECHO "$_output"
+	echo
+	echo "TEST FAILED!"
+	echo
+	exit 1
+    fi
+    set -e
+}
+
 expect_fail() {
     set +e
     _cmd="$@"
@@ -1337,6 +1354,2831 @@ tarball_compression_formats_error() {
 }
 runtest tarball_compression_formats_error
 
+generate_copy_mode_hardlink() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --copy-mode=hardlink
+    try test "$TEST_DIR/image1/bin/program" -ef "$WORK_DIR/package/component/bin/program"
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR"
+    try test -x "$PREFIX_DIR/bin/program"
+    try test -e "$PREFIX_DIR/dir-to-install/foo"
+}
+runtest generate_copy_mode_hardlink
+
+generate_copy_mode_reflink() {
+    # Falls back to copying on filesystems without reflink support
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --copy-mode=reflink
+    try cmp "$TEST_DIR/image1/bin/program" "$WORK_DIR/package/component/bin/program"
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR"
+    try test -x "$PREFIX_DIR/bin/program"
+    try test -e "$PREFIX_DIR/dir-to-install/foo"
+}
+runtest generate_copy_mode_reflink
+
+generate_copy_mode_error() {
+    expect_fail sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --copy-mode=symlink
+}
+runtest generate_copy_mode_error
+
+combine_copy_mode_hardlink() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --package-name=rustc \
+        --component-name=rustc
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image3" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --package-name=cargo \
+        --component-name=cargo
+    try sh "$S/combine-installers.sh" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --package-name=rust \
+        --input-tarballs="$OUT_DIR/rustc.tar.gz,$OUT_DIR/cargo.tar.gz" \
+        --copy-mode=hardlink
+    try test "$WORK_DIR/rustc/rustc/bin/program" -ef "$WORK_DIR/rust/rustc/bin/program"
+    try "$WORK_DIR/rust/install.sh" --prefix="$PREFIX_DIR"
+    try test -e "$PREFIX_DIR/bin/program"
+    try test -e "$PREFIX_DIR/bin/cargo"
+}
+runtest combine_copy_mode_hardlink
+
+
+generate_stream() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR/staged" \
+        --output-dir="$OUT_DIR/staged" \
+        --bulk-dirs=dir-to-install
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR/unused" \
+        --output-dir="$OUT_DIR" \
+        --bulk-dirs=dir-to-install \
+        --stream
+    try test ! -e "$WORK_DIR/unused"
+    tar -tzf "$OUT_DIR/staged/package.tar.gz" | sort > "$WORK_DIR/staged.txt"
+    tar -tzf "$OUT_DIR/package.tar.gz" | sort > "$WORK_DIR/streamed.txt"
+    try diff "$WORK_DIR/staged.txt" "$WORK_DIR/streamed.txt"
+    try tar -xzf "$OUT_DIR/package.tar.gz" -C "$WORK_DIR"
+    try diff "$WORK_DIR/staged/package/component/manifest.in" "$WORK_DIR/package/component/manifest.in"
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR"
+    try test -x "$PREFIX_DIR/bin/program"
+    try test -e "$PREFIX_DIR/dir-to-install/foo"
+    try test -e "$PREFIX_DIR/something-to-install"
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR" --uninstall
+    try test ! -e "$PREFIX_DIR/dir-to-install"
+}
+runtest generate_stream
+
+generate_stream_reproducible() {
+    for _i in 1 2; do
+	try env SOURCE_DATE_EPOCH=1700000000 sh "$S/gen-installer.sh" \
+	    --image-dir="$TEST_DIR/image1" \
+	    --work-dir="$WORK_DIR" \
+	    --output-dir="$OUT_DIR/$_i" \
+	    --bulk-dirs=dir-to-install \
+	    --compression-formats=gz \
+	    --stream
+	# The entries made in memory would otherwise get the time of each run
+	sleep 1
+    done
+    try cmp "$OUT_DIR/1/package.tar.gz" "$OUT_DIR/2/package.tar.gz"
+}
+runtest generate_stream_reproducible
+
+generate_stream_with_overlay() {
+    mkdir -p "$WORK_DIR/overlay/docs"
+    touch "$WORK_DIR/overlay/not-installed"
+    touch "$WORK_DIR/overlay/docs/README"
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --non-installed-overlay="$WORK_DIR/overlay" \
+        --stream
+    try tar -xzf "$OUT_DIR/package.tar.gz" -C "$WORK_DIR"
+    try test -e "$WORK_DIR/package/not-installed"
+    try test -e "$WORK_DIR/package/docs/README"
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR"
+    try test ! -e "$PREFIX_DIR/not-installed"
+    try test -e "$PREFIX_DIR/bin/program"
+}
+runtest generate_stream_with_overlay
+
+
+combine_rejects_escaping_symlink() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR"
+    ln -s ../../outside "$WORK_DIR/package/component/evil"
+    try tar -czf "$OUT_DIR/package.tar.gz" -C "$WORK_DIR" package
+    expect_output_fail "points outside of the package" sh "$S/combine-installers.sh" \
+        --work-dir="$WORK_DIR/combine" \
+        --output-dir="$OUT_DIR/combined" \
+        --package-name=rust \
+        --input-tarballs="$OUT_DIR/package.tar.gz"
+    try test ! -e "$WORK_DIR/combine/rust/component/evil"
+}
+runtest combine_rejects_escaping_symlink
+
+combine_rejects_symlink_chain() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR"
+    echo secret > "$WORK_DIR/secret.txt"
+    mkdir -p "$WORK_DIR/package/evil/c/a/b/c"
+    ln -s ../../.. "$WORK_DIR/package/evil/c/a/b/c/up"
+    ln -s a/b/c/up/../../../secret.txt "$WORK_DIR/package/evil/c/esc"
+    # Both with the link to go through first in the tarball, and last
+    (cd "$WORK_DIR" && find package ! -type l) > "$WORK_DIR/files"
+    (cat "$WORK_DIR/files"; echo package/evil/c/a/b/c/up; echo package/evil/c/esc) \
+        > "$WORK_DIR/list"
+    try tar --no-recursion -czf "$OUT_DIR/package.tar.gz" -C "$WORK_DIR" -T "$WORK_DIR/list"
+    expect_output_fail "points outside of the package" sh "$S/combine-installers.sh" \
+        --work-dir="$WORK_DIR/combine" \
+        --output-dir="$OUT_DIR/combined" \
+        --package-name=rust \
+        --input-tarballs="$OUT_DIR/package.tar.gz"
+    (cat "$WORK_DIR/files"; echo package/evil/c/esc; echo package/evil/c/a/b/c/up) \
+        > "$WORK_DIR/list"
+    try tar --no-recursion -czf "$OUT_DIR/package.tar.gz" -C "$WORK_DIR" -T "$WORK_DIR/list"
+    rm -rf "$WORK_DIR/combine"
+    expect_output_fail "points outside of the package" sh "$S/combine-installers.sh" \
+        --work-dir="$WORK_DIR/combine" \
+        --output-dir="$OUT_DIR/combined" \
+        --package-name=rust \
+        --input-tarballs="$OUT_DIR/package.tar.gz"
+    try test ! -L "$WORK_DIR/combine/package/evil/c/esc"
+    try test ! -e "$OUT_DIR/combined/rust.tar.gz"
+}
+runtest combine_rejects_symlink_chain
+
+combine_accepts_symlink_chain() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR"
+    ln -s foo "$WORK_DIR/package/component/dir-to-install/foo.1"
+    ln -s foo.1 "$WORK_DIR/package/component/dir-to-install/foo.2"
+    ln -s ../dir-to-install/foo.2 "$WORK_DIR/package/component/bin/foo-link"
+    try tar -czf "$OUT_DIR/package.tar.gz" -C "$WORK_DIR" package
+    try sh "$S/combine-installers.sh" \
+        --work-dir="$WORK_DIR/combine" \
+        --output-dir="$OUT_DIR/combined" \
+        --package-name=rust \
+        --input-tarballs="$OUT_DIR/package.tar.gz"
+    try test -L "$WORK_DIR/combine/rust/component/bin/foo-link"
+}
+runtest combine_accepts_symlink_chain
+
+combine_rejects_hardlink_to_symlink() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR"
+    mkdir -p "$WORK_DIR/package/component/bin/sub"
+    # Inside the package where it is, but not where the hardlink is
+    ln -s ../../../x "$WORK_DIR/package/component/bin/sub/link"
+    try ln -P "$WORK_DIR/package/component/bin/sub/link" "$WORK_DIR/package/evil"
+    try tar -czf "$OUT_DIR/package.tar.gz" -C "$WORK_DIR" package
+    tar -tvzf "$OUT_DIR/package.tar.gz" | grep -q "^h.* package/evil link to "
+    need_ok "tar didn't store a hardlink"
+    expect_output_fail "points outside of the package" sh "$S/combine-installers.sh" \
+        --work-dir="$WORK_DIR/combine" \
+        --output-dir="$OUT_DIR/combined" \
+        --package-name=rust \
+        --input-tarballs="$OUT_DIR/package.tar.gz"
+    try test ! -L "$WORK_DIR/combine/package/evil"
+}
+runtest combine_rejects_hardlink_to_symlink
+
+combine_accepts_internal_symlink() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR"
+    ln -s ../dir-to-install/foo "$WORK_DIR/package/component/bin/foo-link"
+    try tar -czf "$OUT_DIR/package.tar.gz" -C "$WORK_DIR" package
+    try sh "$S/combine-installers.sh" \
+        --work-dir="$WORK_DIR/combine" \
+        --output-dir="$OUT_DIR/combined" \
+        --package-name=rust \
+        --input-tarballs="$OUT_DIR/package.tar.gz"
+    try test -L "$WORK_DIR/combine/rust/component/bin/foo-link"
+}
+runtest combine_accepts_internal_symlink
+
+combine_rejects_parent_dir_path() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR"
+    touch "$WORK_DIR/escape"
+    try tar -czPf "$OUT_DIR/package.tar.gz" -C "$WORK_DIR" package package/../escape
+    expect_output_fail "unsafe path in tarball" sh "$S/combine-installers.sh" \
+        --work-dir="$WORK_DIR/combine" \
+        --output-dir="$OUT_DIR/combined" \
+        --package-name=rust \
+        --input-tarballs="$OUT_DIR/package.tar.gz"
+}
+runtest combine_rejects_parent_dir_path
+
+combine_rejects_absolute_path() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR"
+    touch "$WORK_DIR/absolute"
+    try tar -czPf "$OUT_DIR/package.tar.gz" -C "$WORK_DIR" package "$WORK_DIR/absolute"
+    expect_output_fail "unsafe path in tarball" sh "$S/combine-installers.sh" \
+        --work-dir="$WORK_DIR/combine" \
+        --output-dir="$OUT_DIR/combined" \
+        --package-name=rust \
+        --input-tarballs="$OUT_DIR/package.tar.gz"
+}
+runtest combine_rejects_absolute_path
+
+combine_rejects_special_files() {
+    # mkfifo doesn't work on windows
+    if [ ! -n "${WINDOWS-}" ]; then
+        try sh "$S/gen-installer.sh" \
+            --image-dir="$TEST_DIR/image1" \
+            --work-dir="$WORK_DIR" \
+            --output-dir="$OUT_DIR"
+        try mkfifo "$WORK_DIR/package/component/fifo"
+        try tar -czf "$OUT_DIR/package.tar.gz" -C "$WORK_DIR" package
+        expect_output_fail "unsupported entry type" sh "$S/combine-installers.sh" \
+            --work-dir="$WORK_DIR/combine" \
+            --output-dir="$OUT_DIR/combined" \
+            --package-name=rust \
+            --input-tarballs="$OUT_DIR/package.tar.gz"
+    fi
+}
+runtest combine_rejects_special_files
+
+combine_rejects_invalid_component_name() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR"
+    echo "../package" >> "$WORK_DIR/package/components"
+    try tar -czf "$OUT_DIR/package.tar.gz" -C "$WORK_DIR" package
+    expect_output_fail "is not a valid directory name" sh "$S/combine-installers.sh" \
+        --work-dir="$WORK_DIR/combine" \
+        --output-dir="$OUT_DIR/combined" \
+        --package-name=rust \
+        --input-tarballs="$OUT_DIR/package.tar.gz"
+}
+runtest combine_rejects_invalid_component_name
+
+
+# Creates an image with a path longer than 100 bytes and a non-ASCII file name
+make_long_path_image() {
+    local _long="$WORK_DIR/image/share/doc/a-rather-long-directory-name/another-rather-long-directory-name/yet-another-directory"
+    try mkdir -p "$_long"
+    echo long > "$_long/a-long-file-name-that-overflows-the-ustar-name-field.txt"
+    echo unicode > "$WORK_DIR/image/share/doc/ünïcödé.txt"
+    ln -s "a-rather-long-directory-name/another-rather-long-directory-name/yet-another-directory/a-long-file-name-that-overflows-the-ustar-name-field.txt" \
+        "$WORK_DIR/image/share/doc/long-link"
+}
+
+tarball_tar_format_pax() {
+    make_long_path_image
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --tar-format=pax
+    gzip -dc "$OUT_DIR/package.tar.gz" > "$WORK_DIR/package.tar"
+    try grep -aq "PaxHeaders" "$WORK_DIR/package.tar"
+    try test "$(grep -ac "@LongLink" "$WORK_DIR/package.tar")" = 0
+    try rm -r "$WORK_DIR/package"
+    try tar -xf "$WORK_DIR/package.tar" -C "$WORK_DIR"
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR"
+    try test -e "$PREFIX_DIR/share/doc/a-rather-long-directory-name/another-rather-long-directory-name/yet-another-directory/a-long-file-name-that-overflows-the-ustar-name-field.txt"
+    try test -e "$PREFIX_DIR/share/doc/ünïcödé.txt"
+    try test -L "$WORK_DIR/package/component/share/doc/long-link"
+}
+runtest tarball_tar_format_pax
+
+tarball_tar_format_gnu() {
+    make_long_path_image
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --tar-format=gnu
+    try rm -r "$WORK_DIR/package"
+    try tar -xzf "$OUT_DIR/package.tar.gz" -C "$WORK_DIR"
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR"
+    try test -e "$PREFIX_DIR/share/doc/a-rather-long-directory-name/another-rather-long-directory-name/yet-another-directory/a-long-file-name-that-overflows-the-ustar-name-field.txt"
+    try test -L "$WORK_DIR/package/component/share/doc/long-link"
+}
+runtest tarball_tar_format_gnu
+
+tarball_tar_format_ustar() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --tar-format=ustar
+    try rm -r "$WORK_DIR/package"
+    try tar -xzf "$OUT_DIR/package.tar.gz" -C "$WORK_DIR"
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR"
+    try test -e "$PREFIX_DIR/bin/program"
+}
+runtest tarball_tar_format_ustar
+
+tarball_tar_format_ustar_long_path_error() {
+    make_long_path_image
+    expect_output_fail "try the pax tar format" sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --tar-format=ustar
+}
+runtest tarball_tar_format_ustar_long_path_error
+
+combine_tar_format_pax() {
+    make_long_path_image
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --package-name=docs \
+        --component-name=docs \
+        --tar-format=pax
+    try sh "$S/combine-installers.sh" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --package-name=rust \
+        --input-tarballs="$OUT_DIR/docs.tar.gz" \
+        --tar-format=pax
+    try "$WORK_DIR/rust/install.sh" --prefix="$PREFIX_DIR"
+    try test -e "$PREFIX_DIR/share/doc/ünïcödé.txt"
+    try test -e "$PREFIX_DIR/share/doc/a-rather-long-directory-name/another-rather-long-directory-name/yet-another-directory/a-long-file-name-that-overflows-the-ustar-name-field.txt"
+}
+runtest combine_tar_format_pax
+
+tarball_tar_format_error() {
+    expect_fail sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --tar-format=v7
+}
+runtest tarball_tar_format_error
+
+
+generate_dedup() {
+    try mkdir -p "$WORK_DIR/image/share/doc/a" "$WORK_DIR/image/share/doc/b" "$WORK_DIR/image/bin"
+    echo "license text" > "$WORK_DIR/image/share/doc/a/LICENSE"
+    echo "license text" > "$WORK_DIR/image/share/doc/b/LICENSE"
+    echo "license text" > "$WORK_DIR/image/bin/not-a-license"
+    chmod +x "$WORK_DIR/image/bin/not-a-license"
+    echo "other text!!" > "$WORK_DIR/image/share/doc/b/OTHER"
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --dedup
+    # Only one of the identical LICENSE files is stored in full
+    try test "$(tar -tvzf "$OUT_DIR/package.tar.gz" | grep -c "LICENSE link to")" = 1
+    # Files with a different mode or contents aren't linked
+    try test "$(tar -tvzf "$OUT_DIR/package.tar.gz" | grep -c "not-a-license link to")" = 0
+    try test "$(tar -tvzf "$OUT_DIR/package.tar.gz" | grep -c "OTHER link to")" = 0
+    try rm -r "$WORK_DIR/package"
+    try tar -xzf "$OUT_DIR/package.tar.gz" -C "$WORK_DIR"
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR"
+    try cmp "$PREFIX_DIR/share/doc/a/LICENSE" "$WORK_DIR/image/share/doc/a/LICENSE"
+    try cmp "$PREFIX_DIR/share/doc/b/LICENSE" "$WORK_DIR/image/share/doc/b/LICENSE"
+    try test -x "$PREFIX_DIR/bin/not-a-license"
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR" --uninstall
+    try test ! -e "$PREFIX_DIR/share/doc/a/LICENSE"
+    try test ! -e "$PREFIX_DIR/share/doc/b/LICENSE"
+}
+runtest generate_dedup
+
+combine_dedup() {
+    try mkdir -p "$WORK_DIR/image-a/share/doc/a" "$WORK_DIR/image-b/share/doc/b"
+    echo "license text" > "$WORK_DIR/image-a/share/doc/a/LICENSE"
+    echo "license text" > "$WORK_DIR/image-b/share/doc/b/LICENSE"
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image-a" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --package-name=a \
+        --component-name=a
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image-b" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --package-name=b \
+        --component-name=b
+    try sh "$S/combine-installers.sh" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --package-name=rust \
+        --input-tarballs="$OUT_DIR/a.tar.gz,$OUT_DIR/b.tar.gz" \
+        --dedup
+    try test "$(tar -tvzf "$OUT_DIR/rust.tar.gz" | grep -c "LICENSE link to")" = 1
+    # Combining again unpacks the hardlinks
+    try sh "$S/combine-installers.sh" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --package-name=rust2 \
+        --input-tarballs="$OUT_DIR/rust.tar.gz"
+    try "$WORK_DIR/rust2/install.sh" --prefix="$PREFIX_DIR"
+    try test -e "$PREFIX_DIR/share/doc/a/LICENSE"
+    try test -e "$PREFIX_DIR/share/doc/b/LICENSE"
+}
+runtest combine_dedup
+
+# Sets an extended attribute with whatever tool is around, failing if the
+# filesystem or the environment doesn't support it
+set_test_xattr() {
+    if command -v setfattr > /dev/null 2>&1; then
+        setfattr -n "$1" -v "$2" "$3" 2> /dev/null
+    elif command -v python3 > /dev/null 2>&1; then
+        python3 -c 'import os, sys; os.setxattr(sys.argv[3], sys.argv[1], sys.argv[2].encode())' "$1" "$2" "$3" 2> /dev/null
+    else
+        return 1
+    fi
+}
+
+make_xattr_image() {
+    try mkdir -p "$WORK_DIR/image/bin" "$WORK_DIR/image/share/doc"
+    printf '#!/bin/sh\n' > "$WORK_DIR/image/bin/tool"
+    try chmod +x "$WORK_DIR/image/bin/tool"
+    echo "readme" > "$WORK_DIR/image/share/doc/README"
+    set_test_xattr user.test hello "$WORK_DIR/image/bin/tool"
+}
+
+generate_xattrs() {
+    if ! make_xattr_image; then return; fi
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --tar-format=pax \
+        --xattrs
+    test "$(cat "$WORK_DIR/package/component/xattrs.in")" = "$(printf 'user.test\t0x68656c6c6f\tbin/tool')"
+    need_ok "unexpected xattrs.in"
+    gzip -dc "$OUT_DIR/package.tar.gz" > "$WORK_DIR/package.tar"
+    try grep -aq "SCHILY.xattr.user.test=hello" "$WORK_DIR/package.tar"
+    try rm -r "$WORK_DIR/package"
+    try tar -xf "$WORK_DIR/package.tar" -C "$WORK_DIR"
+    # Installing works whether or not the attributes can be restored
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR"
+    try test -x "$PREFIX_DIR/bin/tool"
+    if command -v getfattr > /dev/null 2>&1; then
+        try test "$(getfattr --only-values -n user.test "$PREFIX_DIR/bin/tool")" = hello
+    fi
+}
+runtest generate_xattrs
+
+generate_stream_xattrs() {
+    if ! make_xattr_image; then return; fi
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --tar-format=pax \
+        --xattrs \
+        --stream
+    try tar -xzf "$OUT_DIR/package.tar.gz" -C "$WORK_DIR"
+    test "$(cat "$WORK_DIR/package/component/xattrs.in")" = "$(printf 'user.test\t0x68656c6c6f\tbin/tool')"
+    need_ok "unexpected xattrs.in"
+    gzip -dc "$OUT_DIR/package.tar.gz" | grep -aq "SCHILY.xattr.user.test=hello"
+    need_ok "tarball has no xattr record"
+}
+runtest generate_stream_xattrs
+
+generate_xattrs_need_pax() {
+    try mkdir -p "$WORK_DIR/image/bin"
+    printf '#!/bin/sh\n' > "$WORK_DIR/image/bin/tool"
+    expect_output_fail "need the pax tar format" sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --xattrs
+}
+runtest generate_xattrs_need_pax
+
+generate_sparse() {
+    try mkdir -p "$WORK_DIR/image/lib"
+    # 4MiB of holes around two blocks of data
+    echo "start" | dd of="$WORK_DIR/image/lib/sparse.img" bs=1M seek=1 conv=notrunc 2> /dev/null
+    echo "end" | dd of="$WORK_DIR/image/lib/sparse.img" bs=1M seek=3 conv=notrunc 2> /dev/null
+    try truncate -s 4M "$WORK_DIR/image/lib/sparse.img"
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --tar-format=pax \
+        --sparse
+    gzip -dc "$OUT_DIR/package.tar.gz" > "$WORK_DIR/package.tar"
+    try grep -aq "GNU.sparse.major=1" "$WORK_DIR/package.tar"
+    try test "$(wc -c < "$WORK_DIR/package.tar")" -lt 1048576
+    try rm -r "$WORK_DIR/package"
+    try tar -xf "$WORK_DIR/package.tar" -C "$WORK_DIR"
+    try cmp "$WORK_DIR/package/component/lib/sparse.img" "$WORK_DIR/image/lib/sparse.img"
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR"
+    try cmp "$PREFIX_DIR/lib/sparse.img" "$WORK_DIR/image/lib/sparse.img"
+}
+runtest generate_sparse
+
+combine_sparse() {
+    try mkdir -p "$WORK_DIR/image/lib"
+    echo "start" | dd of="$WORK_DIR/image/lib/sparse.img" bs=1M seek=1 conv=notrunc 2> /dev/null
+    echo "end" | dd of="$WORK_DIR/image/lib/sparse.img" bs=1M seek=3 conv=notrunc 2> /dev/null
+    try truncate -s 4M "$WORK_DIR/image/lib/sparse.img"
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --package-name=sparse \
+        --component-name=c1 \
+        --tar-format=pax \
+        --sparse
+    try sh "$S/combine-installers.sh" \
+        --work-dir="$WORK_DIR/combine" \
+        --output-dir="$OUT_DIR/combined" \
+        --package-name=rust \
+        --input-tarballs="$OUT_DIR/sparse.tar.gz" \
+        --tar-format=pax \
+        --sparse
+    try test ! -e "$WORK_DIR/combine/sparse/c1/lib/GNUSparseFile.0"
+    try cmp "$WORK_DIR/combine/sparse/c1/lib/sparse.img" "$WORK_DIR/image/lib/sparse.img"
+    gzip -dc "$OUT_DIR/combined/rust.tar.gz" > "$WORK_DIR/rust.tar"
+    try test "$(wc -c < "$WORK_DIR/rust.tar")" -lt 1048576
+    try tar -xf "$WORK_DIR/rust.tar" -C "$WORK_DIR"
+    try "$WORK_DIR/rust/install.sh" --prefix="$PREFIX_DIR"
+    try cmp "$PREFIX_DIR/lib/sparse.img" "$WORK_DIR/image/lib/sparse.img"
+    # Repairs read the sparse format too
+    try rm "$PREFIX_DIR/lib/sparse.img"
+    try sh "$S/repair-installation.sh" --prefix="$PREFIX_DIR" \
+        --input-tarball="$OUT_DIR/combined/rust.tar.gz"
+    try cmp "$PREFIX_DIR/lib/sparse.img" "$WORK_DIR/image/lib/sparse.img"
+}
+runtest combine_sparse
+
+combine_xattrs() {
+    if ! make_xattr_image; then return; fi
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --tar-format=pax \
+        --xattrs
+    try sh "$S/combine-installers.sh" \
+        --work-dir="$WORK_DIR/combine" \
+        --output-dir="$OUT_DIR" \
+        --package-name=rust \
+        --input-tarballs="$OUT_DIR/package.tar.gz" \
+        --tar-format=pax \
+        --xattrs
+    try test -e "$WORK_DIR/combine/rust/component/xattrs.in"
+    gzip -dc "$OUT_DIR/rust.tar.gz" | grep -aq "SCHILY.xattr.user.test=hello"
+    need_ok "tarball has no xattr record"
+}
+runtest combine_xattrs
+
+make_build_droppings_image() {
+    try mkdir -p "$WORK_DIR/image/bin" "$WORK_DIR/image/lib/tool/__pycache__" "$WORK_DIR/image/share/doc"
+    echo "program" > "$WORK_DIR/image/bin/program"
+    echo "symbols" > "$WORK_DIR/image/bin/program.pdb"
+    echo "script" > "$WORK_DIR/image/lib/tool/script.py"
+    echo "bytecode" > "$WORK_DIR/image/lib/tool/__pycache__/script.pyc"
+    echo "readme" > "$WORK_DIR/image/share/doc/README"
+    echo "finder" > "$WORK_DIR/image/share/doc/.DS_Store"
+    printf '# Python caches\n__pycache__\n\n' > "$WORK_DIR/image/.installerignore"
+}
+
+generate_exclude() {
+    make_build_droppings_image
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --exclude="*.pdb,.DS_Store"
+    try test -e "$WORK_DIR/package/component/bin/program"
+    try test ! -e "$WORK_DIR/package/component/bin/program.pdb"
+    try test ! -e "$WORK_DIR/package/component/lib/tool/__pycache__"
+    try test ! -e "$WORK_DIR/package/component/share/doc/.DS_Store"
+    try test ! -e "$WORK_DIR/package/component/.installerignore"
+    try test "$(grep -c . "$WORK_DIR/package/component/manifest.in")" = 3
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR"
+    try test -e "$PREFIX_DIR/lib/tool/script.py"
+    try test ! -e "$PREFIX_DIR/bin/program.pdb"
+    try test ! -e "$PREFIX_DIR/lib/tool/__pycache__"
+}
+runtest generate_exclude
+
+generate_stream_exclude() {
+    make_build_droppings_image
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --exclude="*.pdb,share/doc/.DS_Store" \
+        --stream
+    try tar -xzf "$OUT_DIR/package.tar.gz" -C "$WORK_DIR"
+    try test -e "$WORK_DIR/package/component/share/doc/README"
+    try test ! -e "$WORK_DIR/package/component/bin/program.pdb"
+    try test ! -e "$WORK_DIR/package/component/lib/tool/__pycache__"
+    try test ! -e "$WORK_DIR/package/component/share/doc/.DS_Store"
+    try test ! -e "$WORK_DIR/package/component/.installerignore"
+}
+runtest generate_stream_exclude
+
+generate_include() {
+    make_build_droppings_image
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --include="bin/*,share/doc" \
+        --exclude="*.pdb"
+    try test -e "$WORK_DIR/package/component/bin/program"
+    try test -e "$WORK_DIR/package/component/share/doc/README"
+    try test -e "$WORK_DIR/package/component/share/doc/.DS_Store"
+    try test ! -e "$WORK_DIR/package/component/bin/program.pdb"
+    try test ! -e "$WORK_DIR/package/component/lib/tool/script.py"
+    try grep -q "file:share/doc/README" "$WORK_DIR/package/component/manifest.in"
+    try test "$(grep -c "lib/" "$WORK_DIR/package/component/manifest.in")" = 0
+}
+runtest generate_include
+
+generate_include_leaves_out_subtrees() {
+    make_build_droppings_image
+    try mkdir -p "$WORK_DIR/image/share/man/man1"
+    for _stream in "" --stream; do
+	try rm -rf "$WORK_DIR/package" "$OUT_DIR"
+	try sh "$S/gen-installer.sh" \
+	    --image-dir="$WORK_DIR/image" \
+	    --work-dir="$WORK_DIR" \
+	    --output-dir="$OUT_DIR" \
+	    --include="bin/*,share/doc/README" \
+	    --bulk-dirs=lib/tool \
+	    $_stream
+	try rm -rf "$WORK_DIR/unpacked"
+	try mkdir "$WORK_DIR/unpacked"
+	try tar -xzf "$OUT_DIR/package.tar.gz" -C "$WORK_DIR/unpacked"
+	local _component="$WORK_DIR/unpacked/package/component"
+	try test -e "$_component/share/doc/README"
+	try test ! -e "$_component/lib"
+	try test ! -e "$_component/share/man"
+	try test "$(grep -c "lib/" "$_component/manifest.in")" = 0
+    done
+}
+runtest generate_include_leaves_out_subtrees
+
+generate_invalid_glob_error() {
+    make_build_droppings_image
+    expect_output_fail "invalid glob pattern" sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --exclude="[unclosed"
+}
+runtest generate_invalid_glob_error
+
+bulk_directory_glob() {
+    try mkdir -p "$WORK_DIR/image/share/doc/foo/html" "$WORK_DIR/image/share/doc/bar/html" "$WORK_DIR/image/share/doc/bar/txt"
+    echo "foo" > "$WORK_DIR/image/share/doc/foo/html/index.html"
+    echo "bar" > "$WORK_DIR/image/share/doc/bar/html/index.html"
+    echo "bar" > "$WORK_DIR/image/share/doc/bar/txt/README"
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --bulk-dirs="share/doc/*/html"
+    try grep -qx "dir:share/doc/foo/html" "$WORK_DIR/package/component/manifest.in"
+    try grep -qx "dir:share/doc/bar/html" "$WORK_DIR/package/component/manifest.in"
+    try grep -qx "file:share/doc/bar/txt/README" "$WORK_DIR/package/component/manifest.in"
+    try test "$(grep -c "index.html" "$WORK_DIR/package/component/manifest.in")" = 0
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR"
+    try test -e "$PREFIX_DIR/share/doc/foo/html/index.html"
+    try test -e "$PREFIX_DIR/share/doc/bar/html/index.html"
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR" --uninstall
+    try test ! -e "$PREFIX_DIR/share/doc/foo/html"
+}
+runtest bulk_directory_glob
+
+bulk_directory_glob_matching_files() {
+    try mkdir -p "$WORK_DIR/image/share/doc/html"
+    echo "readme" > "$WORK_DIR/image/share/doc/README"
+    echo "index" > "$WORK_DIR/image/share/doc/html/index.html"
+    expect_not_output_ok "matches no directory" sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --bulk-dirs="share/doc/*"
+    try grep -qx "dir:share/doc/html" "$WORK_DIR/package/component/manifest.in"
+    try grep -qx "file:share/doc/README" "$WORK_DIR/package/component/manifest.in"
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR"
+    try test -e "$PREFIX_DIR/share/doc/README"
+    try test -e "$PREFIX_DIR/share/doc/html/index.html"
+}
+runtest bulk_directory_glob_matching_files
+
+bulk_directory_unmatched_warning() {
+    expect_output_ok "bulk dir pattern 'dir-to-instal' matches no directory" sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --bulk-dirs=dir-to-install,dir-to-instal
+    try grep -qx "dir:dir-to-install" "$WORK_DIR/package/component/manifest.in"
+}
+runtest bulk_directory_unmatched_warning
+
+generate_spec_toml() {
+    cat > "$WORK_DIR/package.toml" <<SPEC
+product-name = "Spec Product"
+package-name = "spec-package"
+success-message = "Spec is ready."
+compression-formats = ["xz"]
+output-dir = "../outdir"
+
+[[component]]
+name = "speccomp"
+image-dir = "$TEST_DIR/image1"
+bulk-dirs = ["dir-to-install"]
+SPEC
+    try sh "$S/gen-installer.sh" \
+        --work-dir="$WORK_DIR" \
+        --spec="$WORK_DIR/package.toml"
+    try test -e "$OUT_DIR/spec-package.tar.xz"
+    try test ! -e "$OUT_DIR/spec-package.tar.gz"
+    try grep -qx "dir:dir-to-install" "$WORK_DIR/spec-package/speccomp/manifest.in"
+    expect_output_ok "Spec is ready." "$WORK_DIR/spec-package/install.sh" --prefix="$PREFIX_DIR"
+    try test -e "$PREFIX_DIR/dir-to-install/foo"
+    try test -e "$PREFIX_DIR/lib/packagelib/manifest-speccomp"
+}
+runtest generate_spec_toml
+
+generate_spec_json() {
+    cat > "$WORK_DIR/package.json" <<SPEC
+{
+    "package-name": "json-package",
+    "output-dir": "$OUT_DIR",
+    "component": [
+        { "name": "jsoncomp", "image-dir": "$TEST_DIR/image1", "exclude": ["program2", "*-not-install"] }
+    ]
+}
+SPEC
+    try sh "$S/gen-installer.sh" \
+        --work-dir="$WORK_DIR" \
+        --spec="$WORK_DIR/package.json"
+    try test -e "$OUT_DIR/json-package.tar.gz"
+    try test -e "$WORK_DIR/json-package/jsoncomp/bin/program"
+    try test ! -e "$WORK_DIR/json-package/jsoncomp/bin/program2"
+    try test ! -e "$WORK_DIR/json-package/jsoncomp/something-to-not-install"
+}
+runtest generate_spec_json
+
+combine_spec() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --package-name=rustc \
+        --component-name=rustc
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image3" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --package-name=cargo \
+        --component-name=cargo
+    cat > "$WORK_DIR/rust.toml" <<SPEC
+package-name = "rust"
+work-dir = "combine"
+input-tarballs = ["$OUT_DIR/rustc.tar.gz", "$OUT_DIR/cargo.tar.gz"]
+SPEC
+    try sh "$S/combine-installers.sh" \
+        --output-dir="$OUT_DIR" \
+        --spec="$WORK_DIR/rust.toml"
+    try test -e "$OUT_DIR/rust.tar.gz"
+    try "$WORK_DIR/combine/rust/install.sh" --prefix="$PREFIX_DIR"
+    try test -e "$PREFIX_DIR/something-to-install"
+    try test -e "$PREFIX_DIR/bin/cargo"
+}
+runtest combine_spec
+
+generate_spec_list_items_with_commas() {
+    cat > "$WORK_DIR/package.toml" <<SPEC
+package-name = "spec-package"
+output-dir = "$OUT_DIR"
+
+[[component]]
+name = "speccomp"
+image-dir = "$TEST_DIR/image1"
+exclude = ["{program2,something-to-not-install}"]
+SPEC
+    try sh "$S/gen-installer.sh" \
+        --work-dir="$WORK_DIR" \
+        --spec="$WORK_DIR/package.toml"
+    try test -e "$WORK_DIR/spec-package/speccomp/bin/program"
+    try test ! -e "$WORK_DIR/spec-package/speccomp/bin/program2"
+    try test ! -e "$WORK_DIR/spec-package/speccomp/something-to-not-install"
+}
+runtest generate_spec_list_items_with_commas
+
+generate_spec_flags_take_precedence() {
+    cat > "$WORK_DIR/package.toml" <<SPEC
+package-name = "spec-package"
+success-message = "Spec is ready."
+compression-formats = ["xz"]
+
+[[component]]
+name = "speccomp"
+image-dir = "$TEST_DIR/image1"
+exclude = ["program2"]
+SPEC
+    # Even the default compression formats, when given explicitly
+    try sh "$S/gen-installer.sh" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --package-name=flag-package \
+        --compression-formats=gz \
+        --exclude=bin \
+        --spec="$WORK_DIR/package.toml"
+    try test -e "$OUT_DIR/flag-package.tar.gz"
+    try test ! -e "$OUT_DIR/flag-package.tar.xz"
+    try test ! -e "$OUT_DIR/spec-package.tar.xz"
+    try test ! -e "$WORK_DIR/flag-package/speccomp/bin"
+    expect_output_ok "Spec is ready." "$WORK_DIR/flag-package/install.sh" --prefix="$PREFIX_DIR"
+}
+runtest generate_spec_flags_take_precedence
+
+combine_spec_input_tarball_with_comma() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --package-name="rustc,nightly" \
+        --component-name=rustc
+    cat > "$WORK_DIR/rust.toml" <<SPEC
+package-name = "rust"
+work-dir = "combine"
+input-tarballs = ["$OUT_DIR/rustc,nightly.tar.gz"]
+SPEC
+    try sh "$S/combine-installers.sh" \
+        --output-dir="$OUT_DIR" \
+        --package-name=flag-rust \
+        --spec="$WORK_DIR/rust.toml"
+    try test -e "$OUT_DIR/flag-rust.tar.gz"
+    try test ! -e "$OUT_DIR/rust.tar.gz"
+    try "$WORK_DIR/combine/flag-rust/install.sh" --prefix="$PREFIX_DIR"
+    try test -e "$PREFIX_DIR/something-to-install"
+}
+runtest combine_spec_input_tarball_with_comma
+
+generate_spec_unknown_key_error() {
+    printf 'product-nmae = "typo"\n' > "$WORK_DIR/package.toml"
+    expect_output_fail "unknown field" sh "$S/gen-installer.sh" \
+        --work-dir="$WORK_DIR" \
+        --spec="$WORK_DIR/package.toml"
+}
+runtest generate_spec_unknown_key_error
+
+generate_multiple_components() {
+    try sh "$S/gen-installer.sh" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --package-name=rust \
+        --component rustc "$TEST_DIR/image1" \
+        --component-bulk-dirs rustc dir-to-install \
+        --component cargo "$TEST_DIR/image3"
+    test "$(cat "$WORK_DIR/rust/components")" = "$(printf 'rustc
+cargo')"
+    need_ok "unexpected components"
+    try grep -qx "dir:dir-to-install" "$WORK_DIR/rust/rustc/manifest.in"
+    try grep -qx "file:bin/cargo" "$WORK_DIR/rust/cargo/manifest.in"
+    try rm -r "$WORK_DIR/rust"
+    try tar -xzf "$OUT_DIR/rust.tar.gz" -C "$WORK_DIR"
+    try "$WORK_DIR/rust/install.sh" --prefix="$PREFIX_DIR"
+    try test -e "$PREFIX_DIR/something-to-install"
+    try test -e "$PREFIX_DIR/dir-to-install/foo"
+    try test -e "$PREFIX_DIR/bin/cargo"
+    try "$WORK_DIR/rust/install.sh" --prefix="$PREFIX_DIR" --uninstall --components=cargo
+    try test ! -e "$PREFIX_DIR/bin/cargo"
+    try test -e "$PREFIX_DIR/bin/program"
+}
+runtest generate_multiple_components
+
+generate_stream_multiple_components() {
+    try sh "$S/gen-installer.sh" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --package-name=rust \
+        --component rustc "$TEST_DIR/image1" \
+        --component cargo "$TEST_DIR/image3" \
+        --stream
+    try tar -xzf "$OUT_DIR/rust.tar.gz" -C "$WORK_DIR"
+    test "$(cat "$WORK_DIR/rust/components")" = "$(printf 'rustc
+cargo')"
+    need_ok "unexpected components"
+    try "$WORK_DIR/rust/install.sh" --prefix="$PREFIX_DIR"
+    try test -e "$PREFIX_DIR/bin/program"
+    try test -e "$PREFIX_DIR/bin/cargo"
+}
+runtest generate_stream_multiple_components
+
+generate_spec_multiple_components() {
+    cat > "$WORK_DIR/package.toml" <<SPEC
+package-name = "rust"
+
+[[component]]
+name = "rustc"
+image-dir = "$TEST_DIR/image1"
+
+[[component]]
+name = "cargo"
+image-dir = "$TEST_DIR/image3"
+SPEC
+    try sh "$S/gen-installer.sh" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --exclude=program2 \
+        --spec="$WORK_DIR/package.toml"
+    test "$(cat "$WORK_DIR/rust/components")" = "$(printf 'rustc
+cargo')"
+    need_ok "unexpected components"
+    try test -e "$WORK_DIR/rust/cargo/bin/cargo"
+    try test ! -e "$WORK_DIR/rust/rustc/bin/program2"
+}
+runtest generate_spec_multiple_components
+
+generate_duplicate_component_error() {
+    expect_output_fail "component 'rustc' is given more than once" sh "$S/gen-installer.sh" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --component rustc "$TEST_DIR/image1" \
+        --component rustc "$TEST_DIR/image3"
+}
+runtest generate_duplicate_component_error
+
+generate_component_dir_with_comma() {
+    cp -R "$TEST_DIR/image1" "$WORK_DIR/image,1"
+    try sh "$S/gen-installer.sh" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --package-name=rust \
+        --component rustc "$WORK_DIR/image,1" \
+        --component cargo "$TEST_DIR/image3"
+    try test -e "$WORK_DIR/rust/rustc/bin/program"
+    try test -e "$WORK_DIR/rust/cargo/bin/cargo"
+}
+runtest generate_component_dir_with_comma
+
+generate_invalid_component_name_error() {
+    expect_output_fail "component name 'x/../y' is not a valid directory name" \
+        sh "$S/gen-installer.sh" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --component "x/../y" "$TEST_DIR/image1"
+    if sh "$S/gen-installer.sh" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --component "a b" "$TEST_DIR/image1" > "$WORK_DIR/log" 2>&1; then
+        fail "the component name 'a b' was accepted"
+    fi
+    try_quoted grep -q "component name 'a b' is not a valid directory name" "$WORK_DIR/log"
+    cat > "$WORK_DIR/package.toml" <<SPEC
+[[component]]
+name = "a,b"
+image-dir = "$TEST_DIR/image1"
+SPEC
+    expect_output_fail "component name 'a,b' is not a valid directory name" \
+        sh "$S/gen-installer.sh" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --spec="$WORK_DIR/package.toml"
+    try test ! -e "$WORK_DIR/x"
+}
+runtest generate_invalid_component_name_error
+
+generate_component_bulk_dirs_error() {
+    expect_output_fail "which isn't a --component" sh "$S/gen-installer.sh" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --component rustc "$TEST_DIR/image1" \
+        --component-bulk-dirs cargo share
+}
+runtest generate_component_bulk_dirs_error
+
+make_unusual_names_image() {
+    local _newline="$(printf 'new\nline')"
+    local _trailing="$(printf 'trailing\nx')"
+    _trailing="${_trailing%x}"
+    mkdir -p "$WORK_DIR/image/share/odd" "$WORK_DIR/image/lib/dir$_newline"
+    need_ok "failed to create image"
+    echo backslash > "$WORK_DIR/image/share/odd/back\\slash"
+    echo newline > "$WORK_DIR/image/share/odd/$_newline"
+    echo trailing > "$WORK_DIR/image/share/odd/$_trailing"
+    echo latin1 > "$WORK_DIR/image/share/odd/$(printf 'caf\351')"
+    echo colon > "$WORK_DIR/image/share/odd/colon:name"
+    echo space > "$WORK_DIR/image/share/odd/ leading space"
+    echo escape > "$WORK_DIR/image/share/odd/not\\nan\\0101escape"
+    echo bulk > "$WORK_DIR/image/lib/dir$_newline/file"
+}
+
+check_unusual_names_installed() {
+    local _newline="$(printf 'new\nline')"
+    local _trailing="$(printf 'trailing\nx')"
+    _trailing="${_trailing%x}"
+    test "$(cat "$PREFIX_DIR/share/odd/back\\slash")" = backslash
+    need_ok "unusual name mishandled"
+    test "$(cat "$PREFIX_DIR/share/odd/$_newline")" = newline
+    need_ok "unusual name mishandled"
+    test "$(cat "$PREFIX_DIR/share/odd/$_trailing")" = trailing
+    need_ok "unusual name mishandled"
+    test "$(cat "$PREFIX_DIR/share/odd/$(printf 'caf\351')")" = latin1
+    need_ok "unusual name mishandled"
+    test "$(cat "$PREFIX_DIR/share/odd/colon:name")" = colon
+    need_ok "unusual name mishandled"
+    test -e "$PREFIX_DIR/share/odd/ leading space"
+    need_ok "unusual name mishandled"
+    test "$(cat "$PREFIX_DIR/share/odd/not\\nan\\0101escape")" = escape
+    need_ok "unusual name mishandled"
+    test "$(cat "$PREFIX_DIR/lib/dir$_newline/file")" = bulk
+    need_ok "unusual name mishandled"
+}
+
+generate_unusual_names() {
+    make_unusual_names_image
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --bulk-dirs="lib/dir*"
+    try grep -qxF 'file:share/odd/new\nline' "$WORK_DIR/package/component/manifest.in"
+    try grep -qxF 'file:share/odd/back\\slash' "$WORK_DIR/package/component/manifest.in"
+    try grep -qxF 'file:share/odd/caf\0351' "$WORK_DIR/package/component/manifest.in"
+    try grep -qxF 'dir:lib/dirnew\nline' "$WORK_DIR/package/component/manifest.in"
+    try rm -r "$WORK_DIR/package"
+    try tar -xzf "$OUT_DIR/package.tar.gz" -C "$WORK_DIR"
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR"
+    check_unusual_names_installed
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR" --uninstall
+    try test -z "$(ls -A "$PREFIX_DIR/share/odd")"
+    test ! -e "$PREFIX_DIR/lib/dir$(printf 'new\nline')"
+    need_ok "unusual name mishandled"
+}
+runtest generate_unusual_names
+
+generate_stream_unusual_names() {
+    make_unusual_names_image
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --tar-format=pax \
+        --stream
+    try tar -xzf "$OUT_DIR/package.tar.gz" -C "$WORK_DIR"
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR"
+    check_unusual_names_installed
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR" --uninstall
+    try test -z "$(ls -A "$PREFIX_DIR/share/odd")"
+}
+runtest generate_stream_unusual_names
+
+# Copies test/image-spaces and adds the names that can't be checked out on
+# every platform
+make_spaces_image() {
+    try cp -R "$TEST_DIR/image-spaces" "$WORK_DIR/image"
+    echo colon > "$WORK_DIR/image/share/doc/my docs/colon:file.txt"
+    echo backslash > "$WORK_DIR/image/share/doc/my docs/back\\slash.txt"
+    echo trailing > "$WORK_DIR/image/share/doc/my docs/trailing space "
+    echo tab > "$WORK_DIR/image/share/doc/my docs/$(printf 'tab\tname')"
+    echo bulk > "$WORK_DIR/image/lib/dir with spaces/file:2"
+}
+
+install_prefix_with_spaces_and_colons() {
+    make_spaces_image
+    try_quoted sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --bulk-dirs="lib/dir with spaces"
+    local _prefix="$PREFIX_DIR/My Tools:2024"
+    try_quoted "$WORK_DIR/package/install.sh" --prefix="$_prefix"
+    try_quoted test -x "$_prefix/bin/program with spaces"
+    try_quoted test -e "$_prefix/etc/a  b.conf"
+    try_quoted test -e "$_prefix/share/doc/my docs/ leading space.txt"
+    try_quoted test -e "$_prefix/share/doc/my docs/colon:file.txt"
+    try_quoted test -e "$_prefix/share/doc/my docs/back\\slash.txt"
+    try_quoted test -e "$_prefix/share/doc/my docs/trailing space "
+    try_quoted test -e "$_prefix/share/doc/my docs/$(printf 'tab\tname')"
+    try_quoted test -e "$_prefix/lib/dir with spaces/file 1"
+    try_quoted test -e "$_prefix/lib/dir with spaces/file:2"
+    try_quoted grep -qxF "file:$_prefix/etc/a  b.conf" "$_prefix/lib/packagelib/manifest-component"
+    try_quoted grep -qxF "dir:$_prefix/lib/dir with spaces" "$_prefix/lib/packagelib/manifest-component"
+    try_quoted "$WORK_DIR/package/install.sh" --prefix="$_prefix" --uninstall
+    try_quoted test ! -e "$_prefix/bin/program with spaces"
+    try_quoted test ! -e "$_prefix/lib/dir with spaces"
+    try_quoted test -z "$(ls -A "$_prefix/share/doc/my docs")"
+}
+runtest install_prefix_with_spaces_and_colons
+
+uninstall_from_installed_script_with_spaces() {
+    make_spaces_image
+    try_quoted sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR"
+    local _prefix="$PREFIX_DIR/with spaces"
+    try_quoted "$WORK_DIR/package/install.sh" --prefix="$_prefix"
+    try_quoted test -e "$_prefix/share/doc/my docs/colon:file.txt"
+    try_quoted "$_prefix/lib/packagelib/uninstall.sh"
+    try_quoted test ! -e "$_prefix/share/doc/my docs/colon:file.txt"
+    try_quoted test ! -e "$_prefix/lib/packagelib"
+}
+runtest uninstall_from_installed_script_with_spaces
+
+install_docdir_and_prefix_with_equals() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image-docdir1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR"
+    local _prefix="$PREFIX_DIR/a=b"
+    try_quoted "$WORK_DIR/package/install.sh" --prefix="$_prefix" --docdir="$PREFIX_DIR/doc dir"
+    try_quoted test -e "$_prefix/lib/packagelib/manifest-component"
+    try_quoted test -e "$PREFIX_DIR/doc dir/README"
+    try_quoted test ! -e "$_prefix/share/doc/rust/README"
+}
+runtest install_docdir_and_prefix_with_equals
+
+combine_install_prefix_with_spaces() {
+    make_spaces_image
+    try_quoted sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --package-name=spaces \
+        --component-name=spaces
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image3" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --package-name=cargo \
+        --component-name=cargo
+    try sh "$S/combine-installers.sh" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --package-name=rust \
+        --input-tarballs="$OUT_DIR/spaces.tar.gz,$OUT_DIR/cargo.tar.gz"
+    local _prefix="$PREFIX_DIR/My Tools:2024"
+    try_quoted "$WORK_DIR/rust/install.sh" --prefix="$_prefix"
+    try_quoted test -e "$_prefix/bin/cargo"
+    try_quoted test -e "$_prefix/share/doc/my docs/back\\slash.txt"
+    try_quoted "$WORK_DIR/rust/install.sh" --prefix="$_prefix" --uninstall --components=spaces
+    try_quoted test ! -e "$_prefix/share/doc/my docs/back\\slash.txt"
+    try_quoted test -e "$_prefix/bin/cargo"
+}
+runtest combine_install_prefix_with_spaces
+
+make_user_image() {
+    try mkdir -p "$WORK_DIR/image/bin" "$WORK_DIR/image/etc" "$WORK_DIR/image/share/doc/product" "$WORK_DIR/image/lib"
+    printf '#!/bin/sh\n' > "$WORK_DIR/image/bin/program"
+    try chmod +x "$WORK_DIR/image/bin/program"
+    echo "config" > "$WORK_DIR/image/etc/program.conf"
+    echo "readme" > "$WORK_DIR/image/share/doc/product/README"
+    echo "library" > "$WORK_DIR/image/lib/libprogram.so"
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR"
+}
+
+install_user() {
+    make_user_image
+    local _home="$WORK_DIR/home"
+    try mkdir -p "$_home"
+    expect_output_ok "$_home/.local/bin is not in your PATH" \
+        env HOME="$_home" XDG_DATA_HOME= XDG_CONFIG_HOME= \
+        "$WORK_DIR/package/install.sh" --user
+    try test -x "$_home/.local/bin/program"
+    try test -e "$_home/.local/lib/libprogram.so"
+    try test -e "$_home/.local/share/doc/product/README"
+    try test -e "$_home/.config/program.conf"
+    try test -e "$_home/.local/lib/packagelib/manifest-component"
+    # The system linker configuration is left alone
+    try test "$(grep -c "running ldconfig" "$_home/.local/lib/packagelib/install.log")" = 0
+    try env HOME="$_home" "$WORK_DIR/package/install.sh" --user --uninstall
+    try test ! -e "$_home/.local/bin/program"
+    try test ! -e "$_home/.config/program.conf"
+}
+runtest install_user
+
+install_user_xdg_dirs() {
+    make_user_image
+    local _home="$WORK_DIR/home"
+    try mkdir -p "$_home"
+    try env HOME="$_home" XDG_DATA_HOME="$WORK_DIR/data" XDG_CONFIG_HOME="$WORK_DIR/config" \
+        "$WORK_DIR/package/install.sh" --user
+    try test -x "$_home/.local/bin/program"
+    try test -e "$WORK_DIR/data/doc/product/README"
+    try test -e "$WORK_DIR/config/program.conf"
+}
+runtest install_user_xdg_dirs
+
+install_user_bindir_on_path() {
+    make_user_image
+    local _home="$WORK_DIR/home"
+    try mkdir -p "$_home"
+    env HOME="$_home" PATH="$_home/.local/bin:$PATH" "$WORK_DIR/package/install.sh" --user > "$WORK_DIR/output"
+    need_ok "install failed"
+    try test "$(grep -c "not in your PATH" "$WORK_DIR/output")" = 0
+}
+runtest install_user_bindir_on_path
+
+install_user_with_prefix() {
+    make_user_image
+    local _home="$WORK_DIR/home"
+    try mkdir -p "$_home"
+    try env HOME="$_home" "$WORK_DIR/package/install.sh" --user --prefix="$PREFIX_DIR"
+    try test -x "$PREFIX_DIR/bin/program"
+    try test ! -e "$_home/.local/bin/program"
+}
+runtest install_user_with_prefix
+
+install_lock_released() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR"
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR"
+    try test ! -e "$PREFIX_DIR/lib/packagelib.lock"
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR" --uninstall
+    try test ! -e "$PREFIX_DIR/lib/packagelib.lock"
+}
+runtest install_lock_released
+
+install_lock_timeout() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR"
+    sleep 30 &
+    local _holder=$!
+    try mkdir -p "$PREFIX_DIR/lib/packagelib.lock"
+    echo "$(uname -n) $_holder" > "$PREFIX_DIR/lib/packagelib.lock/owner"
+    expect_output_fail "another installation is in progress" \
+        "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR" --lock-timeout=1
+    kill $_holder
+    try test ! -e "$PREFIX_DIR/something-to-install"
+    # The lock of the other installation is left alone
+    try test -e "$PREFIX_DIR/lib/packagelib.lock/owner"
+}
+runtest install_lock_timeout
+
+install_lock_stale() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR"
+    sh -c 'exit 0' &
+    local _dead=$!
+    wait $_dead
+    try mkdir -p "$PREFIX_DIR/lib/packagelib.lock"
+    echo "$(uname -n) $_dead" > "$PREFIX_DIR/lib/packagelib.lock/owner"
+    expect_output_ok "removing stale lock" \
+        "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR" --lock-timeout=0
+    try test -e "$PREFIX_DIR/something-to-install"
+    try test ! -e "$PREFIX_DIR/lib/packagelib.lock"
+}
+runtest install_lock_stale
+
+install_lock_stale_concurrent() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR"
+    sh -c 'exit 0' &
+    local _dead=$!
+    wait $_dead
+    try mkdir -p "$PREFIX_DIR/lib/packagelib.lock"
+    echo "$(uname -n) $_dead" > "$PREFIX_DIR/lib/packagelib.lock/owner"
+    # All of them find the lock stale, and take turns once it's broken
+    local _pids=""
+    for _i in 1 2 3 4; do
+        "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR" --lock-timeout=60 \
+            > "$WORK_DIR/install$_i.log" 2>&1 &
+        _pids="$_pids $!"
+    done
+    for _pid in $_pids; do
+        wait $_pid
+        need_ok "a concurrent installation failed"
+    done
+    try test "$(cat "$WORK_DIR"/install*.log | grep -c 'removing stale lock')" -le 1
+    try test -e "$PREFIX_DIR/something-to-install"
+    try test ! -e "$PREFIX_DIR/lib/packagelib.lock"
+    try test ! -e "$PREFIX_DIR/lib/packagelib.lock.break"
+}
+runtest install_lock_stale_concurrent
+
+install_lock_timeout_error() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR"
+    expect_output_fail "must be a number of seconds" \
+        "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR" --lock-timeout=soon
+}
+runtest install_lock_timeout_error
+
+install_concurrently() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR/c1" \
+        --output-dir="$OUT_DIR/c1" \
+        --component-name=rustc
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image3" \
+        --work-dir="$WORK_DIR/c2" \
+        --output-dir="$OUT_DIR/c2" \
+        --component-name=cargo
+    "$WORK_DIR/c1/package/install.sh" --prefix="$PREFIX_DIR" > /dev/null &
+    local _first=$!
+    "$WORK_DIR/c2/package/install.sh" --prefix="$PREFIX_DIR" > /dev/null
+    need_ok "second install failed"
+    wait $_first
+    need_ok "first install failed"
+    try grep -qx rustc "$PREFIX_DIR/lib/packagelib/components"
+    try grep -qx cargo "$PREFIX_DIR/lib/packagelib/components"
+    try test -e "$PREFIX_DIR/something-to-install"
+    try test -e "$PREFIX_DIR/bin/cargo"
+}
+runtest install_concurrently
+
+generate_installed_sizes() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR"
+    try_quoted test "$(cat "$WORK_DIR/package/component/size.in")" = "12288 bin"
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR/stream" \
+        --output-dir="$OUT_DIR/stream" \
+        --stream
+    try mkdir -p "$WORK_DIR/stream"
+    try tar -xzf "$OUT_DIR/stream/package.tar.gz" -C "$WORK_DIR/stream"
+    try cmp "$WORK_DIR/package/component/size.in" "$WORK_DIR/stream/package/component/size.in"
+}
+runtest generate_installed_sizes
+
+# Pretends that the filesystems of the paths matching a pattern, all of them
+# by default, have only 4 KiB free
+make_full_df() {
+    try mkdir -p "$WORK_DIR/fake-bin"
+    cat > "$WORK_DIR/fake-bin/df" <<SCRIPT
+#!/bin/sh
+echo "Filesystem 1024-blocks Used Available Capacity Mounted on"
+case "\$2" in
+    ${1-*}) echo "/dev/fake 100 96 4 96% /mnt/fake disk" ;;
+    *) echo "/dev/big 1000000 0 1000000 0% /mnt/big" ;;
+esac
+SCRIPT
+    try chmod +x "$WORK_DIR/fake-bin/df"
+}
+
+install_not_enough_space() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR"
+    make_full_df
+    expect_output_fail "12 KiB are needed in $PREFIX_DIR/bin, but only 4 KiB are free on /mnt/fake disk" \
+        env PATH="$WORK_DIR/fake-bin:$PATH" "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR"
+    try test ! -e "$PREFIX_DIR/bin/program"
+    try env PATH="$WORK_DIR/fake-bin:$PATH" "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR" --disable-space-check
+    try test -e "$PREFIX_DIR/bin/program"
+    # Uninstalling doesn't need any space
+    try env PATH="$WORK_DIR/fake-bin:$PATH" "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR" --uninstall
+}
+runtest install_not_enough_space
+
+install_not_enough_space_in_bindir() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR"
+    try mkdir -p "$PREFIX_DIR/custom-bin"
+    make_full_df "*custom-bin"
+    try env PATH="$WORK_DIR/fake-bin:$PATH" "$WORK_DIR/package/install.sh" \
+        --prefix="$PREFIX_DIR" --bindir="$PREFIX_DIR/other-bin"
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR" --uninstall
+    expect_output_fail "12 KiB are needed in $PREFIX_DIR/custom-bin" \
+        env PATH="$WORK_DIR/fake-bin:$PATH" "$WORK_DIR/package/install.sh" \
+        --prefix="$PREFIX_DIR" --bindir="$PREFIX_DIR/custom-bin"
+}
+runtest install_not_enough_space_in_bindir
+
+combine_installed_sizes() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --package-name=rustc \
+        --component-name=rustc
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image3" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --package-name=cargo \
+        --component-name=cargo
+    try sh "$S/combine-installers.sh" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --package-name=rust \
+        --input-tarballs="$OUT_DIR/rustc.tar.gz,$OUT_DIR/cargo.tar.gz"
+    make_full_df
+    # Both components count towards the same filesystem
+    expect_output_fail "16 KiB are needed in $PREFIX_DIR/bin" \
+        env PATH="$WORK_DIR/fake-bin:$PATH" "$WORK_DIR/rust/install.sh" --prefix="$PREFIX_DIR"
+}
+runtest combine_installed_sizes
+
+install_owner() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --component-name=rustc \
+        --bulk-dirs=dir-to-install
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR"
+    expect_output_ok "rustc" "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR" \
+        --owner="$PREFIX_DIR/bin/program"
+    # Files below bulk directories belong to their component
+    expect_output_ok "rustc" "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR" \
+        --owner="$PREFIX_DIR/dir-to-install/foo"
+    expect_output_ok "rustc" "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR" \
+        --owner="$PREFIX_DIR/bin/../dir-to-install/new-file"
+    expect_output_fail "no installed component owns $PREFIX_DIR/bin/other" \
+        "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR" --owner="$PREFIX_DIR/bin/other"
+    expect_output_fail "no installed component owns $PREFIX_DIR/dir-to-install-too" \
+        "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR" --owner="$PREFIX_DIR/dir-to-install-too"
+}
+runtest install_owner
+
+install_owner_combined() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --package-name=rustc \
+        --component-name=rustc
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image3" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --package-name=cargo \
+        --component-name=cargo
+    try sh "$S/combine-installers.sh" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --package-name=rust \
+        --input-tarballs="$OUT_DIR/rustc.tar.gz,$OUT_DIR/cargo.tar.gz"
+    try "$WORK_DIR/rust/install.sh" --prefix="$PREFIX_DIR"
+    try test "$("$WORK_DIR/rust/install.sh" --prefix="$PREFIX_DIR" --owner="$PREFIX_DIR/bin/cargo")" = cargo
+    try test "$("$WORK_DIR/rust/install.sh" --prefix="$PREFIX_DIR" --owner="$PREFIX_DIR/bin/program")" = rustc
+}
+runtest install_owner_combined
+
+install_owner_not_installed() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR"
+    expect_output_fail "unable to find installation manifest" \
+        "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR" --owner="$PREFIX_DIR/bin/program"
+}
+runtest install_owner_not_installed
+
+query_owner() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --component-name=rustc \
+        --bulk-dirs=dir-to-install
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR"
+    try test "$(sh "$S/query-installation.sh" --prefix="$PREFIX_DIR" \
+        --owner="$PREFIX_DIR/bin/program" 2> /dev/null)" = rustc
+    try test "$(sh "$S/query-installation.sh" --prefix="$PREFIX_DIR" \
+        --owner="$PREFIX_DIR/dir-to-install/foo" 2> /dev/null)" = rustc
+    try test "$(cd "$PREFIX_DIR/bin" && sh "$S/query-installation.sh" --prefix="$PREFIX_DIR" \
+        --owner=./program 2> /dev/null)" = rustc
+    expect_output_fail "no installed component owns" \
+        sh "$S/query-installation.sh" --prefix="$PREFIX_DIR" --owner="$PREFIX_DIR/bin/other"
+}
+runtest query_owner
+
+query_list_files() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --component-name=rustc \
+        --bulk-dirs=dir-to-install
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR"
+    sh "$S/query-installation.sh" --libdir="$PREFIX_DIR/lib" --list-files=rustc \
+        > "$WORK_DIR/files" 2> /dev/null
+    need_ok "query failed"
+    try grep -qx "$PREFIX_DIR/bin/program" "$WORK_DIR/files"
+    try grep -qx "$PREFIX_DIR/something-to-install" "$WORK_DIR/files"
+    # Bulk directories are listed with their contents
+    try grep -qx "$PREFIX_DIR/dir-to-install/foo" "$WORK_DIR/files"
+    try test "$(grep -c "dir-to-install\$" "$WORK_DIR/files")" = 0
+    expect_output_fail "component 'cargo' is not installed" \
+        sh "$S/query-installation.sh" --prefix="$PREFIX_DIR" --list-files=cargo
+}
+runtest query_list_files
+
+query_unusual_names() {
+    make_unusual_names_image
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --bulk-dirs="lib/dir*"
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR"
+    sh "$S/query-installation.sh" --prefix="$PREFIX_DIR" --list-files=component \
+        > "$WORK_DIR/files" 2> /dev/null
+    need_ok "query failed"
+    # Paths are escaped like in the manifests
+    try grep -qxF "$PREFIX_DIR/share/odd/new\\nline" "$WORK_DIR/files"
+    try grep -qxF "$PREFIX_DIR/share/odd/back\\\\slash" "$WORK_DIR/files"
+    try grep -qxF "$PREFIX_DIR/share/odd/caf\\0351" "$WORK_DIR/files"
+    try grep -qxF "$PREFIX_DIR/lib/dirnew\\nline/file" "$WORK_DIR/files"
+    local _newline="$(printf 'new\nline')"
+    test "$(sh "$S/query-installation.sh" --prefix="$PREFIX_DIR" \
+        --owner="$PREFIX_DIR/share/odd/$_newline" 2> /dev/null)" = component
+    need_ok "owner of unusual name not found"
+    test "$("$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR" \
+        --owner="$PREFIX_DIR/share/odd/back\\slash")" = component
+    need_ok "owner of unusual name not found"
+    test "$(sh "$S/query-installation.sh" --prefix="$PREFIX_DIR" \
+        --owner="$PREFIX_DIR/lib/dir$_newline/file" 2> /dev/null)" = component
+    need_ok "owner of unusual name not found"
+}
+runtest query_unusual_names
+
+generate_hashes() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR"
+    local _hash="$(sha256sum "$TEST_DIR/image1/bin/program" | cut -d ' ' -f 1)"
+    try_quoted grep -qxF "$_hash bin/program" "$WORK_DIR/package/component/hashes.in"
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR/stream" \
+        --output-dir="$OUT_DIR/stream" \
+        --stream
+    try mkdir -p "$WORK_DIR/stream"
+    try tar -xzf "$OUT_DIR/stream/package.tar.gz" -C "$WORK_DIR/stream"
+    try cmp "$WORK_DIR/package/component/hashes.in" "$WORK_DIR/stream/package/component/hashes.in"
+}
+runtest generate_hashes
+
+install_records_hashes() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --bulk-dirs=dir-to-install
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR"
+    local _hash="$(sha256sum "$TEST_DIR/image1/bin/program" | cut -d ' ' -f 1)"
+    try_quoted grep -qxF "$_hash	bin/program	$PREFIX_DIR/bin/program" "$PREFIX_DIR/lib/packagelib/hashes-component"
+    # Files below bulk directories are recorded too
+    try_quoted grep -qF "	dir-to-install/foo	$PREFIX_DIR/dir-to-install/foo" "$PREFIX_DIR/lib/packagelib/hashes-component"
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR" --uninstall
+    try test ! -e "$PREFIX_DIR/lib/packagelib/hashes-component"
+}
+runtest install_records_hashes
+
+# Installs image1 with dir-to-install in bulk, then damages the installation
+make_damaged_install() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --bulk-dirs=dir-to-install \
+        "$@"
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR"
+    try rm "$PREFIX_DIR/bin/program"
+    echo changed > "$PREFIX_DIR/something-to-install"
+    try rm "$PREFIX_DIR/dir-to-install/foo"
+    echo extra > "$PREFIX_DIR/dir-to-install/extra"
+}
+
+status_clean() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --bulk-dirs=dir-to-install
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR"
+    try sh "$S/check-installation.sh" --prefix="$PREFIX_DIR"
+}
+runtest status_clean
+
+status_damaged() {
+    make_damaged_install
+    if sh "$S/check-installation.sh" --prefix="$PREFIX_DIR" > "$WORK_DIR/status" 2> /dev/null; then
+        echo "status of a damaged installation succeeded"
+        exit 1
+    fi
+    try_quoted grep -qxF "missing: $PREFIX_DIR/bin/program" "$WORK_DIR/status"
+    try_quoted grep -qxF "modified: $PREFIX_DIR/something-to-install" "$WORK_DIR/status"
+    try_quoted grep -qxF "missing: $PREFIX_DIR/dir-to-install/foo" "$WORK_DIR/status"
+    try_quoted grep -qxF "unexpected: $PREFIX_DIR/dir-to-install/extra" "$WORK_DIR/status"
+    try test "$(wc -l < "$WORK_DIR/status")" = 4
+}
+runtest status_damaged
+
+repair_damaged() {
+    make_damaged_install
+    expect_output_ok "repaired: $PREFIX_DIR/bin/program" \
+        sh "$S/repair-installation.sh" --prefix="$PREFIX_DIR" --input-tarball="$OUT_DIR/package.tar.gz"
+    try test -x "$PREFIX_DIR/bin/program"
+    try cmp "$TEST_DIR/image1/something-to-install" "$PREFIX_DIR/something-to-install"
+    try test -e "$PREFIX_DIR/dir-to-install/foo"
+    # Unexpected files are left alone
+    try test -e "$PREFIX_DIR/dir-to-install/extra"
+    try rm "$PREFIX_DIR/dir-to-install/extra"
+    try sh "$S/check-installation.sh" --prefix="$PREFIX_DIR"
+    expect_output_ok "nothing to repair" \
+        sh "$S/repair-installation.sh" --prefix="$PREFIX_DIR" --input-tarball="$OUT_DIR/package.tar.gz"
+}
+runtest repair_damaged
+
+repair_from_dedup_tarball() {
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$TEST_DIR/image1" \
+        --work-dir="$WORK_DIR" \
+        --output-dir="$OUT_DIR" \
+        --dedup
+    try "$WORK_DIR/package/install.sh" --prefix="$PREFIX_DIR"
+    # Both programs are identical, so the tarball stores one as a hardlink
+    try rm "$PREFIX_DIR/bin/program" "$PREFIX_DIR/bin/program2"
+    try sh "$S/repair-installation.sh" --prefix="$PREFIX_DIR" --input-tarball="$OUT_DIR/package.tar.gz"
+    try test -x "$PREFIX_DIR/bin/program"
+    try test -x "$PREFIX_DIR/bin/program2"
+    try sh "$S/check-installation.sh" --prefix="$PREFIX_DIR"
+}
+runtest repair_from_dedup_tarball
+
+repair_from_other_version_error() {
+    make_damaged_install
+    try cp -R "$TEST_DIR/image1" "$WORK_DIR/image"
+    echo other > "$WORK_DIR/image/bin/program"
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR/other" \
+        --output-dir="$OUT_DIR/other"
+    expect_output_fail "differs from the installed version" \
+        sh "$S/repair-installation.sh" --prefix="$PREFIX_DIR" --input-tarball="$OUT_DIR/other/package.tar.gz"
+    try test ! -e "$PREFIX_DIR/bin/program"
+}
+runtest repair_from_other_version_error
+
+repair_waits_for_lock() {
+    make_damaged_install
+    sleep 30 &
+    local _holder=$!
+    try mkdir "$PREFIX_DIR/lib/packagelib.lock"
+    echo "$(uname -n) $_holder" > "$PREFIX_DIR/lib/packagelib.lock/owner"
+    expect_output_fail "another installation is in progress" \
+        sh "$S/repair-installation.sh" --prefix="$PREFIX_DIR" \
+        --input-tarball="$OUT_DIR/package.tar.gz" --lock-timeout=1
+    kill $_holder
+    try test ! -e "$PREFIX_DIR/bin/program"
+    try test -e "$PREFIX_DIR/lib/packagelib.lock/owner"
+}
+runtest repair_waits_for_lock
+
+repair_breaks_stale_lock() {
+    make_damaged_install
+    sh -c 'exit 0' &
+    local _dead=$!
+    wait $_dead
+    try mkdir "$PREFIX_DIR/lib/packagelib.lock"
+    echo "$(uname -n) $_dead" > "$PREFIX_DIR/lib/packagelib.lock/owner"
+    expect_output_ok "removing stale lock" \
+        sh "$S/repair-installation.sh" --prefix="$PREFIX_DIR" \
+        --input-tarball="$OUT_DIR/package.tar.gz" --lock-timeout=0
+    try test -e "$PREFIX_DIR/bin/program"
+    try test ! -e "$PREFIX_DIR/lib/packagelib.lock"
+    try test ! -e "$PREFIX_DIR/lib/packagelib.lock.break"
+}
+runtest repair_breaks_stale_lock
+
+# Installs a copy of image1, with dir-to-install in bulk, then changes the
+# copy for a new version: bin/program2 and dir-to-install/foo change,
+# bin/program3 and dir-to-install/new are added and something-to-install
+# is removed
+make_upgrade() {
+    try cp -R "$TEST_DIR/image1" "$WORK_DIR/image"
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR/old" \
+        --output-dir="$OUT_DIR/old" \
+        --bulk-dirs=dir-to-install
+    try "$WORK_DIR/old/package/install.sh" --prefix="$PREFIX_DIR"
+    echo "#!/bin/sh" > "$WORK_DIR/image/bin/program2"
+    echo "changed" > "$WORK_DIR/image/dir-to-install/foo"
+    printf '#!/bin/sh\n' > "$WORK_DIR/image/bin/program3"
+    try chmod +x "$WORK_DIR/image/bin/program3"
+    echo "new" > "$WORK_DIR/image/dir-to-install/new"
+    try rm "$WORK_DIR/image/something-to-install"
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR/new" \
+        --output-dir="$OUT_DIR/new" \
+        --bulk-dirs=dir-to-install
+}
+
+inode_of() {
+    ls -i "$1" | cut -d ' ' -f 1
+}
+
+upgrade_keeps_unchanged_files() {
+    make_upgrade
+    local _program="$(inode_of "$PREFIX_DIR/bin/program")"
+    local _bad_bin="$(inode_of "$PREFIX_DIR/bin/bad-bin")"
+    expect_output_ok "keeping unchanged file $PREFIX_DIR/bin/program" \
+        "$WORK_DIR/new/package/install.sh" --prefix="$PREFIX_DIR" --verbose
+    try test "$(inode_of "$PREFIX_DIR/bin/program")" = "$_program"
+    try test "$(inode_of "$PREFIX_DIR/bin/bad-bin")" = "$_bad_bin"
+    try test -x "$PREFIX_DIR/bin/program"
+    try cmp "$WORK_DIR/image/bin/program2" "$PREFIX_DIR/bin/program2"
+    try test -x "$PREFIX_DIR/bin/program3"
+    try cmp "$WORK_DIR/image/dir-to-install/foo" "$PREFIX_DIR/dir-to-install/foo"
+    try test -e "$PREFIX_DIR/dir-to-install/new"
+    try test ! -e "$PREFIX_DIR/something-to-install"
+    try test ! -e "$PREFIX_DIR/bin/program.old"
+    try sh "$S/check-installation.sh" --prefix="$PREFIX_DIR"
+    try "$WORK_DIR/new/package/install.sh" --prefix="$PREFIX_DIR" --uninstall
+    try test ! -e "$PREFIX_DIR/bin/program"
+    try test ! -e "$PREFIX_DIR/dir-to-install"
+}
+runtest upgrade_keeps_unchanged_files
+
+upgrade_keeps_unchanged_files_in_bulk_dirs() {
+    try cp -R "$TEST_DIR/image1" "$WORK_DIR/image"
+    echo "same" > "$WORK_DIR/image/dir-to-install/same"
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR/old" \
+        --output-dir="$OUT_DIR/old" \
+        --bulk-dirs=dir-to-install
+    try "$WORK_DIR/old/package/install.sh" --prefix="$PREFIX_DIR"
+    echo "changed" > "$WORK_DIR/image/dir-to-install/foo"
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR/new" \
+        --output-dir="$OUT_DIR/new" \
+        --bulk-dirs=dir-to-install
+    local _same="$(inode_of "$PREFIX_DIR/dir-to-install/same")"
+    try "$WORK_DIR/new/package/install.sh" --prefix="$PREFIX_DIR"
+    try test "$(inode_of "$PREFIX_DIR/dir-to-install/same")" = "$_same"
+    try cmp "$WORK_DIR/image/dir-to-install/foo" "$PREFIX_DIR/dir-to-install/foo"
+    try test ! -e "$PREFIX_DIR/dir-to-install.old"
+    try sh "$S/check-installation.sh" --prefix="$PREFIX_DIR"
+}
+runtest upgrade_keeps_unchanged_files_in_bulk_dirs
+
+upgrade_changes_modes_of_unchanged_files() {
+    try cp -R "$TEST_DIR/image1" "$WORK_DIR/image"
+    echo "same" > "$WORK_DIR/image/dir-to-install/same"
+    printf '#!/bin/sh\n' > "$WORK_DIR/image/dir-to-install/tool"
+    try chmod 644 "$WORK_DIR/image/something-to-install" "$WORK_DIR/image/dir-to-install/same"
+    try chmod 755 "$WORK_DIR/image/dir-to-install/tool"
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR/old" \
+        --output-dir="$OUT_DIR/old" \
+        --bulk-dirs=dir-to-install
+    try "$WORK_DIR/old/package/install.sh" --prefix="$PREFIX_DIR"
+    try test ! -x "$PREFIX_DIR/something-to-install"
+    try test ! -x "$PREFIX_DIR/dir-to-install/same"
+    try chmod 755 "$WORK_DIR/image/something-to-install" "$WORK_DIR/image/dir-to-install/same"
+    try chmod 644 "$WORK_DIR/image/dir-to-install/tool"
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR/new" \
+        --output-dir="$OUT_DIR/new" \
+        --bulk-dirs=dir-to-install
+    expect_output_ok "keeping unchanged file $PREFIX_DIR/something-to-install" \
+        "$WORK_DIR/new/package/install.sh" --prefix="$PREFIX_DIR" --verbose
+    try test -x "$PREFIX_DIR/something-to-install"
+    try test -x "$PREFIX_DIR/dir-to-install/same"
+    try test ! -x "$PREFIX_DIR/dir-to-install/tool"
+    try sh "$S/check-installation.sh" --prefix="$PREFIX_DIR"
+}
+runtest upgrade_changes_modes_of_unchanged_files
+
+uninstall_ignores_interrupted_upgrade() {
+    make_upgrade
+    # As left by an upgrade that was interrupted before installing
+    echo "$PREFIX_DIR/bin/program" > "$PREFIX_DIR/lib/packagelib/keep-component"
+    try "$WORK_DIR/old/package/install.sh" --prefix="$PREFIX_DIR" --uninstall
+    try test ! -e "$PREFIX_DIR/bin/program"
+    try test ! -e "$PREFIX_DIR/lib/packagelib"
+}
+runtest uninstall_ignores_interrupted_upgrade
+
+upgrade_replaces_locally_modified_file() {
+    make_upgrade
+    echo "local change" > "$PREFIX_DIR/bin/program"
+    try "$WORK_DIR/new/package/install.sh" --prefix="$PREFIX_DIR"
+    try cmp "$WORK_DIR/image/bin/program" "$PREFIX_DIR/bin/program"
+    try sh "$S/check-installation.sh" --prefix="$PREFIX_DIR"
+}
+runtest upgrade_replaces_locally_modified_file
+
+upgrade_backs_up_foreign_files() {
+    make_upgrade
+    echo "foreign" > "$PREFIX_DIR/bin/program3"
+    try "$WORK_DIR/new/package/install.sh" --prefix="$PREFIX_DIR"
+    try test "$(cat "$PREFIX_DIR/bin/program3.old")" = foreign
+    try test -x "$PREFIX_DIR/bin/program3"
+}
+runtest upgrade_backs_up_foreign_files
+
+upgrade_to_other_bindir() {
+    make_upgrade
+    try "$WORK_DIR/new/package/install.sh" --prefix="$PREFIX_DIR" --bindir="$PREFIX_DIR/other-bin"
+    try test ! -e "$PREFIX_DIR/bin/program"
+    try test -x "$PREFIX_DIR/other-bin/program"
+    try sh "$S/check-installation.sh" --prefix="$PREFIX_DIR"
+}
+runtest upgrade_to_other_bindir
+
+self_extracting() {
+    try sh "$S/gen-installer.sh" \
+	--image-dir="$TEST_DIR/image1" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--self-extracting
+    try test -x "$OUT_DIR/package.run"
+    try mkdir "$WORK_DIR/tmp"
+    try env TMPDIR="$WORK_DIR/tmp" "$OUT_DIR/package.run" --prefix="$PREFIX_DIR"
+    try test -e "$PREFIX_DIR/something-to-install"
+    try test -e "$PREFIX_DIR/dir-to-install/foo"
+    try test -e "$PREFIX_DIR/bin/program"
+    try test -z "$(ls "$WORK_DIR/tmp")"
+    try "$OUT_DIR/package.run" --prefix="$PREFIX_DIR" --uninstall
+    try test ! -e "$PREFIX_DIR/something-to-install"
+    try test ! -e "$PREFIX_DIR/bin/program"
+}
+runtest self_extracting
+
+self_extracting_xz() {
+    try sh "$S/gen-installer.sh" \
+	--image-dir="$TEST_DIR/image1" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--compression-formats=xz \
+	--self-extracting
+    try "$OUT_DIR/package.run" --prefix="$PREFIX_DIR"
+    try test -e "$PREFIX_DIR/bin/program"
+}
+runtest self_extracting_xz
+
+self_extracting_streamed() {
+    try sh "$S/gen-installer.sh" \
+	--image-dir="$TEST_DIR/image1" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--stream \
+	--self-extracting
+    try "$OUT_DIR/package.run" --prefix="$PREFIX_DIR"
+    try test -e "$PREFIX_DIR/bin/program"
+}
+runtest self_extracting_streamed
+
+self_extracting_combined() {
+    try sh "$S/gen-installer.sh" \
+	--image-dir="$TEST_DIR/image1" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--package-name=rustc \
+	--component-name=rustc
+    try sh "$S/gen-installer.sh" \
+	--image-dir="$TEST_DIR/image3" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--package-name=cargo \
+	--component-name=cargo
+    try sh "$S/combine-installers.sh" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--package-name=rust \
+	--input-tarballs="$OUT_DIR/rustc.tar.gz,$OUT_DIR/cargo.tar.gz" \
+	--self-extracting
+    try "$OUT_DIR/rust.run" --prefix="$PREFIX_DIR"
+    try test -e "$PREFIX_DIR/bin/program"
+    try test -e "$PREFIX_DIR/bin/cargo"
+}
+runtest self_extracting_combined
+
+self_extracting_extract_only() {
+    try sh "$S/gen-installer.sh" \
+	--image-dir="$TEST_DIR/image1" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--self-extracting
+    expect_output_ok "extracted to $WORK_DIR/extracted/package" \
+	"$OUT_DIR/package.run" --extract-only="$WORK_DIR/extracted"
+    try test -x "$WORK_DIR/extracted/package/install.sh"
+    try test -e "$WORK_DIR/extracted/package/component/manifest.in"
+    try test ! -e "$PREFIX_DIR/bin/program"
+    expect_output_fail "already exists" \
+	"$OUT_DIR/package.run" --extract-only="$WORK_DIR/extracted"
+    try mkdir "$WORK_DIR/cwd"
+    (cd "$WORK_DIR/cwd" && "$OUT_DIR/package.run" --extract-only > /dev/null)
+    need_ok "failed to extract into the current directory"
+    try test -x "$WORK_DIR/cwd/package/install.sh"
+}
+runtest self_extracting_extract_only
+
+self_extracting_passes_failure() {
+    try sh "$S/gen-installer.sh" \
+	--image-dir="$TEST_DIR/image1" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--self-extracting
+    expect_output_fail "unable to find installation manifest" \
+	"$OUT_DIR/package.run" --prefix="$PREFIX_DIR" --uninstall
+    expect_output_ok "unpack the installer into DIR" "$OUT_DIR/package.run" --help
+}
+runtest self_extracting_passes_failure
+
+self_extracting_corrupt() {
+    try sh "$S/gen-installer.sh" \
+	--image-dir="$TEST_DIR/image1" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--self-extracting
+    local _size="$(wc -c < "$OUT_DIR/package.run")"
+    try cp "$OUT_DIR/package.run" "$WORK_DIR/corrupt.run"
+    printf 'x' | dd of="$WORK_DIR/corrupt.run" bs=1 seek=$(($_size - 30)) conv=notrunc 2> /dev/null
+    need_ok "failed to corrupt the payload"
+    expect_output_fail "checksum mismatch" "$WORK_DIR/corrupt.run" --prefix="$PREFIX_DIR"
+    head -c $(($_size - 10)) "$OUT_DIR/package.run" > "$WORK_DIR/truncated.run"
+    try chmod +x "$WORK_DIR/truncated.run"
+    expect_output_fail "may be truncated" "$WORK_DIR/truncated.run" --prefix="$PREFIX_DIR"
+    try test ! -e "$PREFIX_DIR/bin/program"
+}
+runtest self_extracting_corrupt
+
+# Generates an installer from image1, with a configuration file, a
+# document and a symlink in a bulk dir
+make_package_installer() {
+    try cp -R "$TEST_DIR/image1" "$WORK_DIR/image"
+    try mkdir -p "$WORK_DIR/image/etc/rust" "$WORK_DIR/image/share/doc/rust"
+    echo "config" > "$WORK_DIR/image/etc/rust/config"
+    echo "readme" > "$WORK_DIR/image/share/doc/rust/README"
+    try ln -s ../something-to-install "$WORK_DIR/image/dir-to-install/link"
+    try sh "$S/gen-installer.sh" \
+	--image-dir="$WORK_DIR/image" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--package-name=rust-nightly \
+	--component-name=rustc \
+	--bulk-dirs=dir-to-install
+}
+
+deb_package() {
+    make_package_installer
+    try_quoted sh "$S/make-deb.sh" \
+	--input-tarball="$OUT_DIR/rust-nightly.tar.gz" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--version=1.2.3-1 \
+	--architecture=amd64 \
+	--maintainer="Rust Developers <dev@example.com>"
+    local _deb="$OUT_DIR/rust-nightly_1.2.3-1_amd64.deb"
+    try test -f "$_deb"
+    try_quoted test "$(ar t "$_deb" | tr "\n" " ")" = "debian-binary control.tar.xz data.tar.xz "
+    try test "$(ar p "$_deb" debian-binary)" = "2.0"
+    ar p "$_deb" control.tar.xz | tar -xJOf - control > "$WORK_DIR/control"
+    need_ok "failed to extract the control file"
+    try_quoted grep -qx "Package: rust-nightly" "$WORK_DIR/control"
+    try_quoted grep -qx "Version: 1.2.3-1" "$WORK_DIR/control"
+    try_quoted grep -qx "Architecture: amd64" "$WORK_DIR/control"
+    try_quoted grep -qx "Description: rustc components" "$WORK_DIR/control"
+    try_quoted grep -qx "Installed-Size: [0-9]*" "$WORK_DIR/control"
+    ar p "$_deb" control.tar.xz | tar -xJOf - conffiles > "$WORK_DIR/conffiles"
+    need_ok "failed to extract the conffiles"
+    try test "$(cat "$WORK_DIR/conffiles")" = /etc/rust/config
+    ar p "$_deb" control.tar.xz | tar -xJOf - md5sums > "$WORK_DIR/md5sums"
+    need_ok "failed to extract the md5sums"
+    try_quoted grep -q " usr/bin/program$" "$WORK_DIR/md5sums"
+    try mkdir "$WORK_DIR/data"
+    ar p "$_deb" data.tar.xz | tar -xJf - -C "$WORK_DIR/data"
+    need_ok "failed to extract the data"
+    try test -x "$WORK_DIR/data/usr/bin/program"
+    try test ! -x "$WORK_DIR/data/usr/something-to-install"
+    try test -L "$WORK_DIR/data/usr/dir-to-install/link"
+    try test -f "$WORK_DIR/data/usr/share/doc/rust/README"
+    try test -f "$WORK_DIR/data/etc/rust/config"
+    try test ! -e "$WORK_DIR/data/usr/manifest.in"
+    ar p "$_deb" data.tar.xz | tar -tvJf - > "$WORK_DIR/listing"
+    need_ok "failed to list the data"
+    try_quoted grep -q "root/root .* usr/bin/program$" "$WORK_DIR/listing"
+}
+runtest deb_package
+
+deb_package_prefix() {
+    make_package_installer
+    try_quoted sh "$S/make-deb.sh" \
+	--input-tarball="$OUT_DIR/rust-nightly.tar.gz" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--prefix=/opt/rust \
+	--compression-format=gz \
+	--package-name=rust \
+	--version=1:1.2.3 \
+	--architecture=arm64 \
+	--maintainer="Rust Developers <dev@example.com>" \
+	--depends="libc6 (>= 2.17)"
+    local _deb="$OUT_DIR/rust_1.2.3_arm64.deb"
+    try_quoted test "$(ar t "$_deb" | tr "\n" " ")" = "debian-binary control.tar.gz data.tar.gz "
+    ar p "$_deb" control.tar.gz | tar -xzOf - control > "$WORK_DIR/control"
+    need_ok "failed to extract the control file"
+    try_quoted grep -qx "Version: 1:1.2.3" "$WORK_DIR/control"
+    try_quoted grep -qx "Depends: libc6 (>= 2.17)" "$WORK_DIR/control"
+    ar p "$_deb" control.tar.gz | tar -xzOf - conffiles > "$WORK_DIR/conffiles"
+    need_ok "failed to extract the conffiles"
+    try test "$(cat "$WORK_DIR/conffiles")" = /opt/rust/etc/rust/config
+    try mkdir "$WORK_DIR/data"
+    ar p "$_deb" data.tar.gz | tar -xzf - -C "$WORK_DIR/data"
+    need_ok "failed to extract the data"
+    try test -x "$WORK_DIR/data/opt/rust/bin/program"
+    try test -f "$WORK_DIR/data/opt/rust/etc/rust/config"
+}
+runtest deb_package_prefix
+
+deb_package_combined() {
+    try sh "$S/gen-installer.sh" \
+	--image-dir="$TEST_DIR/image1" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--package-name=rustc \
+	--component-name=rustc
+    try sh "$S/gen-installer.sh" \
+	--image-dir="$TEST_DIR/image3" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--package-name=cargo \
+	--component-name=cargo
+    try sh "$S/combine-installers.sh" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--package-name=rust \
+	--input-tarballs="$OUT_DIR/rustc.tar.gz,$OUT_DIR/cargo.tar.gz"
+    try_quoted sh "$S/make-deb.sh" \
+	--input-tarball="$OUT_DIR/rust.tar.xz" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--version=1.0 \
+	--architecture=all \
+	--maintainer="Rust Developers <dev@example.com>"
+    local _deb="$OUT_DIR/rust_1.0_all.deb"
+    ar p "$_deb" control.tar.xz | tar -xJOf - control > "$WORK_DIR/control"
+    need_ok "failed to extract the control file"
+    try_quoted grep -qx "Description: rustc, cargo components" "$WORK_DIR/control"
+    try_quoted grep -qx " \* cargo" "$WORK_DIR/control"
+    ar p "$_deb" data.tar.xz | tar -tJf - > "$WORK_DIR/listing"
+    need_ok "failed to list the data"
+    try_quoted grep -qx "usr/bin/cargo" "$WORK_DIR/listing"
+    try_quoted grep -qx "usr/bin/program" "$WORK_DIR/listing"
+}
+runtest deb_package_combined
+
+deb_package_errors() {
+    make_package_installer
+    expect_output_fail "needs --version and --maintainer" sh "$S/make-deb.sh" \
+	--input-tarball="$OUT_DIR/rust-nightly.tar.gz" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR"
+    expect_output_fail "not a valid Debian package name" sh "$S/make-deb.sh" \
+	--input-tarball="$OUT_DIR/rust-nightly.tar.gz" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--package-name=Rust_Nightly \
+	--version=1.0 \
+	--maintainer=someone
+    expect_output_fail "not an absolute path" sh "$S/make-deb.sh" \
+	--input-tarball="$OUT_DIR/rust-nightly.tar.gz" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--prefix=usr \
+	--version=1.0 \
+	--maintainer=someone
+}
+runtest deb_package_errors
+
+deb_package_reproducible() {
+    make_package_installer
+    local _i
+    for _i in 1 2; do
+	try env SOURCE_DATE_EPOCH=1700000000 sh "$S/make-deb.sh" \
+	    --input-tarball="$OUT_DIR/rust-nightly.tar.gz" \
+	    --work-dir="$WORK_DIR" \
+	    --output-dir="$OUT_DIR/$_i" \
+	    --version=1.0 \
+	    --architecture=amd64 \
+	    --maintainer=someone
+    done
+    try cmp "$OUT_DIR/1/rust-nightly_1.0_amd64.deb" "$OUT_DIR/2/rust-nightly_1.0_amd64.deb"
+}
+runtest deb_package_reproducible
+
+deb_package_installs_with_dpkg() {
+    if ! command -v dpkg > /dev/null 2>&1; then
+	echo "skipping, dpkg is not installed"
+	return
+    fi
+    make_package_installer
+    try_quoted sh "$S/make-deb.sh" \
+	--input-tarball="$OUT_DIR/rust-nightly.tar.gz" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--version=1.0 \
+	--maintainer=someone
+    local _root="$WORK_DIR/root"
+    try mkdir -p "$_root/var/lib/dpkg/info" "$_root/var/lib/dpkg/updates"
+    try touch "$_root/var/lib/dpkg/status"
+    try dpkg --root="$_root" --log=/dev/null --force-script-chrootless --force-not-root -i "$OUT_DIR"/rust-nightly_1.0_*.deb
+    try test -x "$_root/usr/bin/program"
+    try dpkg --root="$_root" --verify rust-nightly
+}
+runtest deb_package_installs_with_dpkg
+
+rpm_package() {
+    make_package_installer
+    try sh "$S/make-rpm.sh" \
+	--input-tarball="$OUT_DIR/rust-nightly.tar.gz" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--version=1.2.3 \
+	--architecture=x86_64
+    local _rpm="$OUT_DIR/rust-nightly-1.2.3-1.x86_64.rpm"
+    try test -f "$_rpm"
+    try test "$(od -An -tx1 -N4 "$_rpm" | tr -d " ")" = edabeedb
+    if ! command -v bsdtar > /dev/null 2>&1; then
+	echo "skipping the payload checks, bsdtar is not installed"
+	return
+    fi
+    try mkdir "$WORK_DIR/data"
+    try bsdtar -xf "$_rpm" -C "$WORK_DIR/data"
+    try test -x "$WORK_DIR/data/usr/bin/program"
+    try test ! -x "$WORK_DIR/data/usr/something-to-install"
+    try test -L "$WORK_DIR/data/usr/dir-to-install/link"
+    try test -f "$WORK_DIR/data/usr/share/doc/rust/README"
+    try test -f "$WORK_DIR/data/etc/rust/config"
+    try test ! -e "$WORK_DIR/data/usr/manifest.in"
+    bsdtar -tvf "$_rpm" > "$WORK_DIR/listing"
+    need_ok "failed to list the payload"
+    try_quoted grep -q " 0  *0 .* \./usr/bin/program$" "$WORK_DIR/listing"
+}
+runtest rpm_package
+
+rpm_package_prefix() {
+    make_package_installer
+    try sh "$S/make-rpm.sh" \
+	--input-tarball="$OUT_DIR/rust-nightly.tar.gz" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--prefix=/opt/rust \
+	--compression-format=gz \
+	--package-name=rust \
+	--version=1.2.3 \
+	--release=2 \
+	--architecture=aarch64
+    local _rpm="$OUT_DIR/rust-1.2.3-2.aarch64.rpm"
+    try test -f "$_rpm"
+    if ! command -v bsdtar > /dev/null 2>&1; then
+	echo "skipping the payload checks, bsdtar is not installed"
+	return
+    fi
+    try mkdir "$WORK_DIR/data"
+    try bsdtar -xf "$_rpm" -C "$WORK_DIR/data"
+    try test -x "$WORK_DIR/data/opt/rust/bin/program"
+    try test -f "$WORK_DIR/data/opt/rust/etc/rust/config"
+}
+runtest rpm_package_prefix
+
+rpm_package_errors() {
+    make_package_installer
+    expect_output_fail "needs --version" sh "$S/make-rpm.sh" \
+	--input-tarball="$OUT_DIR/rust-nightly.tar.gz" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR"
+    expect_output_fail "not a valid version" sh "$S/make-rpm.sh" \
+	--input-tarball="$OUT_DIR/rust-nightly.tar.gz" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--version=1.0-beta
+    sh "$S/make-rpm.sh" \
+	--input-tarball="$OUT_DIR/rust-nightly.tar.gz" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--version=1.0 \
+	--requires="glibc => 2.17" > "$WORK_DIR/output" 2>&1 &&
+	fail "an unknown comparison was accepted"
+    try grep -q "unknown comparison" "$WORK_DIR/output"
+}
+runtest rpm_package_errors
+
+rpm_package_reproducible() {
+    make_package_installer
+    local _i
+    for _i in 1 2; do
+	try env SOURCE_DATE_EPOCH=1700000000 sh "$S/make-rpm.sh" \
+	    --input-tarball="$OUT_DIR/rust-nightly.tar.gz" \
+	    --work-dir="$WORK_DIR" \
+	    --output-dir="$OUT_DIR/$_i" \
+	    --version=1.0 \
+	    --architecture=x86_64
+    done
+    try cmp "$OUT_DIR/1/rust-nightly-1.0-1.x86_64.rpm" "$OUT_DIR/2/rust-nightly-1.0-1.x86_64.rpm"
+}
+runtest rpm_package_reproducible
+
+rpm_package_queries_with_rpm() {
+    if ! command -v rpm > /dev/null 2>&1; then
+	echo "skipping, rpm is not installed"
+	return
+    fi
+    make_package_installer
+    try_quoted sh "$S/make-rpm.sh" \
+	--input-tarball="$OUT_DIR/rust-nightly.tar.gz" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--version=1.0 \
+	--requires="glibc >= 2.17"
+    local _rpm="$(echo "$OUT_DIR"/rust-nightly-1.0-1.*.rpm)"
+    try rpm -K --nosignature "$_rpm"
+    try test "$(rpm -qp --qf "%{NAME}-%{VERSION}-%{RELEASE}" "$_rpm")" = rust-nightly-1.0-1
+    try test "$(rpm -qpc "$_rpm")" = /etc/rust/config
+    rpm -qpR "$_rpm" > "$WORK_DIR/requires"
+    need_ok "failed to query the requirements"
+    try_quoted grep -qx "glibc >= 2.17" "$WORK_DIR/requires"
+    expect_output "/usr/bin/program" rpm -qpl "$_rpm"
+}
+runtest rpm_package_queries_with_rpm
+
+# Prints the hex digest that follows the given media type in a JSON file
+oci_digest() {
+    grep -o "\"mediaType\":\"$2\",\"digest\":\"sha256:[0-9a-f]*\"" "$1" | sed 's/.*sha256://; s/"$//'
+}
+
+tarball_oci_layout() {
+    make_package_installer
+    try sh "$S/make-tarballs.sh" \
+	--input=rust-nightly \
+	--work-dir="$WORK_DIR" \
+	--output="$OUT_DIR/rust-nightly" \
+	--oci-prefix=/opt/rust \
+	--oci-architecture=arm64
+    local _oci="$OUT_DIR/rust-nightly.oci"
+    try test -e "$OUT_DIR/rust-nightly.tar.gz"
+    try_quoted test "$(cat "$_oci/oci-layout")" = '{"imageLayoutVersion":"1.0.0"}'
+    try_quoted grep -q '"org.opencontainers.image.ref.name":"rust-nightly"' "$_oci/index.json"
+    local _blob
+    for _blob in "$_oci"/blobs/sha256/*; do
+	try test "$(sha256sum "$_blob" | cut -d ' ' -f 1)" = "$(basename "$_blob")"
+    done
+    local _manifest="$(oci_digest "$_oci/index.json" "application/vnd.oci.image.manifest.v1+json")"
+    try test -n "$_manifest"
+    try_quoted grep -q "\"digest\":\"sha256:$_manifest\",\"size\":$(wc -c < "$_oci/blobs/sha256/$_manifest")," "$_oci/index.json"
+    local _config="$(oci_digest "$_oci/blobs/sha256/$_manifest" "application/vnd.oci.image.config.v1+json")"
+    local _layer="$(oci_digest "$_oci/blobs/sha256/$_manifest" "application/vnd.oci.image.layer.v1.tar+gzip")"
+    try test -f "$_oci/blobs/sha256/$_config"
+    try test -f "$_oci/blobs/sha256/$_layer"
+    try_quoted grep -q '"architecture":"arm64","os":"linux"' "$_oci/blobs/sha256/$_config"
+    local _diff_id="$(gzip -dc "$_oci/blobs/sha256/$_layer" | sha256sum | cut -d ' ' -f 1)"
+    try_quoted grep -q "\"diff_ids\":\[\"sha256:$_diff_id\"\]" "$_oci/blobs/sha256/$_config"
+    tar -tvzf "$_oci/blobs/sha256/$_layer" > "$WORK_DIR/listing"
+    need_ok "failed to list the layer"
+    try_quoted grep -q "^-rwxr-xr-x root/root .* opt/rust/bin/program$" "$WORK_DIR/listing"
+    try_quoted grep -q " opt/rust/etc/rust/config$" "$WORK_DIR/listing"
+    try_quoted grep -q " opt/rust/dir-to-install/link -> ../something-to-install$" "$WORK_DIR/listing"
+    try_quoted grep -q "^d.* opt/rust/share/doc$" "$WORK_DIR/listing"
+    expect_not_output_ok "manifest.in" tar -tzf "$_oci/blobs/sha256/$_layer"
+}
+runtest tarball_oci_layout
+
+tarball_oci_layout_reproducible() {
+    make_package_installer
+    local _i
+    for _i in 1 2; do
+	try env SOURCE_DATE_EPOCH=1700000000 sh "$S/make-tarballs.sh" \
+	    --input=rust-nightly \
+	    --work-dir="$WORK_DIR" \
+	    --output="$OUT_DIR/$_i" \
+	    --oci-prefix=/usr/local \
+	    --oci-architecture=amd64
+    done
+    try cmp "$OUT_DIR/1.oci/index.json" "$OUT_DIR/2.oci/index.json"
+}
+runtest tarball_oci_layout_reproducible
+
+tarball_oci_layout_needs_installer() {
+    try cp -r "$TEST_DIR/image1" "$WORK_DIR/image"
+    expect_output_fail "failed to read version" sh "$S/make-tarballs.sh" \
+	--input=image \
+	--work-dir="$WORK_DIR" \
+	--output="$OUT_DIR/image" \
+	--oci-prefix=/usr/local
+    try test ! -e "$OUT_DIR/image.tar.gz"
+    try test ! -e "$OUT_DIR/image.oci"
+    expect_output_fail "not an absolute path" sh "$S/make-tarballs.sh" \
+	--input=image \
+	--work-dir="$WORK_DIR" \
+	--output="$OUT_DIR/image" \
+	--oci-prefix=usr/local
+}
+runtest tarball_oci_layout_needs_installer
+
+zip_archive() {
+    try sh "$S/gen-installer.sh" \
+	--image-dir="$TEST_DIR/image1" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--package-name=rustc \
+	--component-name=rustc \
+	--compression-formats=gz,zip
+    try test -e "$OUT_DIR/rustc.tar.gz"
+    try test ! -e "$OUT_DIR/rustc.tar.xz"
+    try test -e "$OUT_DIR/rustc.zip"
+    if ! command -v unzip > /dev/null 2>&1; then
+	echo "skipping the archive checks, unzip is not installed"
+	return
+    fi
+    # The zip has the same paths as the tarball
+    local _zip_paths="$(unzip -Z1 "$OUT_DIR/rustc.zip" | sed 's|/$||' | sort)"
+    local _tar_paths="$(tar -tzf "$OUT_DIR/rustc.tar.gz" | sed 's|/$||' | sort)"
+    try_quoted test "$_zip_paths" = "$_tar_paths"
+    unzip -Z "$OUT_DIR/rustc.zip" > "$WORK_DIR/listing"
+    need_ok "failed to list the zip archive"
+    try_quoted grep -q "^-rwxr-xr-x .* unx .* rustc/install.sh$" "$WORK_DIR/listing"
+    # The image keeps the modes of the checkout, which depend on the umask
+    try_quoted grep -q "^-rwx.* unx .* rustc/rustc/bin/program$" "$WORK_DIR/listing"
+    try_quoted grep -q "^-rw-.* unx .* rustc/rustc/something-to-install$" "$WORK_DIR/listing"
+    try mkdir "$WORK_DIR/unzipped"
+    try unzip -q "$OUT_DIR/rustc.zip" -d "$WORK_DIR/unzipped"
+    try "$WORK_DIR/unzipped/rustc/install.sh" --prefix="$PREFIX_DIR"
+    try test -x "$PREFIX_DIR/bin/program"
+    try test -f "$PREFIX_DIR/something-to-install"
+}
+runtest zip_archive
+
+zip_archive_symlinks() {
+    try cp -R "$TEST_DIR/image1" "$WORK_DIR/image"
+    try ln -s ../something-to-install "$WORK_DIR/image/dir-to-install/link"
+    try sh "$S/gen-installer.sh" \
+	--image-dir="$WORK_DIR/image" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--package-name=rustc \
+	--component-name=rustc \
+	--bulk-dirs=dir-to-install \
+	--compression-formats=zip
+    try test ! -e "$OUT_DIR/rustc.tar.gz"
+    try test ! -e "$OUT_DIR/rustc.tar.xz"
+    if ! command -v unzip > /dev/null 2>&1; then
+	echo "skipping the archive checks, unzip is not installed"
+	return
+    fi
+    unzip -Z "$OUT_DIR/rustc.zip" > "$WORK_DIR/listing"
+    need_ok "failed to list the zip archive"
+    try_quoted grep -q "^lrwxrwxrwx .* rustc/rustc/dir-to-install/link$" "$WORK_DIR/listing"
+    try mkdir "$WORK_DIR/unzipped"
+    try unzip -q "$OUT_DIR/rustc.zip" -d "$WORK_DIR/unzipped"
+    try test -L "$WORK_DIR/unzipped/rustc/rustc/dir-to-install/link"
+    try test "$(readlink "$WORK_DIR/unzipped/rustc/rustc/dir-to-install/link")" = ../something-to-install
+}
+runtest zip_archive_symlinks
+
+zip_archive_streamed() {
+    try sh "$S/gen-installer.sh" \
+	--image-dir="$TEST_DIR/image1" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--package-name=rustc \
+	--component-name=rustc \
+	--compression-formats=zip,xz \
+	--stream
+    try test -e "$OUT_DIR/rustc.tar.xz"
+    try test -e "$OUT_DIR/rustc.zip"
+    if ! command -v unzip > /dev/null 2>&1; then
+	echo "skipping the archive checks, unzip is not installed"
+	return
+    fi
+    unzip -Z "$OUT_DIR/rustc.zip" > "$WORK_DIR/listing"
+    need_ok "failed to list the zip archive"
+    try_quoted grep -q "^-rwxr-xr-x .* rustc/install.sh$" "$WORK_DIR/listing"
+    try_quoted grep -q "^-rw-r--r-- .* rustc/rustc/manifest.in$" "$WORK_DIR/listing"
+    try_quoted grep -q "^drwx.* rustc/rustc/bin/$" "$WORK_DIR/listing"
+}
+runtest zip_archive_streamed
+
+zip_archive_combined() {
+    try sh "$S/gen-installer.sh" \
+	--image-dir="$TEST_DIR/image1" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--package-name=rustc \
+	--component-name=rustc
+    try sh "$S/gen-installer.sh" \
+	--image-dir="$TEST_DIR/image3" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--package-name=cargo \
+	--component-name=cargo
+    try sh "$S/combine-installers.sh" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--package-name=rust \
+	--input-tarballs="$OUT_DIR/rustc.tar.gz,$OUT_DIR/cargo.tar.gz" \
+	--compression-formats=gz,zip
+    try test -e "$OUT_DIR/rust.tar.gz"
+    try test -e "$OUT_DIR/rust.zip"
+    if ! command -v unzip > /dev/null 2>&1; then
+	echo "skipping the archive checks, unzip is not installed"
+	return
+    fi
+    try mkdir "$WORK_DIR/unzipped"
+    try unzip -q "$OUT_DIR/rust.zip" -d "$WORK_DIR/unzipped"
+    try "$WORK_DIR/unzipped/rust/install.sh" --prefix="$PREFIX_DIR"
+    try test -x "$PREFIX_DIR/bin/program"
+    try test -x "$PREFIX_DIR/bin/cargo"
+}
+runtest zip_archive_combined
+
+zst_tarballs() {
+    try sh "$S/gen-installer.sh" \
+	--image-dir="$TEST_DIR/image1" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--package-name=rustc \
+	--component-name=rustc \
+	--compression-formats=zst
+    try test -e "$OUT_DIR/rustc.tar.zst"
+    try test ! -e "$OUT_DIR/rustc.tar.gz"
+    try sh "$S/combine-installers.sh" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--package-name=rust \
+	--input-tarballs="$OUT_DIR/rustc.tar.zst" \
+	--compression-formats=gz
+    try tar -xzf "$OUT_DIR/rust.tar.gz" -C "$WORK_DIR"
+    try "$WORK_DIR/rust/install.sh" --prefix="$PREFIX_DIR"
+    try test -e "$PREFIX_DIR/bin/program"
+}
+runtest zst_tarballs
+
+self_extracting_zst() {
+    if ! command -v zstd > /dev/null 2>&1; then
+	echo "skipping, zstd is not installed"
+	return
+    fi
+    try sh "$S/gen-installer.sh" \
+	--image-dir="$TEST_DIR/image1" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--compression-formats=zst \
+	--self-extracting
+    try "$OUT_DIR/package.run" --prefix="$PREFIX_DIR"
+    try test -e "$PREFIX_DIR/bin/program"
+}
+runtest self_extracting_zst
+
+pacman_package() {
+    if ! command -v zstd > /dev/null 2>&1; then
+	echo "skipping, zstd is not installed"
+	return
+    fi
+    make_package_installer
+    try_quoted sh "$S/make-pacman.sh" \
+	--input-tarball="$OUT_DIR/rust-nightly.tar.gz" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--version=1.2.3 \
+	--architecture=x86_64 \
+	--license=MIT,Apache-2.0 \
+	--depends="glibc>=2.17, gcc-libs"
+    local _pkg="$OUT_DIR/rust-nightly-1.2.3-1-x86_64.pkg.tar.zst"
+    try test -f "$_pkg"
+    try mkdir "$WORK_DIR/data"
+    zstd -dc "$_pkg" > "$WORK_DIR/package.tar"
+    need_ok "failed to decompress the package"
+    try_quoted test "$(tar -tf "$WORK_DIR/package.tar" | head -2 | tr '\n' ' ')" = ".PKGINFO .MTREE "
+    try tar -xf "$WORK_DIR/package.tar" -C "$WORK_DIR/data"
+    try test -x "$WORK_DIR/data/usr/bin/program"
+    try test ! -x "$WORK_DIR/data/usr/something-to-install"
+    try test -L "$WORK_DIR/data/usr/dir-to-install/link"
+    try test -f "$WORK_DIR/data/usr/share/doc/rust/README"
+    try test -f "$WORK_DIR/data/etc/rust/config"
+    try test ! -e "$WORK_DIR/data/usr/manifest.in"
+    local _pkginfo="$WORK_DIR/data/.PKGINFO"
+    try_quoted grep -qx "pkgname = rust-nightly" "$_pkginfo"
+    try_quoted grep -qx "pkgver = 1.2.3-1" "$_pkginfo"
+    try_quoted grep -qx "arch = x86_64" "$_pkginfo"
+    try_quoted grep -qx "license = Apache-2.0" "$_pkginfo"
+    try_quoted grep -qx "backup = etc/rust/config" "$_pkginfo"
+    try_quoted grep -qx "depend = glibc>=2.17" "$_pkginfo"
+    try_quoted grep -qx "depend = gcc-libs" "$_pkginfo"
+    gzip -dc "$WORK_DIR/data/.MTREE" > "$WORK_DIR/mtree"
+    need_ok "failed to decompress the .MTREE"
+    local _sha256="$(sha256sum "$WORK_DIR/data/usr/bin/program" | cut -d ' ' -f 1)"
+    try_quoted grep -q "^\./usr/bin/program time=[0-9]*\.0 mode=755 size=9 .*sha256digest=$_sha256$" "$WORK_DIR/mtree"
+    try_quoted grep -q "^\./usr/dir-to-install/link .* type=link link=\.\./something-to-install$" "$WORK_DIR/mtree"
+    try_quoted grep -q "^\./usr/share/doc time=[0-9]*\.0 mode=755 type=dir$" "$WORK_DIR/mtree"
+    local _sha256="$(sha256sum "$_pkginfo" | cut -d ' ' -f 1)"
+    try_quoted grep -q "^\./\.PKGINFO .*sha256digest=$_sha256$" "$WORK_DIR/mtree"
+}
+runtest pacman_package
+
+pacman_package_prefix() {
+    if ! command -v zstd > /dev/null 2>&1; then
+	echo "skipping, zstd is not installed"
+	return
+    fi
+    make_package_installer
+    try sh "$S/make-pacman.sh" \
+	--input-tarball="$OUT_DIR/rust-nightly.tar.gz" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--prefix=/opt/rust \
+	--package-name=rust \
+	--version=1.2.3 \
+	--release=2 \
+	--architecture=any
+    local _pkg="$OUT_DIR/rust-1.2.3-2-any.pkg.tar.zst"
+    try mkdir "$WORK_DIR/data"
+    zstd -dc "$_pkg" | tar -xf - -C "$WORK_DIR/data"
+    need_ok "failed to unpack the package"
+    try test -x "$WORK_DIR/data/opt/rust/bin/program"
+    try test -f "$WORK_DIR/data/opt/rust/etc/rust/config"
+    try_quoted grep -qx "backup = opt/rust/etc/rust/config" "$WORK_DIR/data/.PKGINFO"
+}
+runtest pacman_package_prefix
+
+pacman_package_errors() {
+    make_package_installer
+    expect_output_fail "needs --version" sh "$S/make-pacman.sh" \
+	--input-tarball="$OUT_DIR/rust-nightly.tar.gz" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR"
+    expect_output_fail "not a valid version" sh "$S/make-pacman.sh" \
+	--input-tarball="$OUT_DIR/rust-nightly.tar.gz" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--version=1.0-beta
+    expect_output_fail "not a valid Arch Linux package name" sh "$S/make-pacman.sh" \
+	--input-tarball="$OUT_DIR/rust-nightly.tar.gz" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--package-name=Rust \
+	--version=1.0
+    sh "$S/make-pacman.sh" \
+	--input-tarball="$OUT_DIR/rust-nightly.tar.gz" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--version=1.0 \
+	--depends="glibc >= 2.17" > "$WORK_DIR/output" 2>&1 &&
+	fail "a dependency with spaces was accepted"
+    try_quoted grep -q "not a valid dependency" "$WORK_DIR/output"
+}
+runtest pacman_package_errors
+
+pacman_package_reproducible() {
+    make_package_installer
+    local _i
+    for _i in 1 2; do
+	try env SOURCE_DATE_EPOCH=1700000000 sh "$S/make-pacman.sh" \
+	    --input-tarball="$OUT_DIR/rust-nightly.tar.gz" \
+	    --work-dir="$WORK_DIR" \
+	    --output-dir="$OUT_DIR/$_i" \
+	    --version=1.0 \
+	    --architecture=x86_64
+    done
+    try cmp "$OUT_DIR/1/rust-nightly-1.0-1-x86_64.pkg.tar.zst" "$OUT_DIR/2/rust-nightly-1.0-1-x86_64.pkg.tar.zst"
+}
+runtest pacman_package_reproducible
+
+pacman_package_queries_with_pacman() {
+    if ! command -v pacman > /dev/null 2>&1; then
+	echo "skipping, pacman is not installed"
+	return
+    fi
+    make_package_installer
+    try sh "$S/make-pacman.sh" \
+	--input-tarball="$OUT_DIR/rust-nightly.tar.gz" \
+	--work-dir="$WORK_DIR" \
+	--output-dir="$OUT_DIR" \
+	--version=1.0
+    local _pkg="$(echo "$OUT_DIR"/rust-nightly-1.0-1-*.pkg.tar.zst)"
+    try mkdir "$WORK_DIR/db"
+    expect_output "rust-nightly 1.0-1" pacman --dbpath "$WORK_DIR/db" -Qp "$_pkg"
+    expect_output "/usr/bin/program" pacman --dbpath "$WORK_DIR/db" -Qlp "$_pkg"
+    expect_output "etc/rust/config" pacman --dbpath "$WORK_DIR/db" -Qip "$_pkg"
+}
+runtest pacman_package_queries_with_pacman
+
+# Makes a delta between the installers of make_upgrade, with the old version
+# installed, and unpacks it
+make_delta() {
+    make_upgrade
+    try sh "$S/make-delta.sh" \
+	--old-tarball="$OUT_DIR/old/package.tar.gz" \
+	--new-tarball="$OUT_DIR/new/package.tar.gz" \
+	--work-dir="$WORK_DIR/delta" \
+	--output-dir="$OUT_DIR/delta"
+    try mkdir "$WORK_DIR/unpacked"
+    try tar -xzf "$OUT_DIR/delta/package-delta.tar.gz" -C "$WORK_DIR/unpacked"
+}
+
+delta_installer() {
+    make_delta
+    local _delta="$WORK_DIR/unpacked/package-delta"
+    try cmp "$WORK_DIR/image/bin/program2" "$_delta/component/bin/program2"
+    try test -x "$_delta/component/bin/program3"
+    try test -e "$_delta/component/dir-to-install/new"
+    try test -e "$_delta/component/dir-to-install/foo"
+    try test ! -e "$_delta/component/bin/program"
+    try test ! -e "$_delta/component/bin/bad-bin"
+    try test ! -e "$_delta/component/something-to-install"
+    try grep -qx "keep:bin/program" "$_delta/component/delta.in"
+    try_quoted test "$(grep -vc '^keep:' "$_delta/component/delta.in")" = 0
+    try cmp "$WORK_DIR/old/package/component/hashes.in" "$_delta/component/base-hashes.in"
+    try cmp "$WORK_DIR/new/package/component/manifest.in" "$_delta/component/manifest.in"
+    local _program="$(inode_of "$PREFIX_DIR/bin/program")"
+    expect_output_ok "upgrading component 'component' from package" \
+	"$_delta/install.sh" --prefix="$PREFIX_DIR"
+    try test "$(inode_of "$PREFIX_DIR/bin/program")" = "$_program"
+    try test -x "$PREFIX_DIR/bin/program"
+    try cmp "$WORK_DIR/image/bin/program2" "$PREFIX_DIR/bin/program2"
+    try test -x "$PREFIX_DIR/bin/program3"
+    try cmp "$WORK_DIR/image/dir-to-install/foo" "$PREFIX_DIR/dir-to-install/foo"
+    try test -e "$PREFIX_DIR/dir-to-install/new"
+    try test ! -e "$PREFIX_DIR/something-to-install"
+    try test ! -e "$PREFIX_DIR/lib/packagelib/keep-component"
+    try sh "$S/check-installation.sh" --prefix="$PREFIX_DIR"
+    try "$PREFIX_DIR/lib/packagelib/uninstall.sh"
+    try test ! -e "$PREFIX_DIR/bin/program"
+    try test ! -e "$PREFIX_DIR/dir-to-install"
+}
+runtest delta_installer
+
+delta_installer_carries_mode_changes() {
+    try cp -R "$TEST_DIR/image1" "$WORK_DIR/image"
+    try chmod 644 "$WORK_DIR/image/something-to-install" "$WORK_DIR/image/dir-to-install/foo"
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR/old" \
+        --output-dir="$OUT_DIR/old" \
+        --bulk-dirs=dir-to-install
+    try "$WORK_DIR/old/package/install.sh" --prefix="$PREFIX_DIR"
+    try chmod 755 "$WORK_DIR/image/something-to-install" "$WORK_DIR/image/dir-to-install/foo"
+    try sh "$S/gen-installer.sh" \
+        --image-dir="$WORK_DIR/image" \
+        --work-dir="$WORK_DIR/new" \
+        --output-dir="$OUT_DIR/new" \
+        --bulk-dirs=dir-to-install
+    try sh "$S/make-delta.sh" \
+	--old-tarball="$OUT_DIR/old/package.tar.gz" \
+	--new-tarball="$OUT_DIR/new/package.tar.gz" \
+	--work-dir="$WORK_DIR/delta" \
+	--output-dir="$OUT_DIR/delta"
+    try mkdir "$WORK_DIR/unpacked"
+    try tar -xzf "$OUT_DIR/delta/package-delta.tar.gz" -C "$WORK_DIR/unpacked"
+    local _delta="$WORK_DIR/unpacked/package-delta"
+    try test -x "$_delta/component/something-to-install"
+    try test -x "$_delta/component/dir-to-install/foo"
+    try grep -qx "keep:bin/program" "$_delta/component/delta.in"
+    try "$_delta/install.sh" --prefix="$PREFIX_DIR"
+    try test -x "$PREFIX_DIR/something-to-install"
+    try test -x "$PREFIX_DIR/dir-to-install/foo"
+    try sh "$S/check-installation.sh" --prefix="$PREFIX_DIR"
+}
+runtest delta_installer_carries_mode_changes
+
+delta_installer_self_check() {
+    make_delta
+    try "$WORK_DIR/unpacked/package-delta/install.sh" --prefix="$PREFIX_DIR"
+    # Applying it again finds the new version installed
+    expect_output_fail "is not package, which this installer upgrades from" \
+	"$WORK_DIR/unpacked/package-delta/install.sh" --prefix="$PREFIX_DIR"
+    try sh "$S/check-installation.sh" --prefix="$PREFIX_DIR"
+}
+runtest delta_installer_self_check
+
+delta_installer_needs_installation() {
+    make_delta
+    try "$WORK_DIR/old/package/install.sh" --prefix="$PREFIX_DIR" --uninstall
+    expect_output_fail "is not installed" \
+	"$WORK_DIR/unpacked/package-delta/install.sh" --prefix="$PREFIX_DIR"
+    try test ! -e "$PREFIX_DIR/bin/program2"
+}
+runtest delta_installer_needs_installation
+
+delta_installer_modified_file() {
+    make_delta
+    echo "local change" > "$PREFIX_DIR/bin/program"
+    expect_output_fail "unable to take $PREFIX_DIR/bin/program from the installation" \
+	"$WORK_DIR/unpacked/package-delta/install.sh" --prefix="$PREFIX_DIR"
+    # Nothing was touched
+    try test -e "$PREFIX_DIR/something-to-install"
+    try test ! -e "$PREFIX_DIR/bin/program3"
+    try test ! -e "$PREFIX_DIR/lib/packagelib/keep-component"
+    try sh "$S/repair-installation.sh" \
+	--prefix="$PREFIX_DIR" \
+	--input-tarball="$OUT_DIR/old/package.tar.gz"
+    try "$WORK_DIR/unpacked/package-delta/install.sh" --prefix="$PREFIX_DIR"
+    try sh "$S/check-installation.sh" --prefix="$PREFIX_DIR"
+}
+runtest delta_installer_modified_file
+
+delta_installer_new_component() {
+    try sh "$S/gen-installer.sh" \
+	--image-dir="$TEST_DIR/image1" \
+	--work-dir="$WORK_DIR/old" \
+	--output-dir="$OUT_DIR/old" \
+	--package-name=rustc \
+	--component-name=rustc
+    try sh "$S/gen-installer.sh" \
+	--image-dir="$TEST_DIR/image3" \
+	--work-dir="$WORK_DIR/cargo" \
+	--output-dir="$OUT_DIR/cargo" \
+	--package-name=cargo \
+	--component-name=cargo
+    try sh "$S/combine-installers.sh" \
+	--work-dir="$WORK_DIR/new" \
+	--output-dir="$OUT_DIR/new" \
+	--package-name=rust \
+	--input-tarballs="$OUT_DIR/old/rustc.tar.gz,$OUT_DIR/cargo/cargo.tar.gz"
+    try "$WORK_DIR/old/rustc/install.sh" --prefix="$PREFIX_DIR"
+    try sh "$S/make-delta.sh" \
+	--old-tarball="$OUT_DIR/old/rustc.tar.gz" \
+	--new-tarball="$OUT_DIR/new/rust.tar.gz" \
+	--work-dir="$WORK_DIR/delta" \
+	--output-dir="$OUT_DIR/delta" \
+	--package-name=rust-update
+    try_quoted test -z "$(grep -v '^keep:' "$WORK_DIR/delta/rust-update/rustc/delta.in")"
+    try test ! -e "$WORK_DIR/delta/rust-update/rustc/bin/program"
+    try test ! -e "$WORK_DIR/delta/rust-update/cargo/delta.in"
+    try "$WORK_DIR/delta/rust-update/install.sh" --prefix="$PREFIX_DIR"
+    try test -x "$PREFIX_DIR/bin/program"
+    try test -x "$PREFIX_DIR/bin/cargo"
+    try sh "$S/check-installation.sh" --prefix="$PREFIX_DIR"
+}
+runtest delta_installer_new_component
+
+delta_installer_errors() {
+    make_upgrade
+    expect_output_fail "needs the installers of both versions" sh "$S/make-delta.sh" \
+	--old-tarball="$OUT_DIR/old/package.tar.gz" \
+	--work-dir="$WORK_DIR/delta" \
+	--output-dir="$OUT_DIR/delta"
+    try rm "$WORK_DIR/old/package/component/hashes.in"
+    try tar -czf "$OUT_DIR/old/package.tar.gz" -C "$WORK_DIR/old" package
+    expect_output_fail "record the hashes of their files" sh "$S/make-delta.sh" \
+	--old-tarball="$OUT_DIR/old/package.tar.gz" \
+	--new-tarball="$OUT_DIR/new/package.tar.gz" \
+	--work-dir="$WORK_DIR/delta" \
+	--output-dir="$OUT_DIR/delta"
+}
+runtest delta_installer_errors
+
 echo
 echo "TOTAL SUCCESS!"
 echo